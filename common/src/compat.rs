@@ -0,0 +1,152 @@
+use std::fmt;
+use std::str::FromStr;
+
+use semver::{Version, VersionReq};
+
+/// Versions of the software of remote peers accepted by a node.
+///
+/// Peers advertise the name and version of their software in the identify protocol
+/// version, e.g. `/chat-example/0.4.0`, and agent version, e.g. `rust-libp2p/0.44.2`.
+/// Software without a requirement is accepted in any version.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    requirements: Vec<Requirement>,
+}
+
+/// Semver requirement on the version of a named software, parsed from
+/// `<name>=<requirement>`, e.g. `chat-example=^0.4`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Requirement {
+    pub name: String,
+    pub version: VersionReq,
+}
+
+impl FromStr for Requirement {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, version) = s
+            .split_once('=')
+            .ok_or_else(|| eyre::eyre!("expected `<name>=<version requirement>`"))?;
+
+        Ok(Self {
+            name: name.trim().to_owned(),
+            version: version.trim().parse()?,
+        })
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.version)
+    }
+}
+
+/// Reason a peer was found incompatible.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Incompatible {
+    #[error("{field} {advertised:?} does not match the required {requirement}")]
+    Version {
+        field: &'static str,
+        advertised: String,
+        requirement: Requirement,
+    },
+    #[error("{field} {advertised:?} has no valid version, {requirement} is required")]
+    InvalidVersion {
+        field: &'static str,
+        advertised: String,
+        requirement: Requirement,
+    },
+}
+
+impl Policy {
+    pub fn new(requirements: Vec<Requirement>) -> Self {
+        Self { requirements }
+    }
+
+    /// Whether the policy accepts any software, so that peers need not be identified.
+    pub fn is_empty(&self) -> bool {
+        self.requirements.is_empty()
+    }
+
+    /// Checks the protocol and agent versions advertised by a peer.
+    pub fn check(&self, protocol_version: &str, agent_version: &str) -> Result<(), Incompatible> {
+        self.check_field("protocol_version", protocol_version)?;
+        self.check_field("agent_version", agent_version)
+    }
+
+    fn check_field(&self, field: &'static str, advertised: &str) -> Result<(), Incompatible> {
+        let Some((name, version)) = advertised
+            .trim_start_matches('/')
+            .split_whitespace()
+            .next()
+            .and_then(|advertised| advertised.split_once('/'))
+        else {
+            return Ok(());
+        };
+
+        for requirement in self.requirements.iter().filter(|req| req.name == name) {
+            match Version::parse(version) {
+                Ok(version) if requirement.version.matches(&version) => {}
+                Ok(_) => {
+                    return Err(Incompatible::Version {
+                        field,
+                        advertised: advertised.to_owned(),
+                        requirement: requirement.clone(),
+                    })
+                }
+                Err(_) => {
+                    return Err(Incompatible::InvalidVersion {
+                        field,
+                        advertised: advertised.to_owned(),
+                        requirement: requirement.clone(),
+                    })
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_versions() {
+        let policy = Policy::new(vec![
+            "chat-example=^0.4".parse().unwrap(),
+            "rust-libp2p = >=0.44, <0.46".parse().unwrap(),
+        ]);
+
+        policy
+            .check("/chat-example/0.4.2", "rust-libp2p/0.44.2")
+            .unwrap();
+        // Software without requirements is accepted in any version.
+        policy.check("/boot-node/0.1.0", "custom agent").unwrap();
+        policy.check("", "").unwrap();
+
+        assert!(matches!(
+            policy.check("/chat-example/0.3.0", "rust-libp2p/0.44.2"),
+            Err(Incompatible::Version {
+                field: "protocol_version",
+                ..
+            })
+        ));
+        assert!(matches!(
+            policy.check("/chat-example/0.4.0", "rust-libp2p/0.43.0 (linux)"),
+            Err(Incompatible::Version {
+                field: "agent_version",
+                ..
+            })
+        ));
+        assert!(matches!(
+            policy.check("/chat-example/latest", "rust-libp2p/0.44.2"),
+            Err(Incompatible::InvalidVersion { .. })
+        ));
+
+        assert!("chat-example".parse::<Requirement>().is_err());
+        assert!("chat-example=0.4.x.y".parse::<Requirement>().is_err());
+    }
+}
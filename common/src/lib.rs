@@ -0,0 +1,5 @@
+//! Parts of the Calimero boot node protocols shared by the boot node and its clients.
+
+pub mod compat;
+pub mod token;
+pub mod transport;
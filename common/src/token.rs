@@ -0,0 +1,23 @@
+use libp2p::StreamProtocol;
+use serde::{Deserialize, Serialize};
+
+/// Protocol of boot nodes restricting relay reservations to peers with a token issued
+/// by their operator.
+pub const PROTOCOL_NAME: StreamProtocol = StreamProtocol::new("/calimero/relay-token/1.0.0");
+
+/// Token presented by a peer before requesting a relay reservation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Response {
+    /// Reservations are accepted until `expires_at`, in seconds since the unix epoch.
+    Accepted {
+        expires_at: u64,
+    },
+    Rejected {
+        reason: String,
+    },
+}
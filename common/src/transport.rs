@@ -0,0 +1,22 @@
+use libp2p::core::muxing::StreamMuxerBox;
+use libp2p::core::transport::Boxed;
+use libp2p::core::upgrade;
+use libp2p::pnet::{PnetConfig, PreSharedKey};
+use libp2p::{identity, noise, tcp, yamux, PeerId, Transport};
+
+/// Builds the TCP transport of a private network, with connections encrypted with the
+/// pre-shared key before they are secured with noise.
+///
+/// QUIC cannot be used in a private network, its handshake cannot be wrapped.
+pub fn private_tcp(
+    keypair: &identity::Keypair,
+    psk: PreSharedKey,
+) -> eyre::Result<Boxed<(PeerId, StreamMuxerBox)>> {
+    Ok(tcp::tokio::Transport::new(tcp::Config::default())
+        .and_then(move |socket, _| PnetConfig::new(psk).handshake(socket))
+        .upgrade(upgrade::Version::V1Lazy)
+        .authenticate(noise::Config::new(keypair)?)
+        .multiplex(yamux::Config::default())
+        .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+        .boxed())
+}
@@ -0,0 +1,4 @@
+pub mod network;
+pub mod node;
+pub mod store;
+pub mod types;
@@ -1,10 +1,12 @@
 use std::collections::hash_map::{self, HashMap};
 use std::time::Duration;
 
+use boot_node_common::{compat, token, transport};
 use libp2p::futures::prelude::*;
 use libp2p::swarm::{NetworkBehaviour, Swarm, SwarmEvent};
 use libp2p::{
-    dcutr, gossipsub, identify, identity, kad, mdns, noise, ping, relay, rendezvous, yamux, PeerId,
+    dcutr, gossipsub, identify, identity, kad, mdns, noise, ping, pnet, relay, rendezvous,
+    request_response, yamux, PeerId,
 };
 use multiaddr::Multiaddr;
 use tokio::sync::{mpsc, oneshot};
@@ -33,6 +35,7 @@ struct Behaviour {
     ping: ping::Behaviour,
     rendezvous: rendezvous::client::Behaviour,
     relay: relay::client::Behaviour,
+    relay_token: request_response::json::Behaviour<token::Request, token::Response>,
     stream: libp2p_stream::Behaviour,
 }
 
@@ -41,16 +44,28 @@ pub async fn run(
     port: u16,
     boot_nodes: Vec<Multiaddr>,
     rendezvous_namespace: rendezvous::Namespace,
+    version_requirements: Vec<compat::Requirement>,
+    swarm_key: Option<pnet::PreSharedKey>,
+    relay_token: Option<String>,
 ) -> eyre::Result<(NetworkClient, mpsc::Receiver<types::NetworkEvent>)> {
-    let (client, event_receiver, event_loop) =
-        init(keypair, boot_nodes, rendezvous_namespace).await?;
+    let private = swarm_key.is_some();
+    let (client, event_receiver, event_loop) = init(
+        keypair,
+        boot_nodes,
+        rendezvous_namespace,
+        version_requirements,
+        swarm_key,
+        relay_token,
+    )
+    .await?;
 
     tokio::spawn(event_loop.run());
 
-    let swarm_listen: Vec<Multiaddr> = vec![
-        format!("/ip4/0.0.0.0/udp/{}/quic-v1", port).parse()?,
-        format!("/ip4/0.0.0.0/tcp/{}", port).parse()?,
-    ];
+    let mut swarm_listen: Vec<Multiaddr> = vec![format!("/ip4/0.0.0.0/tcp/{}", port).parse()?];
+    // QUIC cannot be used in a private network.
+    if !private {
+        swarm_listen.insert(0, format!("/ip4/0.0.0.0/udp/{}/quic-v1", port).parse()?);
+    }
 
     for addr in swarm_listen {
         client.listen_on(addr).await?;
@@ -63,6 +78,9 @@ async fn init(
     keypair: identity::Keypair,
     boot_nodes: Vec<Multiaddr>,
     rendezvous_namespace: rendezvous::Namespace,
+    version_requirements: Vec<compat::Requirement>,
+    swarm_key: Option<pnet::PreSharedKey>,
+    relay_token: Option<String>,
 ) -> eyre::Result<(
     NetworkClient,
     mpsc::Receiver<types::NetworkEvent>,
@@ -83,56 +101,80 @@ async fn init(
     };
 
     let peer_id = keypair.public().to_peer_id();
-    let swarm = libp2p::SwarmBuilder::with_existing_identity(keypair.clone())
-        .with_tokio()
-        .with_tcp(
-            Default::default(),
-            (libp2p::tls::Config::new, libp2p::noise::Config::new),
-            libp2p::yamux::Config::default,
-        )?
-        .with_quic()
-        .with_relay_client(noise::Config::new, yamux::Config::default)?
-        .with_behaviour(|keypair, relay_behaviour| Behaviour {
-            dcutr: dcutr::Behaviour::new(peer_id.clone()),
-            identify: identify::Behaviour::new(
-                identify::Config::new(PROTOCOL_VERSION.to_owned(), keypair.public())
-                    .with_push_listen_addr_updates(true),
-            ),
-            kad: {
-                let mut kademlia_config = kad::Config::default();
-                kademlia_config.set_protocol_names(vec![CALIMERO_KAD_PROTO_NAME]);
-
-                let mut kademlia = kad::Behaviour::with_config(
-                    peer_id,
-                    kad::store::MemoryStore::new(peer_id),
-                    kademlia_config,
-                );
-
-                kademlia.set_mode(Some(kad::Mode::Client));
-
-                for (peer_id, addr) in bootstrap_peers {
-                    kademlia.add_address(&peer_id, addr);
-                }
-                if let Err(err) = kademlia.bootstrap() {
-                    warn!(%err, "Failed to bootstrap Kademlia");
-                };
+    let private_transport = swarm_key
+        .map(|psk| transport::private_tcp(&keypair, psk))
+        .transpose()?;
+
+    let behaviour = |keypair: &identity::Keypair, relay_behaviour| Behaviour {
+        dcutr: dcutr::Behaviour::new(peer_id),
+        identify: identify::Behaviour::new(
+            identify::Config::new(PROTOCOL_VERSION.to_owned(), keypair.public())
+                .with_push_listen_addr_updates(true),
+        ),
+        kad: {
+            let mut kademlia_config = kad::Config::default();
+            kademlia_config.set_protocol_names(vec![CALIMERO_KAD_PROTO_NAME]);
+
+            let mut kademlia = kad::Behaviour::with_config(
+                peer_id,
+                kad::store::MemoryStore::new(peer_id),
+                kademlia_config,
+            );
+
+            kademlia.set_mode(Some(kad::Mode::Client));
+
+            for (peer_id, addr) in bootstrap_peers {
+                kademlia.add_address(&peer_id, addr);
+            }
+            if let Err(err) = kademlia.bootstrap() {
+                warn!(%err, "Failed to bootstrap Kademlia");
+            };
 
-                kademlia
-            },
-            gossipsub: gossipsub::Behaviour::new(
-                gossipsub::MessageAuthenticity::Signed(keypair.clone()),
-                gossipsub::Config::default(),
-            )
-            .expect("Valid gossipsub config."),
-            mdns: mdns::Behaviour::new(mdns::Config::default(), peer_id.clone())
-                .expect("Valid mdns config."),
-            ping: ping::Behaviour::default(),
-            rendezvous: rendezvous::client::Behaviour::new(keypair.clone()),
-            relay: relay_behaviour,
-            stream: libp2p_stream::Behaviour::new(),
-        })?
-        .with_swarm_config(|cfg| cfg.with_idle_connection_timeout(time::Duration::from_secs(30)))
-        .build();
+            kademlia
+        },
+        gossipsub: gossipsub::Behaviour::new(
+            gossipsub::MessageAuthenticity::Signed(keypair.clone()),
+            gossipsub::Config::default(),
+        )
+        .expect("Valid gossipsub config."),
+        mdns: mdns::Behaviour::new(mdns::Config::default(), peer_id).expect("Valid mdns config."),
+        ping: ping::Behaviour::default(),
+        rendezvous: rendezvous::client::Behaviour::new(keypair.clone()),
+        relay: relay_behaviour,
+        relay_token: request_response::json::Behaviour::new(
+            [(
+                token::PROTOCOL_NAME,
+                request_response::ProtocolSupport::Outbound,
+            )],
+            request_response::Config::default(),
+        ),
+        stream: libp2p_stream::Behaviour::new(),
+    };
+
+    let builder = libp2p::SwarmBuilder::with_existing_identity(keypair.clone()).with_tokio();
+    let swarm = match private_transport {
+        Some(private_transport) => builder
+            .with_other_transport(|_| private_transport)?
+            .with_relay_client(noise::Config::new, yamux::Config::default)?
+            .with_behaviour(behaviour)?
+            .with_swarm_config(|cfg| {
+                cfg.with_idle_connection_timeout(time::Duration::from_secs(30))
+            })
+            .build(),
+        None => builder
+            .with_tcp(
+                Default::default(),
+                (libp2p::tls::Config::new, libp2p::noise::Config::new),
+                libp2p::yamux::Config::default,
+            )?
+            .with_quic()
+            .with_relay_client(noise::Config::new, yamux::Config::default)?
+            .with_behaviour(behaviour)?
+            .with_swarm_config(|cfg| {
+                cfg.with_idle_connection_timeout(time::Duration::from_secs(30))
+            })
+            .build(),
+    };
 
     let (command_sender, command_receiver) = mpsc::channel(32);
     let (event_sender, event_receiver) = mpsc::channel(32);
@@ -159,6 +201,8 @@ async fn init(
         command_receiver,
         event_sender,
         rendezvous_namespace,
+        version_requirements,
+        relay_token,
     );
 
     Ok((client, event_receiver, event_loop))
@@ -170,6 +214,9 @@ pub(crate) struct EventLoop {
     command_receiver: mpsc::Receiver<Command>,
     event_sender: mpsc::Sender<types::NetworkEvent>,
     discovery: discovery::Discovery,
+    version_policy: compat::Policy,
+    /// Token presented to relays requiring one before reserving.
+    relay_token: Option<String>,
     pending_dial: HashMap<PeerId, oneshot::Sender<eyre::Result<Option<()>>>>,
 }
 
@@ -180,6 +227,8 @@ impl EventLoop {
         command_receiver: mpsc::Receiver<Command>,
         event_sender: mpsc::Sender<types::NetworkEvent>,
         rendezvous_namespace: rendezvous::Namespace,
+        version_requirements: Vec<compat::Requirement>,
+        relay_token: Option<String>,
     ) -> Self {
         Self {
             swarm,
@@ -191,6 +240,8 @@ impl EventLoop {
                 Duration::from_secs(90),
                 0.5,
             )),
+            version_policy: compat::Policy::new(version_requirements),
+            relay_token,
             pending_dial: Default::default(),
         }
     }
@@ -291,19 +342,14 @@ impl EventLoop {
                 };
             }
             Command::PeersInfo { sender } => {
-                let peers = self
-                    .swarm
-                    .connected_peers()
-                    .into_iter()
-                    .map(|peer| peer.clone())
-                    .collect::<Vec<_>>();
+                let peers = self.swarm.connected_peers().copied().collect::<Vec<_>>();
                 let count = peers.len();
 
                 let discovered_peers = self
                     .discovery
                     .state
                     .get_peers()
-                    .map(|(id, peer)| (id.clone(), peer.clone()))
+                    .map(|(id, peer)| (*id, peer.clone()))
                     .collect::<Vec<_>>();
                 let discovered_count = discovered_peers.len();
 
@@ -320,7 +366,7 @@ impl EventLoop {
                     .behaviour_mut()
                     .gossipsub
                     .mesh_peers(&topic)
-                    .map(|peer| peer.clone())
+                    .copied()
                     .collect::<Vec<_>>();
                 let count = peers.len();
 
@@ -368,7 +414,7 @@ pub(crate) enum Command {
 
 #[allow(dead_code)] // Info structs for pretty printing
 #[derive(Debug)]
-pub(crate) struct PeersInfo {
+pub struct PeersInfo {
     count: usize,
     peers: Vec<PeerId>,
     discovered_count: usize,
@@ -377,7 +423,7 @@ pub(crate) struct PeersInfo {
 
 #[allow(dead_code)] // Info structs for pretty printing
 #[derive(Debug)]
-pub(crate) struct MeshPeersInfo {
+pub struct MeshPeersInfo {
     count: usize,
     peers: Vec<PeerId>,
 }
@@ -87,8 +87,8 @@ impl EventLoop {
             .get_peer_info(rendezvous_peer)
             .wrap_err("Failed to get peer info {}")?;
 
-        let is_throttled = peer_info.rendezvous().map_or(false, |info| {
-            info.last_discovery_at().map_or(false, |instant| {
+        let is_throttled = peer_info.rendezvous().is_some_and(|info| {
+            info.last_discovery_at().is_some_and(|instant| {
                 instant.elapsed()
                     > time::Duration::from_secs_f32(
                         60.0 / self.discovery.rendezvous_config.discovery_rpm,
@@ -188,11 +188,10 @@ impl EventLoop {
             .wrap_err("Failed to get peer info")?;
 
         let is_relay_reservation_required = match peer_info.relay() {
-            Some(info) => match info.reservation_status() {
-                state::RelayReservationStatus::Discovered => true,
-                state::RelayReservationStatus::Expired => true,
-                _ => false,
-            },
+            Some(info) => matches!(
+                info.reservation_status(),
+                state::RelayReservationStatus::Discovered | state::RelayReservationStatus::Expired
+            ),
             None => true,
         };
         debug!(
@@ -213,7 +212,7 @@ impl EventLoop {
         self.swarm.listen_on(relayed_addr)?;
         self.discovery
             .state
-            .update_relay_reservation_status(&peer_id, state::RelayReservationStatus::Requested);
+            .update_relay_reservation_status(peer_id, state::RelayReservationStatus::Requested);
 
         Ok(())
     }
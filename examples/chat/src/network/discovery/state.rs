@@ -8,7 +8,7 @@ use libp2p::{rendezvous, Multiaddr, PeerId, StreamProtocol};
 const RENDEZVOUS_PROTOCOL_NAME: libp2p::StreamProtocol =
     libp2p::StreamProtocol::new("/rendezvous/1.0.0");
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub(crate) struct DiscoveryState {
     peers: BTreeMap<PeerId, PeerInfo>,
     relay_index: BTreeSet<PeerId>,
@@ -16,17 +16,6 @@ pub(crate) struct DiscoveryState {
     pending_addr_changes: bool,
 }
 
-impl Default for DiscoveryState {
-    fn default() -> Self {
-        DiscoveryState {
-            peers: Default::default(),
-            relay_index: Default::default(),
-            rendezvous_index: Default::default(),
-            pending_addr_changes: false,
-        }
-    }
-}
-
 impl DiscoveryState {
     pub(crate) fn get_peers(&self) -> impl Iterator<Item = (&PeerId, &PeerInfo)> {
         self.peers.iter()
@@ -9,9 +9,10 @@ mod kad;
 mod mdns;
 mod ping;
 mod relay;
+mod relay_token;
 mod rendezvous;
 
-pub trait EventHandler<E> {
+pub(crate) trait EventHandler<E> {
     async fn handle(&mut self, event: E);
 }
 
@@ -26,6 +27,9 @@ impl EventLoop {
                 BehaviourEvent::Mdns(event) => events::EventHandler::handle(self, event).await,
                 BehaviourEvent::Ping(event) => events::EventHandler::handle(self, event).await,
                 BehaviourEvent::Relay(event) => events::EventHandler::handle(self, event).await,
+                BehaviourEvent::RelayToken(event) => {
+                    events::EventHandler::handle(self, event).await
+                }
                 BehaviourEvent::Rendezvous(event) => {
                     events::EventHandler::handle(self, event).await
                 }
@@ -53,17 +57,14 @@ impl EventLoop {
             } => {
                 debug!(%peer_id, ?endpoint, "Connection established");
 
-                match endpoint {
-                    libp2p::core::ConnectedPoint::Dialer { .. } => {
-                        self.discovery
-                            .state
-                            .add_peer_addr(peer_id, endpoint.get_remote_address());
+                if let libp2p::core::ConnectedPoint::Dialer { .. } = endpoint {
+                    self.discovery
+                        .state
+                        .add_peer_addr(peer_id, endpoint.get_remote_address());
 
-                        if let Some(sender) = self.pending_dial.remove(&peer_id) {
-                            let _ = sender.send(Ok(Some(())));
-                        }
+                    if let Some(sender) = self.pending_dial.remove(&peer_id) {
+                        let _ = sender.send(Ok(Some(())));
                     }
-                    _ => {}
                 }
             }
             SwarmEvent::ConnectionClosed {
@@ -130,7 +131,7 @@ impl EventLoop {
                 debug!("External address expired: {}", address);
                 if let Ok(relayed_addr) = RelayedMultiaddr::try_from(&address) {
                     self.discovery.state.update_relay_reservation_status(
-                        &relayed_addr.relay_peer_id(),
+                        relayed_addr.relay_peer_id(),
                         discovery::state::RelayReservationStatus::Expired,
                     );
 
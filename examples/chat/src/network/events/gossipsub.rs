@@ -23,12 +23,12 @@ impl EventHandler<gossipsub::Event> for EventLoop {
                 }
             }
             gossipsub::Event::Subscribed { peer_id, topic } => {
-                if let Err(_) = self
+                if let Err(err) = self
                     .event_sender
                     .send(types::NetworkEvent::Subscribed { peer_id, topic })
                     .await
                 {
-                    error!("Failed to send subscribed event");
+                    error!("Failed to send subscribed event: {:?}", err);
                 }
             }
             _ => {}
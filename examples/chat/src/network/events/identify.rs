@@ -1,6 +1,7 @@
+use boot_node_common::token;
 use libp2p::identify;
 use owo_colors::OwoColorize;
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 
 use super::{EventHandler, EventLoop};
 
@@ -8,29 +9,50 @@ impl EventHandler<identify::Event> for EventLoop {
     async fn handle(&mut self, event: identify::Event) {
         debug!("{}: {:?}", "identify".yellow(), event);
 
-        match event {
-            identify::Event::Received { peer_id, info } => {
-                self.discovery
-                    .state
-                    .update_peer_protocols(&peer_id, &info.protocols);
+        if let identify::Event::Received { peer_id, info } = event {
+            // Peers on another wire format would fail in confusing ways further on.
+            if let Err(reason) = self
+                .version_policy
+                .check(&info.protocol_version, &info.agent_version)
+            {
+                warn!(%peer_id, %reason, "Disconnecting peer with incompatible version");
+                let _ = self.swarm.disconnect_peer_id(peer_id);
+                return;
+            }
+
+            self.discovery
+                .state
+                .update_peer_protocols(&peer_id, &info.protocols);
 
-                if self.discovery.state.is_peer_relay(&peer_id) {
-                    if let Err(err) = self.create_relay_reservation(&peer_id) {
-                        error!(%err, "Failed to handle relay reservation");
-                    };
+            if self.discovery.state.is_peer_relay(&peer_id) {
+                match &self.relay_token {
+                    // The reservation is made once the relay accepted the token.
+                    Some(token) if info.protocols.contains(&token::PROTOCOL_NAME) => {
+                        let request = token::Request {
+                            token: token.clone(),
+                        };
+                        self.swarm
+                            .behaviour_mut()
+                            .relay_token
+                            .send_request(&peer_id, request);
+                    }
+                    _ => {
+                        if let Err(err) = self.create_relay_reservation(&peer_id) {
+                            error!(%err, "Failed to handle relay reservation");
+                        };
+                    }
                 }
+            }
 
-                if self.discovery.state.is_peer_rendezvous(&peer_id) {
-                    if let Err(err) = self.perform_rendezvous_discovery(&peer_id) {
-                        error!(%err, "Failed to perform rendezvous discovery");
-                    };
+            if self.discovery.state.is_peer_rendezvous(&peer_id) {
+                if let Err(err) = self.perform_rendezvous_discovery(&peer_id) {
+                    error!(%err, "Failed to perform rendezvous discovery");
+                };
 
-                    if let Err(err) = self.update_rendezvous_registration(&peer_id) {
-                        error!(%err, "Failed to update registration discovery");
-                    };
-                }
+                if let Err(err) = self.update_rendezvous_registration(&peer_id) {
+                    error!(%err, "Failed to update registration discovery");
+                };
             }
-            _ => {}
         }
     }
 }
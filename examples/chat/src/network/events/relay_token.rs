@@ -0,0 +1,33 @@
+use boot_node_common::token;
+use libp2p::request_response;
+use owo_colors::OwoColorize;
+use tracing::{debug, error, info, warn};
+
+use super::{EventHandler, EventLoop};
+
+impl EventHandler<request_response::Event<token::Request, token::Response>> for EventLoop {
+    async fn handle(&mut self, event: request_response::Event<token::Request, token::Response>) {
+        debug!("{}: {:?}", "relay_token".yellow(), event);
+
+        match event {
+            request_response::Event::Message {
+                peer,
+                message: request_response::Message::Response { response, .. },
+            } => match response {
+                token::Response::Accepted { expires_at } => {
+                    info!(%peer, expires_at, "Relay accepted token");
+                    if let Err(err) = self.create_relay_reservation(&peer) {
+                        error!(%err, "Failed to handle relay reservation");
+                    };
+                }
+                token::Response::Rejected { reason } => {
+                    warn!(%peer, %reason, "Relay rejected token, not reserving");
+                }
+            },
+            request_response::Event::OutboundFailure { peer, error, .. } => {
+                warn!(%peer, %error, "Failed to present token to relay");
+            }
+            _ => {}
+        }
+    }
+}
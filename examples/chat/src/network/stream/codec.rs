@@ -99,6 +99,6 @@ mod tests {
         assert_eq!(decoded_response, response);
 
         let decoded3 = framed.next().await;
-        assert_eq!(decoded3.is_none(), true);
+        assert!(decoded3.is_none());
     }
 }
@@ -4,6 +4,8 @@ pub use libp2p::identity::PeerId;
 
 use super::stream;
 
+#[allow(dead_code)] // Event fields for pretty printing
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum NetworkEvent {
     ListeningOn {
@@ -35,8 +35,8 @@ impl Store {
         &self,
         application_id: types::ApplicationId,
         batch_size: usize,
-    ) -> MessageStream {
-        MessageStream::new(&self, application_id, batch_size)
+    ) -> MessageStream<'_> {
+        MessageStream::new(self, application_id, batch_size)
     }
 }
 
@@ -110,6 +110,6 @@ mod tests {
         assert_eq!(batch.len(), 1);
 
         let batch = message_iterator.next().await;
-        assert_eq!(batch.is_none(), true);
+        assert!(batch.is_none());
     }
 }
@@ -19,9 +19,9 @@ impl From<String> for ApplicationId {
     }
 }
 
-impl Into<String> for ApplicationId {
-    fn into(self) -> String {
-        self.0
+impl From<ApplicationId> for String {
+    fn from(val: ApplicationId) -> Self {
+        val.0
     }
 }
 
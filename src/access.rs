@@ -0,0 +1,382 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Instant, SystemTime};
+
+use camino::Utf8PathBuf;
+use eyre::WrapErr;
+use ipnet::IpNet;
+use libp2p::core::Endpoint;
+use libp2p::swarm::{
+    dummy, CloseConnection, ConnectionClosed, ConnectionDenied, ConnectionId, FromSwarm,
+    NetworkBehaviour, THandler, THandlerInEvent, THandlerOutEvent, ToSwarm,
+};
+use libp2p::{relay, Multiaddr, PeerId};
+use tracing::{debug, info, warn};
+
+/// Peers and IP networks allowed or denied to use the boot node.
+///
+/// Denied entries always win. If any allow entry is present, peers matching none of
+/// them are denied as well.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct AccessList {
+    allowed_peers: HashSet<PeerId>,
+    allowed_networks: Vec<IpNet>,
+    denied_peers: HashSet<PeerId>,
+    denied_networks: Vec<IpNet>,
+}
+
+impl AccessList {
+    pub fn is_allowed(&self, peer_id: &PeerId, ip: Option<IpAddr>) -> bool {
+        if self.denied_peers.contains(peer_id) || self.is_denied_ip(ip) {
+            return false;
+        }
+
+        if self.allowed_peers.is_empty() && self.allowed_networks.is_empty() {
+            return true;
+        }
+
+        self.allowed_peers.contains(peer_id)
+            || ip.is_some_and(|ip| self.allowed_networks.iter().any(|net| net.contains(&ip)))
+    }
+
+    fn add(&mut self, line: &str) -> eyre::Result<()> {
+        let (action, entry) = line
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| eyre::eyre!("expected `allow` or `deny` followed by an entry"))?;
+
+        match (action, entry.trim().parse()?) {
+            ("allow", Entry::Peer(peer_id)) => {
+                self.allowed_peers.insert(peer_id);
+            }
+            ("allow", Entry::Network(net)) => self.allowed_networks.push(net),
+            ("deny", Entry::Peer(peer_id)) => {
+                self.denied_peers.insert(peer_id);
+            }
+            ("deny", Entry::Network(net)) => self.denied_networks.push(net),
+            _ => eyre::bail!("unknown action {action:?}, expected `allow` or `deny`"),
+        }
+
+        Ok(())
+    }
+
+    /// Whether connections from `ip` are denied regardless of the remote peer.
+    fn is_denied_ip(&self, ip: Option<IpAddr>) -> bool {
+        ip.is_some_and(|ip| self.denied_networks.iter().any(|net| net.contains(&ip)))
+    }
+}
+
+/// Parses an access list file with one `allow` or `deny` entry per line, followed by
+/// a peer id, an IP address or a CIDR network. Empty lines and `#` comments are ignored:
+///
+/// ```text
+/// # Misbehaving relay users
+/// deny 12D3KooWQLSpVrM77fqiBYGY9Fn7czbko8FWZRxMbhBk1moN8uen
+/// deny 203.0.113.0/24
+/// ```
+impl FromStr for AccessList {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut list = AccessList::default();
+
+        for (number, line) in s.lines().enumerate() {
+            let line = line.split_once('#').map_or(line, |(line, _)| line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            list.add(line)
+                .wrap_err_with(|| format!("Invalid access list entry on line {}", number + 1))?;
+        }
+
+        Ok(list)
+    }
+}
+
+enum Entry {
+    Peer(PeerId),
+    Network(IpNet),
+}
+
+impl FromStr for Entry {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(peer_id) = s.parse() {
+            return Ok(Entry::Peer(peer_id));
+        }
+        if let Ok(net) = s.parse() {
+            return Ok(Entry::Network(net));
+        }
+        if let Ok(ip) = s.parse::<IpAddr>() {
+            return Ok(Entry::Network(ip.into()));
+        }
+
+        eyre::bail!("{s:?} is neither a peer id, an IP address nor a CIDR network")
+    }
+}
+
+/// Enforces the [`AccessList`] on inbound connections and, through [`Self::relay_filter`],
+/// on relay reservations and circuits.
+///
+/// The list is read from a file and can be reloaded at runtime, upon which inbound
+/// connections of peers that are no longer allowed are closed. This also drops their
+/// relay reservations, so that they can no longer be the destination of circuits.
+pub struct Behaviour {
+    list: Arc<RwLock<AccessList>>,
+    path: Option<Utf8PathBuf>,
+    modified: Option<SystemTime>,
+    /// Remote peer and IP address of established inbound connections.
+    inbound_connections: HashMap<ConnectionId, (PeerId, Option<IpAddr>)>,
+    pending_closes: VecDeque<(PeerId, ConnectionId)>,
+}
+
+impl Behaviour {
+    /// Loads the access list from `path`, allowing everyone if unset.
+    pub fn open(path: Option<Utf8PathBuf>) -> eyre::Result<Self> {
+        let mut behaviour = Self {
+            list: Default::default(),
+            path,
+            modified: None,
+            inbound_connections: Default::default(),
+            pending_closes: Default::default(),
+        };
+
+        behaviour.load()?;
+
+        Ok(behaviour)
+    }
+
+    /// Rate limiter denying relay reservations and circuits of peers not allowed.
+    pub fn relay_filter(&self) -> Box<dyn relay::RateLimiter> {
+        Box::new(RelayFilter(self.list.clone()))
+    }
+
+    /// Reloads the access list, keeping the current one if the file cannot be read.
+    pub fn reload(&mut self) {
+        if let Err(err) = self.load() {
+            warn!(
+                ?err,
+                "Failed to reload access list, keeping the current one"
+            );
+        }
+    }
+
+    /// Reloads the access list if its file was modified since it was last loaded.
+    pub fn reload_if_changed(&mut self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        match std::fs::metadata(path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) if Some(modified) != self.modified => self.reload(),
+            Ok(_) => {}
+            Err(err) => debug!(%path, %err, "Failed to check access list for changes"),
+        }
+    }
+
+    fn load(&mut self) -> eyre::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let modified = std::fs::metadata(path).and_then(|metadata| metadata.modified())?;
+        let list: AccessList = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read access list {path}"))?
+            .parse()
+            .wrap_err_with(|| format!("Failed to parse access list {path}"))?;
+
+        info!(
+            %path,
+            allowed_peers = list.allowed_peers.len(),
+            allowed_networks = list.allowed_networks.len(),
+            denied_peers = list.denied_peers.len(),
+            denied_networks = list.denied_networks.len(),
+            "Loaded access list"
+        );
+
+        for (connection_id, (peer_id, ip)) in &self.inbound_connections {
+            if !list.is_allowed(peer_id, *ip) {
+                info!(%peer_id, "Closing connection of peer no longer allowed");
+                self.pending_closes.push_back((*peer_id, *connection_id));
+            }
+        }
+
+        *self
+            .list
+            .write()
+            .expect("access list lock to not be poisoned") = list;
+        self.modified = Some(modified);
+
+        Ok(())
+    }
+
+    fn is_allowed(&self, peer_id: &PeerId, ip: Option<IpAddr>) -> bool {
+        self.list
+            .read()
+            .expect("access list lock to not be poisoned")
+            .is_allowed(peer_id, ip)
+    }
+}
+
+struct RelayFilter(Arc<RwLock<AccessList>>);
+
+impl relay::RateLimiter for RelayFilter {
+    fn try_next(&mut self, peer: PeerId, addr: &Multiaddr, _: Instant) -> bool {
+        self.0
+            .read()
+            .expect("access list lock to not be poisoned")
+            .is_allowed(&peer, ip(addr))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("denied by the access list")]
+pub struct Denied;
+
+impl NetworkBehaviour for Behaviour {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Infallible;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        _: ConnectionId,
+        _: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        let denied = self
+            .list
+            .read()
+            .expect("access list lock to not be poisoned")
+            .is_denied_ip(ip(remote_addr));
+
+        if denied {
+            debug!(%remote_addr, "Denied inbound connection");
+            return Err(ConnectionDenied::new(Denied));
+        }
+
+        Ok(())
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        _: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        let ip = ip(remote_addr);
+
+        if !self.is_allowed(&peer, ip) {
+            debug!(%peer, %remote_addr, "Denied inbound connection");
+            return Err(ConnectionDenied::new(Denied));
+        }
+
+        self.inbound_connections.insert(connection_id, (peer, ip));
+
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        _: PeerId,
+        _: &Multiaddr,
+        _: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        if let FromSwarm::ConnectionClosed(ConnectionClosed { connection_id, .. }) = event {
+            self.inbound_connections.remove(&connection_id);
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _: PeerId,
+        _: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        match event {}
+    }
+
+    fn poll(&mut self, _: &mut Context<'_>) -> Poll<ToSwarm<Infallible, THandlerInEvent<Self>>> {
+        if let Some((peer_id, connection_id)) = self.pending_closes.pop_front() {
+            return Poll::Ready(ToSwarm::CloseConnection {
+                peer_id,
+                connection: CloseConnection::One(connection_id),
+            });
+        }
+
+        Poll::Pending
+    }
+}
+
+/// IP address a connection was established from.
+pub fn ip(addr: &Multiaddr) -> Option<IpAddr> {
+    match addr.iter().next()? {
+        multiaddr::Protocol::Ip4(ip) => Some(ip.into()),
+        multiaddr::Protocol::Ip6(ip) => Some(ip.into()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_access_list() {
+        let peer_id = PeerId::random();
+        let list: AccessList = format!(
+            "# comment\n\nallow {peer_id}\ndeny 10.0.0.0/8  # inline comment\ndeny 2001:db8::1\n"
+        )
+        .parse()
+        .unwrap();
+
+        assert_eq!(
+            list,
+            AccessList {
+                allowed_peers: [peer_id].into(),
+                allowed_networks: vec![],
+                denied_peers: Default::default(),
+                denied_networks: vec![
+                    "10.0.0.0/8".parse().unwrap(),
+                    "2001:db8::1/128".parse().unwrap()
+                ],
+            }
+        );
+
+        assert!("block 10.0.0.1".parse::<AccessList>().is_err());
+        assert!("deny nonsense".parse::<AccessList>().is_err());
+        assert!("deny".parse::<AccessList>().is_err());
+    }
+
+    #[test]
+    fn test_access_list_decisions() {
+        let (allowed, denied, other) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let list: AccessList = format!("deny {denied}\ndeny 10.0.0.0/8\n").parse().unwrap();
+
+        assert!(list.is_allowed(&other, None));
+        assert!(list.is_allowed(&other, Some("192.168.1.1".parse().unwrap())));
+        assert!(!list.is_allowed(&denied, None));
+        assert!(!list.is_allowed(&other, Some("10.1.2.3".parse().unwrap())));
+
+        let list: AccessList = format!("allow {allowed}\nallow 192.168.0.0/16\ndeny 192.168.1.1\n")
+            .parse()
+            .unwrap();
+
+        assert!(list.is_allowed(&allowed, None));
+        assert!(list.is_allowed(&other, Some("192.168.2.1".parse().unwrap())));
+        assert!(!list.is_allowed(&other, None));
+        assert!(!list.is_allowed(&other, Some("10.1.2.3".parse().unwrap())));
+        // Denied entries take precedence.
+        assert!(!list.is_allowed(&allowed, Some("192.168.1.1".parse().unwrap())));
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::SystemTime;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use libp2p::kad;
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot};
+use tracing::info;
+
+use crate::server::EventLoop;
+use crate::store::system_time_to_unix_millis;
+
+/// Queries sent by the admin API to the event loop owning the swarm.
+#[derive(Debug)]
+pub enum Command {
+    Peers {
+        sender: oneshot::Sender<Vec<PeerInfo>>,
+    },
+    Reservations {
+        sender: oneshot::Sender<Vec<ReservationInfo>>,
+    },
+    Circuits {
+        sender: oneshot::Sender<Vec<CircuitInfo>>,
+    },
+    Registrations {
+        sender: oneshot::Sender<Vec<NamespaceInfo>>,
+    },
+    KadBuckets {
+        sender: oneshot::Sender<Vec<KadBucketInfo>>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct PeerInfo {
+    pub peer_id: String,
+    pub agent_version: Option<String>,
+    pub protocol_version: Option<String>,
+    pub connected_addrs: Vec<String>,
+    pub listen_addrs: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReservationInfo {
+    pub peer_id: String,
+    pub accepted_at: u64,
+    pub renewed_at: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CircuitInfo {
+    pub src_peer_id: String,
+    pub dst_peer_id: String,
+    pub opened_at: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NamespaceInfo {
+    pub namespace: String,
+    pub registrations: Vec<RegistrationInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RegistrationInfo {
+    pub peer_id: String,
+    pub addresses: Vec<String>,
+    pub ttl: u64,
+    pub registered_at: u64,
+    /// Federation peer the registration was replicated from.
+    pub replicated_from: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KadBucketInfo {
+    /// Index of the bucket, i.e. the base 2 logarithm of the distance of its peers.
+    pub index: Option<u32>,
+    pub has_pending: bool,
+    pub entries: Vec<KadEntryInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KadEntryInfo {
+    pub peer_id: String,
+    pub connected: bool,
+    pub addresses: Vec<String>,
+}
+
+/// Seconds since the unix epoch, as used for all timestamps of the admin API.
+pub fn unix_timestamp(time: SystemTime) -> u64 {
+    system_time_to_unix_millis(time) / 1000
+}
+
+impl EventLoop {
+    pub(crate) fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Peers { sender } => {
+                let peers = self
+                    .state
+                    .peers()
+                    .map(|(peer_id, peer)| PeerInfo {
+                        peer_id: peer_id.to_string(),
+                        agent_version: peer.agent_version.clone(),
+                        protocol_version: peer.protocol_version.clone(),
+                        connected_addrs: peer
+                            .connections
+                            .values()
+                            .map(ToString::to_string)
+                            .collect(),
+                        listen_addrs: peer.listen_addrs.iter().map(ToString::to_string).collect(),
+                    })
+                    .collect();
+
+                let _ = sender.send(peers);
+            }
+            Command::Reservations { sender } => {
+                let reservations = self
+                    .state
+                    .reservations()
+                    .map(|(peer_id, reservation)| ReservationInfo {
+                        peer_id: peer_id.to_string(),
+                        accepted_at: unix_timestamp(reservation.accepted_at),
+                        renewed_at: reservation.renewed_at.map(unix_timestamp),
+                    })
+                    .collect();
+
+                let _ = sender.send(reservations);
+            }
+            Command::Circuits { sender } => {
+                let circuits = self
+                    .state
+                    .circuits()
+                    .map(|circuit| CircuitInfo {
+                        src_peer_id: circuit.src_peer_id.to_string(),
+                        dst_peer_id: circuit.dst_peer_id.to_string(),
+                        opened_at: unix_timestamp(circuit.opened_at),
+                    })
+                    .collect();
+
+                let _ = sender.send(circuits);
+            }
+            Command::Registrations { sender } => {
+                let mut namespaces = BTreeMap::<_, Vec<_>>::new();
+                for entry in self.swarm.behaviour().rendezvous.registrations().iter() {
+                    let registration = &entry.registration;
+                    namespaces
+                        .entry(registration.namespace.to_string())
+                        .or_default()
+                        .push(RegistrationInfo {
+                            peer_id: registration.record.peer_id().to_string(),
+                            addresses: registration
+                                .record
+                                .addresses()
+                                .iter()
+                                .map(ToString::to_string)
+                                .collect(),
+                            ttl: registration.ttl,
+                            registered_at: unix_timestamp(entry.registered_at),
+                            replicated_from: entry
+                                .replicated_from
+                                .map(|peer_id| peer_id.to_string()),
+                        });
+                }
+
+                let namespaces = namespaces
+                    .into_iter()
+                    .map(|(namespace, registrations)| NamespaceInfo {
+                        namespace,
+                        registrations,
+                    })
+                    .collect();
+
+                let _ = sender.send(namespaces);
+            }
+            Command::KadBuckets { sender } => {
+                let buckets = self
+                    .swarm
+                    .behaviour_mut()
+                    .kad
+                    .kbuckets()
+                    .map(|bucket| KadBucketInfo {
+                        index: bucket.range().0.ilog2(),
+                        has_pending: bucket.has_pending(),
+                        entries: bucket
+                            .iter()
+                            .map(|entry| KadEntryInfo {
+                                peer_id: entry.node.key.preimage().to_string(),
+                                connected: matches!(entry.status, kad::NodeStatus::Connected),
+                                addresses: entry
+                                    .node
+                                    .value
+                                    .iter()
+                                    .map(ToString::to_string)
+                                    .collect(),
+                            })
+                            .collect(),
+                    })
+                    .collect();
+
+                let _ = sender.send(buckets);
+            }
+        }
+    }
+}
+
+/// Serves the read-only admin API until the process exits.
+///
+/// The API exposes peer ids and addresses of all connected peers and is meant to be
+/// bound to a loopback or otherwise private interface.
+pub async fn serve(addr: SocketAddr, sender: mpsc::Sender<Command>) -> eyre::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+
+    info!("Serving admin API on http://{}", listener.local_addr()?);
+
+    let app = Router::new()
+        .route("/peers", get(peers))
+        .route("/relay/reservations", get(reservations))
+        .route("/relay/circuits", get(circuits))
+        .route("/rendezvous/registrations", get(registrations))
+        .route("/kad/buckets", get(kad_buckets))
+        .with_state(sender);
+
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
+
+async fn peers(State(sender): State<mpsc::Sender<Command>>) -> Response<Vec<PeerInfo>> {
+    request(&sender, |sender| Command::Peers { sender }).await
+}
+
+async fn reservations(
+    State(sender): State<mpsc::Sender<Command>>,
+) -> Response<Vec<ReservationInfo>> {
+    request(&sender, |sender| Command::Reservations { sender }).await
+}
+
+async fn circuits(State(sender): State<mpsc::Sender<Command>>) -> Response<Vec<CircuitInfo>> {
+    request(&sender, |sender| Command::Circuits { sender }).await
+}
+
+async fn registrations(
+    State(sender): State<mpsc::Sender<Command>>,
+) -> Response<Vec<NamespaceInfo>> {
+    request(&sender, |sender| Command::Registrations { sender }).await
+}
+
+async fn kad_buckets(State(sender): State<mpsc::Sender<Command>>) -> Response<Vec<KadBucketInfo>> {
+    request(&sender, |sender| Command::KadBuckets { sender }).await
+}
+
+type Response<T> = Result<Json<T>, (StatusCode, &'static str)>;
+
+async fn request<T>(
+    sender: &mpsc::Sender<Command>,
+    command: impl FnOnce(oneshot::Sender<T>) -> Command,
+) -> Response<T> {
+    const UNAVAILABLE: (StatusCode, &str) = (StatusCode::SERVICE_UNAVAILABLE, "event loop stopped");
+
+    let (response_sender, response_receiver) = oneshot::channel();
+
+    sender
+        .send(command(response_sender))
+        .await
+        .map_err(|_| UNAVAILABLE)?;
+
+    response_receiver.await.map(Json).map_err(|_| UNAVAILABLE)
+}
@@ -0,0 +1,393 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+use camino::Utf8PathBuf;
+use eyre::WrapErr;
+use libp2p::relay;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::store::system_time_to_unix_millis;
+use crate::{rendezvous, state};
+
+/// When the audit log is rotated.
+#[derive(Clone, Copy, Debug)]
+pub struct Rotation {
+    pub max_bytes: u64,
+    pub max_age: Duration,
+}
+
+/// Append-only JSON lines record of relay reservations and circuits and of rendezvous
+/// requests, for accounting and investigating abuse after the fact.
+///
+/// Closed circuits are recorded with the bytes relayed over them in both directions,
+/// which include the encryption and multiplexing overhead of the relayed connection.
+///
+/// Once the file exceeds the [`Rotation`] limits it is renamed with the time of the
+/// rotation as suffix and a new file is started. Rotated files are never removed, their
+/// retention is left to the operator.
+pub struct AuditLog {
+    path: Utf8PathBuf,
+    rotation: Rotation,
+    file: File,
+    size: u64,
+    created_at: SystemTime,
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    /// Unix timestamp in milliseconds.
+    timestamp: u64,
+    #[serde(flatten)]
+    entry: Entry<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Entry<'a> {
+    ReservationAccepted {
+        peer_id: String,
+        renewed: bool,
+    },
+    ReservationDenied {
+        peer_id: String,
+    },
+    ReservationTimedOut {
+        peer_id: String,
+    },
+    CircuitOpened {
+        src_peer_id: String,
+        dst_peer_id: String,
+    },
+    CircuitDenied {
+        src_peer_id: String,
+        dst_peer_id: String,
+    },
+    CircuitClosed {
+        src_peer_id: String,
+        dst_peer_id: String,
+        /// Duration in milliseconds, unknown if the opening of the circuit was missed.
+        duration_ms: Option<u64>,
+        error: Option<String>,
+        /// Bytes relayed in both directions, unknown if not counted by the relay.
+        bytes: Option<u64>,
+    },
+    Registered {
+        peer_id: String,
+        namespace: String,
+        ttl: u64,
+    },
+    RegistrationRefused {
+        peer_id: String,
+        namespace: String,
+        error_code: &'a str,
+    },
+    Unregistered {
+        peer_id: String,
+        namespace: String,
+    },
+    RegistrationExpired {
+        peer_id: String,
+        namespace: String,
+    },
+    Replicated {
+        peer_id: String,
+        namespace: String,
+        ttl: u64,
+        federation_peer_id: String,
+    },
+    ReplicaUnregistered {
+        peer_id: String,
+        namespace: String,
+        federation_peer_id: String,
+    },
+    Discovered {
+        peer_id: String,
+        num_registrations: usize,
+    },
+    DiscoveryRefused {
+        peer_id: String,
+        error_code: &'a str,
+    },
+}
+
+impl AuditLog {
+    pub fn open(path: Utf8PathBuf, rotation: Rotation) -> eyre::Result<Self> {
+        let (file, size, created_at) = open_file(&path)?;
+
+        info!(%path, "Writing audit log");
+
+        Ok(Self {
+            path,
+            rotation,
+            file,
+            size,
+            created_at,
+        })
+    }
+
+    /// Records a relay event, with the circuit it closed and the bytes relayed over it
+    /// if any.
+    #[allow(deprecated)] // Failure events are not audited, they are logged instead.
+    pub fn record_relay_event(
+        &mut self,
+        event: &relay::Event,
+        closed: Option<&state::Circuit>,
+        bytes: Option<u64>,
+    ) {
+        let entry = match event {
+            relay::Event::ReservationReqAccepted {
+                src_peer_id,
+                renewed,
+            } => Entry::ReservationAccepted {
+                peer_id: src_peer_id.to_string(),
+                renewed: *renewed,
+            },
+            relay::Event::ReservationReqDenied { src_peer_id } => Entry::ReservationDenied {
+                peer_id: src_peer_id.to_string(),
+            },
+            relay::Event::ReservationTimedOut { src_peer_id } => Entry::ReservationTimedOut {
+                peer_id: src_peer_id.to_string(),
+            },
+            relay::Event::CircuitReqAccepted {
+                src_peer_id,
+                dst_peer_id,
+            } => Entry::CircuitOpened {
+                src_peer_id: src_peer_id.to_string(),
+                dst_peer_id: dst_peer_id.to_string(),
+            },
+            relay::Event::CircuitReqDenied {
+                src_peer_id,
+                dst_peer_id,
+            } => Entry::CircuitDenied {
+                src_peer_id: src_peer_id.to_string(),
+                dst_peer_id: dst_peer_id.to_string(),
+            },
+            relay::Event::CircuitClosed {
+                src_peer_id,
+                dst_peer_id,
+                error,
+            } => Entry::CircuitClosed {
+                src_peer_id: src_peer_id.to_string(),
+                dst_peer_id: dst_peer_id.to_string(),
+                duration_ms: closed
+                    .and_then(|circuit| circuit.opened_at.elapsed().ok())
+                    .map(|duration| duration.as_millis() as u64),
+                error: error.as_ref().map(ToString::to_string),
+                bytes,
+            },
+            _ => return,
+        };
+
+        self.record(entry);
+    }
+
+    pub fn record_rendezvous_event(&mut self, event: &rendezvous::Event) {
+        let entry = match event {
+            rendezvous::Event::PeerRegistered { peer, registration } => Entry::Registered {
+                peer_id: peer.to_string(),
+                namespace: registration.namespace.to_string(),
+                ttl: registration.ttl,
+            },
+            rendezvous::Event::PeerNotRegistered {
+                peer,
+                namespace,
+                error,
+            } => Entry::RegistrationRefused {
+                peer_id: peer.to_string(),
+                namespace: namespace.to_string(),
+                error_code: rendezvous::error_code(error),
+            },
+            rendezvous::Event::PeerUnregistered { peer, namespace } => Entry::Unregistered {
+                peer_id: peer.to_string(),
+                namespace: namespace.to_string(),
+            },
+            rendezvous::Event::RegistrationExpired(registration) => Entry::RegistrationExpired {
+                peer_id: registration.record.peer_id().to_string(),
+                namespace: registration.namespace.to_string(),
+            },
+            rendezvous::Event::RegistrationReplicated { from, registration } => Entry::Replicated {
+                peer_id: registration.record.peer_id().to_string(),
+                namespace: registration.namespace.to_string(),
+                ttl: registration.ttl,
+                federation_peer_id: from.to_string(),
+            },
+            rendezvous::Event::ReplicaUnregistered {
+                from,
+                peer,
+                namespace,
+            } => Entry::ReplicaUnregistered {
+                peer_id: peer.to_string(),
+                namespace: namespace.to_string(),
+                federation_peer_id: from.to_string(),
+            },
+            rendezvous::Event::DiscoverServed {
+                enquirer,
+                registrations,
+            } => Entry::Discovered {
+                peer_id: enquirer.to_string(),
+                num_registrations: registrations.len(),
+            },
+            rendezvous::Event::DiscoverNotServed { enquirer, error } => Entry::DiscoveryRefused {
+                peer_id: enquirer.to_string(),
+                error_code: rendezvous::error_code(error),
+            },
+        };
+
+        self.record(entry);
+    }
+
+    fn record(&mut self, entry: Entry) {
+        let now = SystemTime::now();
+        let record = Record {
+            timestamp: system_time_to_unix_millis(now),
+            entry,
+        };
+
+        if let Err(err) = self.write(&record, now) {
+            warn!(path = %self.path, ?err, "Failed to write audit log");
+        }
+    }
+
+    fn write(&mut self, record: &Record, now: SystemTime) -> eyre::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        let expired = now
+            .duration_since(self.created_at)
+            .is_ok_and(|age| age >= self.rotation.max_age);
+        if self.size > 0 && (self.size + line.len() as u64 > self.rotation.max_bytes || expired) {
+            self.rotate(now)?;
+        }
+
+        self.file.write_all(&line)?;
+        self.size += line.len() as u64;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self, now: SystemTime) -> eyre::Result<()> {
+        let suffix = system_time_to_unix_millis(now);
+        let mut rotated_path = Utf8PathBuf::from(format!("{}.{suffix}", self.path));
+        // Renaming would silently replace a file rotated within the same millisecond.
+        for n in 1.. {
+            if !rotated_path.try_exists()? {
+                break;
+            }
+            rotated_path = Utf8PathBuf::from(format!("{}.{suffix}-{n}", self.path));
+        }
+
+        std::fs::rename(&self.path, &rotated_path)
+            .wrap_err_with(|| format!("Failed to rotate {} to {rotated_path}", self.path))?;
+        (self.file, self.size, self.created_at) = open_file(&self.path)?;
+
+        info!(path = %rotated_path, "Rotated audit log");
+
+        Ok(())
+    }
+}
+
+fn open_file(path: &Utf8PathBuf) -> eyre::Result<(File, u64, SystemTime)> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .wrap_err_with(|| format!("Failed to open audit log {path}"))?;
+
+    let metadata = file.metadata()?;
+    // The creation time is not available on all file systems, the age of such files
+    // then only counts from when they were opened.
+    let created_at = metadata.created().unwrap_or_else(|_| SystemTime::now());
+
+    Ok((file, metadata.len(), created_at))
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::PeerId;
+
+    use super::*;
+
+    fn read_lines(path: &Utf8PathBuf) -> Vec<serde_json::Value> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_rotate_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Utf8PathBuf::from_path_buf(dir.path().join("audit.jsonl")).unwrap();
+        let rotation = Rotation {
+            max_bytes: 200,
+            max_age: Duration::from_secs(3600),
+        };
+        let peer_id = PeerId::random();
+
+        let mut audit = AuditLog::open(path.clone(), rotation).unwrap();
+        for _ in 0..3 {
+            audit.record_relay_event(
+                &relay::Event::ReservationReqAccepted {
+                    src_peer_id: peer_id,
+                    renewed: false,
+                },
+                None,
+                None,
+            );
+        }
+
+        let lines = read_lines(&path);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["event"], "reservation_accepted");
+        assert_eq!(lines[0]["peer_id"], peer_id.to_string());
+        assert_eq!(lines[0]["renewed"], false);
+
+        let rotated = std::fs::read_dir(dir.path()).unwrap().count() - 1;
+        assert_eq!(rotated, 2);
+
+        drop(audit);
+        let mut audit = AuditLog::open(path.clone(), rotation).unwrap();
+        audit.record_relay_event(
+            &relay::Event::ReservationTimedOut {
+                src_peer_id: peer_id,
+            },
+            None,
+            None,
+        );
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count() - 1, 3);
+    }
+
+    #[test]
+    fn test_circuit_closed_with_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Utf8PathBuf::from_path_buf(dir.path().join("audit.jsonl")).unwrap();
+        let rotation = Rotation {
+            max_bytes: 1 << 20,
+            max_age: Duration::from_secs(3600),
+        };
+        let (src_peer_id, dst_peer_id) = (PeerId::random(), PeerId::random());
+
+        let mut audit = AuditLog::open(path.clone(), rotation).unwrap();
+        audit.record_relay_event(
+            &relay::Event::CircuitClosed {
+                src_peer_id,
+                dst_peer_id,
+                error: None,
+            },
+            Some(&state::Circuit {
+                src_peer_id,
+                dst_peer_id,
+                opened_at: SystemTime::now(),
+            }),
+            Some(4096),
+        );
+
+        let lines = read_lines(&path);
+        assert_eq!(lines[0]["event"], "circuit_closed");
+        assert_eq!(lines[0]["bytes"], 4096);
+        assert!(lines[0]["duration_ms"].is_u64());
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use libp2p::rendezvous::Namespace;
+use libp2p::PeerId;
+
+use crate::rendezvous::{self, NamespacePattern, Registrations};
+
+/// Peers that may be connected through relayed circuits.
+#[derive(Debug, Clone, Default)]
+pub enum Policy {
+    /// Any peers, as long as the destination holds a reservation.
+    #[default]
+    Open,
+    /// Peers registered under a shared rendezvous namespace, or under namespaces
+    /// linked to each other.
+    SharedNamespace { links: Vec<Link> },
+}
+
+/// Namespaces whose peers may be connected as if they shared a namespace, parsed from
+/// `<pattern>=<pattern>`, e.g. `/calimero/devnet/*=/calimero/devnet/tools`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link(pub NamespacePattern, pub NamespacePattern);
+
+impl Link {
+    fn links(&self, a: &Namespace, b: &Namespace) -> bool {
+        (self.0.matches(a) && self.1.matches(b)) || (self.0.matches(b) && self.1.matches(a))
+    }
+}
+
+impl FromStr for Link {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (a, b) = s
+            .split_once('=')
+            .ok_or_else(|| eyre::eyre!("expected `<namespace>=<namespace>`"))?;
+
+        Ok(Self(
+            a.trim().parse().map_err(eyre::Report::msg)?,
+            b.trim().parse().map_err(eyre::Report::msg)?,
+        ))
+    }
+}
+
+impl fmt::Display for Link {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.0, self.1)
+    }
+}
+
+/// Enforces the [`Policy`] on relayed circuits, checked by the relay before accepting
+/// them, see [`crate::classes::Behaviour`].
+#[derive(Clone)]
+pub(crate) struct Admission {
+    policy: Policy,
+    /// Namespaces each peer is registered under, mirrored from the rendezvous server.
+    namespaces: Arc<RwLock<HashMap<PeerId, HashSet<Namespace>>>>,
+}
+
+impl Admission {
+    pub fn new(policy: Policy, registrations: &Registrations) -> Self {
+        let mut namespaces: HashMap<PeerId, HashSet<Namespace>> = HashMap::new();
+        for entry in registrations.iter() {
+            let registration = &entry.registration;
+            namespaces
+                .entry(registration.record.peer_id())
+                .or_default()
+                .insert(registration.namespace.clone());
+        }
+
+        Self {
+            policy,
+            namespaces: Arc::new(RwLock::new(namespaces)),
+        }
+    }
+
+    /// Whether a circuit from `src` to `dst` is allowed.
+    pub fn allows(&self, src: &PeerId, dst: &PeerId) -> bool {
+        let Policy::SharedNamespace { links } = &self.policy else {
+            return true;
+        };
+
+        let namespaces = self
+            .namespaces
+            .read()
+            .expect("namespaces lock to not be poisoned");
+        let (Some(src), Some(dst)) = (namespaces.get(src), namespaces.get(dst)) else {
+            return false;
+        };
+
+        src.iter().any(|a| {
+            dst.iter()
+                .any(|b| a == b || links.iter().any(|link| link.links(a, b)))
+        })
+    }
+
+    pub fn on_rendezvous_event(&mut self, event: &rendezvous::Event) {
+        match event {
+            rendezvous::Event::PeerRegistered { registration, .. }
+            | rendezvous::Event::RegistrationReplicated { registration, .. } => {
+                self.namespaces
+                    .write()
+                    .expect("namespaces lock to not be poisoned")
+                    .entry(registration.record.peer_id())
+                    .or_default()
+                    .insert(registration.namespace.clone());
+            }
+            rendezvous::Event::PeerUnregistered { peer, namespace }
+            | rendezvous::Event::ReplicaUnregistered {
+                peer, namespace, ..
+            } => self.remove(peer, namespace),
+            rendezvous::Event::RegistrationExpired(registration) => {
+                self.remove(&registration.record.peer_id(), &registration.namespace);
+            }
+            rendezvous::Event::DiscoverServed { .. }
+            | rendezvous::Event::DiscoverNotServed { .. }
+            | rendezvous::Event::PeerNotRegistered { .. } => {}
+        }
+    }
+
+    fn remove(&mut self, peer: &PeerId, namespace: &Namespace) {
+        let mut namespaces = self
+            .namespaces
+            .write()
+            .expect("namespaces lock to not be poisoned");
+
+        if let Some(peer_namespaces) = namespaces.get_mut(peer) {
+            peer_namespaces.remove(namespace);
+            if peer_namespaces.is_empty() {
+                namespaces.remove(peer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::core::PeerRecord;
+    use libp2p::identity::Keypair;
+    use libp2p::rendezvous::Registration;
+
+    use super::*;
+
+    fn registered(keypair: &Keypair, namespace: &'static str) -> rendezvous::Event {
+        rendezvous::Event::PeerRegistered {
+            peer: keypair.public().to_peer_id(),
+            registration: Registration {
+                namespace: Namespace::from_static(namespace),
+                record: PeerRecord::new(keypair, vec![]).unwrap(),
+                ttl: 60,
+            },
+        }
+    }
+
+    #[test]
+    fn test_shared_namespace_policy() {
+        let policy = Policy::SharedNamespace {
+            links: vec!["/calimero/devnet/*=/calimero/tools".parse().unwrap()],
+        };
+        let mut admission = Admission::new(policy, &Registrations::default());
+
+        let (chat, echo, tool, other) = (
+            Keypair::generate_ed25519(),
+            Keypair::generate_ed25519(),
+            Keypair::generate_ed25519(),
+            Keypair::generate_ed25519(),
+        );
+        let [chat_id, echo_id, tool_id, other_id] =
+            [&chat, &echo, &tool, &other].map(|keypair| keypair.public().to_peer_id());
+
+        admission.on_rendezvous_event(&registered(&chat, "/calimero/devnet/chat"));
+        admission.on_rendezvous_event(&registered(&echo, "/calimero/devnet/chat"));
+        admission.on_rendezvous_event(&registered(&tool, "/calimero/tools"));
+        admission.on_rendezvous_event(&registered(&other, "/other"));
+
+        assert!(admission.allows(&chat_id, &echo_id));
+        // Linked namespaces are linked in both directions.
+        assert!(admission.allows(&chat_id, &tool_id));
+        assert!(admission.allows(&tool_id, &echo_id));
+        assert!(!admission.allows(&chat_id, &other_id));
+        assert!(!admission.allows(&other_id, &tool_id));
+        assert!(!admission.allows(&chat_id, &PeerId::random()));
+
+        admission.on_rendezvous_event(&rendezvous::Event::PeerUnregistered {
+            peer: echo_id,
+            namespace: Namespace::from_static("/calimero/devnet/chat"),
+        });
+        assert!(!admission.allows(&chat_id, &echo_id));
+
+        let open = Admission::new(Policy::Open, &Registrations::default());
+        assert!(open.allows(&chat_id, &PeerId::random()));
+
+        assert!("/calimero".parse::<Link>().is_err());
+        assert!("/calimero/**/x=/tools".parse::<Link>().is_err());
+    }
+}
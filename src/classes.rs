@@ -0,0 +1,884 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use std::{io, iter};
+
+use camino::Utf8Path;
+use either::Either;
+use eyre::WrapErr;
+use libp2p::core::Endpoint;
+use libp2p::swarm::{
+    ConnectionClosed, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler,
+    THandlerInEvent, THandlerOutEvent, ToSwarm,
+};
+use libp2p::{relay, Multiaddr, PeerId};
+use prometheus_client::metrics::counter::Counter;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::config::RateLimit;
+use crate::{circuits, compat, token};
+
+mod handler;
+
+use handler::{CircuitLimits, Handler, RelayEvent, RelayOut};
+
+/// Events of the relay.
+#[derive(Debug)]
+pub enum Event {
+    /// Event of the relay, other than the closing of a circuit.
+    Relay(relay::Event),
+    /// A circuit was closed, once per accepted circuit.
+    CircuitClosed {
+        src_peer_id: PeerId,
+        dst_peer_id: PeerId,
+        error: Option<io::Error>,
+        /// Bytes relayed in both directions, including the encryption and multiplexing
+        /// overhead of the connection relayed over the circuit.
+        bytes: u64,
+    },
+}
+
+/// Relay limits applying to the peers of a class.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of reservations held by the peers of the class together.
+    pub max_reservations: usize,
+    pub max_circuits_per_peer: usize,
+    pub max_circuit_duration: Duration,
+    pub max_circuit_bytes: u64,
+    pub reservation_rate_per_peer: RateLimit,
+    pub reservation_rate_per_ip: RateLimit,
+    pub circuit_rate_per_peer: RateLimit,
+    pub circuit_rate_per_ip: RateLimit,
+}
+
+/// Class of peers served with their own relay limits, e.g. the Calimero
+/// infrastructure nodes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Class {
+    /// Name of the class, which relay tokens grant membership of with their class claim.
+    pub name: String,
+    pub peers: HashSet<PeerId>,
+    pub limits: Limits,
+}
+
+/// Relay classes, with the limits of the anonymous peers belonging to none of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub anonymous: Limits,
+    pub classes: Vec<Class>,
+}
+
+/// Limits of a class as written in the classes file, unset ones falling back to
+/// those of anonymous peers.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ClassTable {
+    #[serde(default)]
+    peers: Vec<String>,
+    max_reservations: Option<usize>,
+    max_circuits_per_peer: Option<usize>,
+    /// Seconds.
+    max_circuit_duration: Option<u64>,
+    max_circuit_bytes: Option<u64>,
+    reservation_rate_per_peer: Option<String>,
+    reservation_rate_per_ip: Option<String>,
+    circuit_rate_per_peer: Option<String>,
+    circuit_rate_per_ip: Option<String>,
+}
+
+impl Config {
+    /// Reads the classes file at `path`, see [`Self::parse`].
+    pub fn load(path: &Utf8Path, anonymous: Limits) -> eyre::Result<Self> {
+        std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read relay classes {path}"))
+            .and_then(|s| Self::parse(&s, anonymous))
+            .wrap_err_with(|| format!("Failed to parse relay classes {path}"))
+    }
+
+    /// Parses a TOML document with a table per class, holding the peers of the class
+    /// and the limits in which it differs from anonymous peers:
+    ///
+    /// ```toml
+    /// [infrastructure]
+    /// peers = ["12D3KooWQLSpVrM77fqiBYGY9Fn7czbko8FWZRxMbhBk1moN8uen"]
+    /// max-circuit-duration = 3600
+    /// max-circuit-bytes = 1073741824
+    /// circuit-rate-per-peer = "none"
+    ///
+    /// # Peers presenting a relay token with the class claim `verified`
+    /// [verified]
+    /// max-reservations = 512
+    /// ```
+    pub fn parse(s: &str, anonymous: Limits) -> eyre::Result<Self> {
+        let tables: HashMap<String, ClassTable> = toml::from_str(s)?;
+
+        let mut classes = vec![];
+        let mut listed = HashSet::new();
+        for (name, table) in tables {
+            let mut peers = HashSet::new();
+            for peer in &table.peers {
+                let peer_id: PeerId = peer
+                    .parse()
+                    .wrap_err_with(|| format!("Invalid peer id {peer:?} in class {name:?}"))?;
+                if !listed.insert(peer_id) {
+                    eyre::bail!("Peer {peer_id} is listed in more than one class");
+                }
+                peers.insert(peer_id);
+            }
+
+            let rate = |rate: Option<String>, default: RateLimit| {
+                rate.map_or(Ok(default), |rate| rate.parse())
+                    .map_err(|err| eyre::eyre!("Invalid rate limit in class {name:?}: {err}"))
+            };
+
+            let limits = Limits {
+                max_reservations: table.max_reservations.unwrap_or(anonymous.max_reservations),
+                max_circuits_per_peer: table
+                    .max_circuits_per_peer
+                    .unwrap_or(anonymous.max_circuits_per_peer),
+                max_circuit_duration: table
+                    .max_circuit_duration
+                    .map_or(anonymous.max_circuit_duration, Duration::from_secs),
+                max_circuit_bytes: table
+                    .max_circuit_bytes
+                    .unwrap_or(anonymous.max_circuit_bytes),
+                reservation_rate_per_peer: rate(
+                    table.reservation_rate_per_peer,
+                    anonymous.reservation_rate_per_peer,
+                )?,
+                reservation_rate_per_ip: rate(
+                    table.reservation_rate_per_ip,
+                    anonymous.reservation_rate_per_ip,
+                )?,
+                circuit_rate_per_peer: rate(
+                    table.circuit_rate_per_peer,
+                    anonymous.circuit_rate_per_peer,
+                )?,
+                circuit_rate_per_ip: rate(
+                    table.circuit_rate_per_ip,
+                    anonymous.circuit_rate_per_ip,
+                )?,
+            };
+
+            classes.push(Class {
+                name,
+                peers,
+                limits,
+            });
+        }
+        classes.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(Self { anonymous, classes })
+    }
+}
+
+/// Assigns peers to classes, by their peer id or else by the class claim of the relay
+/// token they presented.
+#[derive(Clone)]
+struct Classifier {
+    classes: Arc<[Class]>,
+    grants: token::Grants,
+    /// Circuit limits of anonymous peers.
+    anonymous: CircuitLimits,
+}
+
+impl Classifier {
+    /// Index of the class of `peer`, `None` for anonymous peers.
+    fn classify(&self, peer: &PeerId) -> Option<usize> {
+        if let Some(index) = self
+            .classes
+            .iter()
+            .position(|class| class.peers.contains(peer))
+        {
+            return Some(index);
+        }
+
+        let name = self.grants.class(peer)?;
+        self.classes.iter().position(|class| class.name == name)
+    }
+
+    /// Limits of the circuits `peer` is the source of, by its current class.
+    fn circuit_limits(&self, peer: &PeerId) -> CircuitLimits {
+        match self.classify(peer) {
+            Some(index) => CircuitLimits::from(&self.classes[index].limits),
+            None => self.anonymous,
+        }
+    }
+}
+
+impl From<&Limits> for CircuitLimits {
+    fn from(limits: &Limits) -> Self {
+        Self {
+            duration: limits.max_circuit_duration,
+            bytes: limits.max_circuit_bytes,
+        }
+    }
+}
+
+/// Reservations and circuits of the relay, mirrored from its events.
+#[derive(Debug, Default)]
+struct Usage {
+    reservations: HashSet<PeerId>,
+    /// Number of circuits each peer is the source or destination of.
+    circuits: HashMap<PeerId, usize>,
+}
+
+impl Usage {
+    fn on_event(&mut self, event: &Event) {
+        match event {
+            Event::Relay(relay::Event::ReservationReqAccepted { src_peer_id, .. }) => {
+                self.reservations.insert(*src_peer_id);
+            }
+            Event::Relay(relay::Event::ReservationTimedOut { src_peer_id }) => {
+                self.reservations.remove(src_peer_id);
+            }
+            Event::Relay(relay::Event::CircuitReqAccepted {
+                src_peer_id,
+                dst_peer_id,
+            }) => {
+                for peer in [src_peer_id, dst_peer_id] {
+                    *self.circuits.entry(*peer).or_default() += 1;
+                }
+            }
+            Event::CircuitClosed {
+                src_peer_id,
+                dst_peer_id,
+                ..
+            } => {
+                for peer in [src_peer_id, dst_peer_id] {
+                    if let Some(count) = self.circuits.get_mut(peer) {
+                        *count -= 1;
+                        if *count == 0 {
+                            self.circuits.remove(peer);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Request {
+    Reservation,
+    Circuit,
+}
+
+/// Rate limiter enforcing the limits of the class of the requesting peer.
+struct ClassFilter {
+    request: Request,
+    classifier: Classifier,
+    usage: Arc<RwLock<Usage>>,
+    /// Limits of anonymous peers, then of each class.
+    limits: Vec<Limits>,
+    /// Rate limiters of anonymous peers, then of each class.
+    rate_limiters: Vec<Vec<Box<dyn relay::RateLimiter>>>,
+}
+
+impl ClassFilter {
+    fn new(
+        request: Request,
+        classifier: Classifier,
+        usage: Arc<RwLock<Usage>>,
+        limits: Vec<Limits>,
+    ) -> Self {
+        let rate_limiters = limits
+            .iter()
+            .map(|limits| rate_limiters(request, limits))
+            .collect();
+
+        Self {
+            request,
+            classifier,
+            usage,
+            limits,
+            rate_limiters,
+        }
+    }
+}
+
+impl relay::RateLimiter for ClassFilter {
+    fn try_next(&mut self, peer: PeerId, addr: &Multiaddr, now: Instant) -> bool {
+        let class = self.classifier.classify(&peer);
+        let index = class.map_or(0, |index| index + 1);
+        let limits = &self.limits[index];
+
+        {
+            let usage = self.usage.read().expect("usage lock to not be poisoned");
+            let within_limits = match self.request {
+                // Renewals keep the reservation count unchanged.
+                Request::Reservation => {
+                    usage.reservations.contains(&peer)
+                        || usage
+                            .reservations
+                            .iter()
+                            .filter(|holder| self.classifier.classify(holder) == class)
+                            .count()
+                            < limits.max_reservations
+                }
+                Request::Circuit => {
+                    usage.circuits.get(&peer).copied().unwrap_or(0) < limits.max_circuits_per_peer
+                }
+            };
+
+            if !within_limits {
+                return false;
+            }
+        }
+
+        self.rate_limiters[index]
+            .iter_mut()
+            .all(|limiter| limiter.try_next(peer, addr, now))
+    }
+}
+
+/// Rate limiter denying circuits the circuit policy does not allow between their source
+/// and the destination of the request being decided on.
+struct DestinationFilter {
+    admission: circuits::Admission,
+    destination: Arc<Mutex<Option<PeerId>>>,
+}
+
+impl relay::RateLimiter for DestinationFilter {
+    fn try_next(&mut self, peer: PeerId, _: &Multiaddr, _: Instant) -> bool {
+        let destination = *self
+            .destination
+            .lock()
+            .expect("destination lock to not be poisoned");
+        let Some(destination) = destination else {
+            return true;
+        };
+
+        let allowed = self.admission.allows(&peer, &destination);
+        if !allowed {
+            debug!(src_peer_id = %peer, dst_peer_id = %destination, "Denying circuit outside the namespaces of its source");
+        }
+        allowed
+    }
+}
+
+/// Rate limiters of the relay implementing the rate limits of a class.
+fn rate_limiters(request: Request, limits: &Limits) -> Vec<Box<dyn relay::RateLimiter>> {
+    let mut config = relay::Config {
+        reservation_rate_limiters: vec![],
+        circuit_src_rate_limiters: vec![],
+        ..Default::default()
+    };
+
+    match request {
+        Request::Reservation => {
+            if let RateLimit::Limited { limit, interval } = limits.reservation_rate_per_peer {
+                config = config.reservation_rate_per_peer(limit, interval);
+            }
+            if let RateLimit::Limited { limit, interval } = limits.reservation_rate_per_ip {
+                config = config.reservation_rate_per_ip(limit, interval);
+            }
+            config.reservation_rate_limiters
+        }
+        Request::Circuit => {
+            if let RateLimit::Limited { limit, interval } = limits.circuit_rate_per_peer {
+                config = config.circuit_src_per_peer(limit, interval);
+            }
+            if let RateLimit::Limited { limit, interval } = limits.circuit_rate_per_ip {
+                config = config.circuit_src_per_ip(limit, interval);
+            }
+            config.circuit_src_rate_limiters
+        }
+    }
+}
+
+/// Relay server applying the limits of the class of each peer.
+///
+/// Reservation counts and rate limits are enforced by rate limiters added to the relay
+/// configuration, while accepted circuits are relayed by the connection [`Handler`] of
+/// their source with the duration and byte limits of its class at that time. A class
+/// granted by a relay token thereby applies to the circuits opened after the token was
+/// presented, including those on connections established before.
+///
+/// The relay only passes the source of a circuit request to its rate limiters, so the
+/// destination of the request is kept aside while the relay decides on it, for the
+/// circuit policy to be checked before the circuit is accepted.
+///
+/// Reservation and circuit requests are held until the requesting peer was identified
+/// as compatible with the version policy.
+///
+/// The bytes relayed over each circuit are counted by the [`Handler`] relaying it and
+/// reported when the circuit is closed, in place of the closing events of the relay.
+pub struct Behaviour {
+    inner: relay::Behaviour,
+    classifier: Classifier,
+    usage: Arc<RwLock<Usage>>,
+    /// Destination of the circuit request the relay is deciding on.
+    destination: Arc<Mutex<Option<PeerId>>>,
+    /// Bytes relayed over all circuits.
+    relayed_bytes: Counter,
+    /// Circuits being relayed.
+    circuits: HashMap<relay::CircuitId, Traffic>,
+    /// Reservation and circuit requests of peers yet to be identified, with the
+    /// connection they were made on.
+    parked: compat::Parked<(ConnectionId, RelayOut)>,
+    events: VecDeque<Event>,
+}
+
+/// Peers and byte count of a circuit being relayed.
+struct Traffic {
+    src_peer_id: PeerId,
+    /// Connection of the source, whose handler relays the circuit.
+    src_connection_id: ConnectionId,
+    dst_peer_id: PeerId,
+    bytes: Arc<AtomicU64>,
+}
+
+impl Behaviour {
+    /// Creates the relay, replacing the limits of `config` with those of anonymous
+    /// peers if `classes` are given, and adding the limits of the classes and the
+    /// circuit policy of `admission`. Requests are held until their peer is `verified`,
+    /// and the bytes relayed over circuits are added to `relayed_bytes`.
+    ///
+    /// The relay itself is left with the total number of reservations of all classes
+    /// and their highest number of circuits per peer, the limits of each class being
+    /// enforced on top of it.
+    pub(crate) fn new(
+        local_peer_id: PeerId,
+        mut config: relay::Config,
+        classes: Option<Config>,
+        grants: token::Grants,
+        admission: circuits::Admission,
+        verified: compat::Verified,
+        relayed_bytes: Counter,
+    ) -> Self {
+        let usage = Arc::<RwLock<Usage>>::default();
+        let destination = Arc::<Mutex<Option<PeerId>>>::default();
+        config
+            .circuit_src_rate_limiters
+            .push(Box::new(DestinationFilter {
+                admission,
+                destination: destination.clone(),
+            }));
+
+        let Some(classes) = classes else {
+            let anonymous = CircuitLimits {
+                duration: config.max_circuit_duration,
+                bytes: config.max_circuit_bytes,
+            };
+
+            return Self {
+                inner: relay::Behaviour::new(local_peer_id, config),
+                classifier: Classifier {
+                    classes: Arc::new([]),
+                    grants,
+                    anonymous,
+                },
+                usage,
+                destination,
+                relayed_bytes,
+                circuits: Default::default(),
+                parked: compat::Parked::new(verified),
+                events: Default::default(),
+            };
+        };
+
+        let limits: Vec<Limits> = iter::once(classes.anonymous.clone())
+            .chain(classes.classes.iter().map(|class| class.limits.clone()))
+            .collect();
+
+        config.max_reservations = limits.iter().map(|limits| limits.max_reservations).sum();
+        config.max_circuits_per_peer = limits
+            .iter()
+            .map(|limits| limits.max_circuits_per_peer)
+            .max()
+            .unwrap_or_default();
+        // Only announced to the peers of circuits, see `Handler`.
+        config.max_circuit_duration = classes.anonymous.max_circuit_duration;
+        config.max_circuit_bytes = classes.anonymous.max_circuit_bytes;
+
+        let classifier = Classifier {
+            classes: classes.classes.into(),
+            grants,
+            anonymous: CircuitLimits::from(&classes.anonymous),
+        };
+
+        config
+            .reservation_rate_limiters
+            .push(Box::new(ClassFilter::new(
+                Request::Reservation,
+                classifier.clone(),
+                usage.clone(),
+                limits.clone(),
+            )));
+        config
+            .circuit_src_rate_limiters
+            .push(Box::new(ClassFilter::new(
+                Request::Circuit,
+                classifier.clone(),
+                usage.clone(),
+                limits,
+            )));
+
+        Self {
+            inner: relay::Behaviour::new(local_peer_id, config),
+            classifier,
+            usage,
+            destination,
+            relayed_bytes,
+            circuits: Default::default(),
+            parked: compat::Parked::new(verified),
+            events: Default::default(),
+        }
+    }
+
+    /// Passes a reservation or circuit request on to the relay.
+    fn on_request(&mut self, peer_id: PeerId, connection_id: ConnectionId, event: RelayOut) {
+        let Either::Left(RelayEvent::CircuitReqReceived {
+            inbound_circuit_req,
+            ..
+        }) = &event
+        else {
+            self.inner
+                .on_connection_handler_event(peer_id, connection_id, event);
+            return;
+        };
+
+        // The relay decides on the request before returning.
+        let destination = Some(inbound_circuit_req.dst());
+        *self
+            .destination
+            .lock()
+            .expect("destination lock to not be poisoned") = destination;
+        self.inner
+            .on_connection_handler_event(peer_id, connection_id, event);
+        *self
+            .destination
+            .lock()
+            .expect("destination lock to not be poisoned") = None;
+    }
+
+    fn push_event(&mut self, event: Event) {
+        self.usage
+            .write()
+            .expect("usage lock to not be poisoned")
+            .on_event(&event);
+        self.events.push_back(event);
+    }
+}
+
+impl NetworkBehaviour for Behaviour {
+    type ConnectionHandler = Handler;
+    type ToSwarm = Event;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.inner
+            .handle_pending_inbound_connection(connection_id, local_addr, remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        let inner = self.inner.handle_established_inbound_connection(
+            connection_id,
+            peer,
+            local_addr,
+            remote_addr,
+        )?;
+
+        Ok(Handler::new(
+            inner,
+            peer,
+            self.classifier.clone(),
+            self.relayed_bytes.clone(),
+        ))
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.inner.handle_pending_outbound_connection(
+            connection_id,
+            maybe_peer,
+            addresses,
+            effective_role,
+        )
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        let inner = self.inner.handle_established_outbound_connection(
+            connection_id,
+            peer,
+            addr,
+            role_override,
+        )?;
+
+        Ok(Handler::new(
+            inner,
+            peer,
+            self.classifier.clone(),
+            self.relayed_bytes.clone(),
+        ))
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        if let FromSwarm::ConnectionClosed(ConnectionClosed {
+            peer_id,
+            connection_id,
+            remaining_established,
+            ..
+        }) = event
+        {
+            self.parked
+                .retain(|_, (parked_connection_id, _)| *parked_connection_id != connection_id);
+
+            // The handler relaying the circuits of the connection is gone with it.
+            let closed: Vec<_> = self
+                .circuits
+                .iter()
+                .filter(|(_, traffic)| traffic.src_connection_id == connection_id)
+                .map(|(circuit_id, _)| *circuit_id)
+                .collect();
+            for circuit_id in closed {
+                let traffic = self.circuits.remove(&circuit_id).expect("circuit to exist");
+                self.push_event(Event::CircuitClosed {
+                    src_peer_id: traffic.src_peer_id,
+                    dst_peer_id: traffic.dst_peer_id,
+                    error: Some(io::ErrorKind::ConnectionAborted.into()),
+                    bytes: traffic.bytes.load(Ordering::Relaxed),
+                });
+            }
+
+            if remaining_established == 0 {
+                // The relay drops reservations of disconnected peers without an event.
+                self.usage
+                    .write()
+                    .expect("usage lock to not be poisoned")
+                    .reservations
+                    .remove(&peer_id);
+            }
+        }
+
+        self.inner.on_swarm_event(event);
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        let event = match event {
+            handler::Event::Relay(event) => event,
+            handler::Event::CircuitAccepted {
+                circuit_id,
+                dst_peer_id,
+                bytes,
+            } => {
+                self.circuits.insert(
+                    circuit_id,
+                    Traffic {
+                        src_peer_id: peer_id,
+                        src_connection_id: connection_id,
+                        dst_peer_id,
+                        bytes,
+                    },
+                );
+                Either::Left(RelayEvent::CircuitReqAccepted {
+                    circuit_id,
+                    dst_peer_id,
+                })
+            }
+        };
+
+        if let Either::Left(RelayEvent::CircuitClosed {
+            circuit_id,
+            dst_peer_id,
+            error,
+        }) = event
+        {
+            if let Some(traffic) = self.circuits.remove(&circuit_id) {
+                self.push_event(Event::CircuitClosed {
+                    src_peer_id: peer_id,
+                    dst_peer_id,
+                    error,
+                    bytes: traffic.bytes.load(Ordering::Relaxed),
+                });
+            }
+            // The closing event of the relay is dropped, so its error is not needed.
+            let event = Either::Left(RelayEvent::CircuitClosed {
+                circuit_id,
+                dst_peer_id,
+                error: None,
+            });
+            self.inner
+                .on_connection_handler_event(peer_id, connection_id, event);
+            return;
+        }
+
+        if let Either::Left(
+            RelayEvent::ReservationReqReceived { .. } | RelayEvent::CircuitReqReceived { .. },
+        ) = &event
+        {
+            if let Some((connection_id, event)) = self.parked.park(peer_id, (connection_id, event))
+            {
+                self.on_request(peer_id, connection_id, event);
+            }
+            return;
+        }
+
+        self.inner
+            .on_connection_handler_event(peer_id, connection_id, event);
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<ToSwarm<Event, THandlerInEvent<Self>>> {
+        // Requests of peers not identified in time are denied by the version filter.
+        while let Poll::Ready((peer_id, (connection_id, event), _)) = self.parked.poll(cx) {
+            self.on_request(peer_id, connection_id, event);
+        }
+
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Poll::Ready(ToSwarm::GenerateEvent(event));
+            }
+
+            match self.inner.poll(cx) {
+                // Reported with the bytes relayed instead, see `on_connection_handler_event`.
+                Poll::Ready(ToSwarm::GenerateEvent(relay::Event::CircuitClosed { .. })) => {}
+                Poll::Ready(ToSwarm::GenerateEvent(event)) => {
+                    self.push_event(Event::Relay(event));
+                }
+                Poll::Ready(event) => return Poll::Ready(event.map_out(Event::Relay)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use relay::RateLimiter;
+
+    use super::*;
+
+    fn anonymous() -> Limits {
+        Limits {
+            max_reservations: 1,
+            max_circuits_per_peer: 1,
+            max_circuit_duration: Duration::from_secs(120),
+            max_circuit_bytes: 1 << 17,
+            reservation_rate_per_peer: RateLimit::Limited {
+                limit: NonZeroU32::new(1).unwrap(),
+                interval: Duration::from_secs(60),
+            },
+            reservation_rate_per_ip: RateLimit::Unlimited,
+            circuit_rate_per_peer: RateLimit::Unlimited,
+            circuit_rate_per_ip: RateLimit::Unlimited,
+        }
+    }
+
+    #[test]
+    fn test_parse_classes() {
+        let peer_id = PeerId::random();
+        let config = Config::parse(
+            &format!(
+                "[infrastructure]\npeers = [\"{peer_id}\"]\nmax-circuit-bytes = 1073741824\nreservation-rate-per-peer = \"none\"\n\n[verified]\n"
+            ),
+            anonymous(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.classes,
+            vec![
+                Class {
+                    name: "infrastructure".to_owned(),
+                    peers: [peer_id].into(),
+                    limits: Limits {
+                        max_circuit_bytes: 1 << 30,
+                        reservation_rate_per_peer: RateLimit::Unlimited,
+                        ..anonymous()
+                    },
+                },
+                Class {
+                    name: "verified".to_owned(),
+                    peers: Default::default(),
+                    limits: anonymous(),
+                },
+            ]
+        );
+
+        assert!(Config::parse("[a]\nmax-circuit-time = 1\n", anonymous()).is_err());
+        assert!(Config::parse("[a]\npeers = [\"nonsense\"]\n", anonymous()).is_err());
+        assert!(Config::parse(
+            &format!("[a]\npeers = [\"{peer_id}\"]\n[b]\npeers = [\"{peer_id}\"]\n"),
+            anonymous()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_class_limits() {
+        let member = PeerId::random();
+        let classifier = Classifier {
+            classes: Arc::new([Class {
+                name: "infrastructure".to_owned(),
+                peers: [member].into(),
+                limits: Limits {
+                    max_reservations: 2,
+                    reservation_rate_per_peer: RateLimit::Unlimited,
+                    ..anonymous()
+                },
+            }]),
+            grants: Default::default(),
+            anonymous: CircuitLimits::from(&anonymous()),
+        };
+        let usage = Arc::<RwLock<Usage>>::default();
+        let mut filter = ClassFilter::new(
+            Request::Reservation,
+            classifier.clone(),
+            usage.clone(),
+            vec![anonymous(), classifier.classes[0].limits.clone()],
+        );
+        let (addr, now) = (Multiaddr::empty(), Instant::now());
+
+        let (first, second) = (PeerId::random(), PeerId::random());
+        assert!(filter.try_next(first, &addr, now));
+        usage
+            .write()
+            .unwrap()
+            .on_event(&Event::Relay(relay::Event::ReservationReqAccepted {
+                src_peer_id: first,
+                renewed: false,
+            }));
+        // Anonymous peers share a single reservation, limited to one request a minute.
+        assert!(!filter.try_next(second, &addr, now));
+        assert!(!filter.try_next(first, &addr, now));
+
+        // Members are limited by their own class only.
+        for _ in 0..3 {
+            assert!(filter.try_next(member, &addr, now));
+        }
+    }
+}
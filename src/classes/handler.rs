@@ -0,0 +1,350 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use either::Either;
+use libp2p::futures::future::{self, BoxFuture};
+use libp2p::futures::io::{AsyncBufRead, AsyncWrite, AsyncWriteExt, BufReader};
+use libp2p::futures::stream::FuturesUnordered;
+use libp2p::futures::{ready, FutureExt, StreamExt};
+use libp2p::swarm::handler::{ConnectionEvent, ConnectionHandler, ConnectionHandlerEvent};
+use libp2p::swarm::{SubstreamProtocol, THandler, THandlerInEvent, THandlerOutEvent};
+use libp2p::{relay, PeerId, Stream};
+use prometheus_client::metrics::counter::Counter;
+use tokio::time::{self, Sleep};
+
+use super::Classifier;
+
+type Inner = THandler<relay::Behaviour>;
+
+/// Gives access to the handler of non-relayed connections of the relay, whose events
+/// are only exported through the relay's `Either` handler.
+pub(super) trait Split {
+    type Left;
+}
+
+impl<L, R> Split for Either<L, R> {
+    type Left = L;
+}
+
+type RelayIn = <THandlerInEvent<relay::Behaviour> as Split>::Left;
+pub(super) type RelayOut = THandlerOutEvent<relay::Behaviour>;
+pub(super) type RelayEvent = <THandlerOutEvent<relay::Behaviour> as Split>::Left;
+
+type Futures<T> = FuturesUnordered<BoxFuture<'static, T>>;
+
+/// Duration and byte limits of a circuit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct CircuitLimits {
+    pub duration: Duration,
+    /// Bytes relayed in both directions together, unlimited if zero.
+    pub bytes: u64,
+}
+
+/// Events of the [`Handler`].
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum Event {
+    /// Event of the relay's handler.
+    Relay(RelayOut),
+    /// A circuit was accepted and is being relayed, having relayed `bytes` so far.
+    CircuitAccepted {
+        circuit_id: relay::CircuitId,
+        dst_peer_id: PeerId,
+        bytes: Arc<AtomicU64>,
+    },
+}
+
+/// Connection handler of the relay, relaying the circuits requested by the peer itself
+/// instead of leaving them to the relay's handler, so that they get the limits of the
+/// class of the peer at the time they are accepted.
+///
+/// The limits announced to the peers of a circuit remain those of the relay
+/// configuration, they are informational only.
+pub struct Handler {
+    inner: Inner,
+    peer_id: PeerId,
+    classifier: Classifier,
+    /// Bytes relayed over all circuits.
+    relayed_bytes: Counter,
+    /// Circuits being accepted, resolving to the circuit once accepted or to the
+    /// failure event of the relay.
+    accepting: Futures<Result<Accepted, RelayEvent>>,
+    /// Circuits being relayed, resolving to the event of the relay closing them.
+    circuits: Futures<RelayEvent>,
+}
+
+/// Circuit accepted by its source, yet to be relayed.
+struct Accepted {
+    circuit_id: relay::CircuitId,
+    dst_peer_id: PeerId,
+    bytes: Arc<AtomicU64>,
+    circuit: BoxFuture<'static, RelayEvent>,
+}
+
+impl Handler {
+    pub(super) fn new(
+        inner: Inner,
+        peer_id: PeerId,
+        classifier: Classifier,
+        relayed_bytes: Counter,
+    ) -> Self {
+        Self {
+            inner,
+            peer_id,
+            classifier,
+            relayed_bytes,
+            accepting: Default::default(),
+            circuits: Default::default(),
+        }
+    }
+}
+
+impl ConnectionHandler for Handler {
+    type FromBehaviour = <Inner as ConnectionHandler>::FromBehaviour;
+    type ToBehaviour = Event;
+    type InboundProtocol = <Inner as ConnectionHandler>::InboundProtocol;
+    type OutboundProtocol = <Inner as ConnectionHandler>::OutboundProtocol;
+    type InboundOpenInfo = <Inner as ConnectionHandler>::InboundOpenInfo;
+    type OutboundOpenInfo = <Inner as ConnectionHandler>::OutboundOpenInfo;
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, Self::InboundOpenInfo> {
+        self.inner.listen_protocol()
+    }
+
+    fn on_behaviour_event(&mut self, event: Self::FromBehaviour) {
+        let Either::Left(RelayIn::AcceptAndDriveCircuit {
+            circuit_id,
+            dst_peer_id,
+            inbound_circuit_req,
+            mut dst_stream,
+            dst_pending_data,
+        }) = event
+        else {
+            self.inner.on_behaviour_event(event);
+            return;
+        };
+
+        let limits = self.classifier.circuit_limits(&self.peer_id);
+        let relayed_bytes = self.relayed_bytes.clone();
+        self.accepting.push(
+            async move {
+                let (mut src_stream, src_pending_data) = inbound_circuit_req
+                    .accept()
+                    .await
+                    .map_err(|error| RelayEvent::CircuitReqAcceptFailed {
+                        circuit_id,
+                        dst_peer_id,
+                        error,
+                    })?;
+
+                let bytes = Arc::<AtomicU64>::default();
+                let circuit = Circuit::new(limits, bytes.clone(), relayed_bytes);
+                let circuit = async move {
+                    let (src, dst) = future::join(
+                        src_stream.write_all(&dst_pending_data),
+                        dst_stream.write_all(&src_pending_data),
+                    )
+                    .await;
+                    src?;
+                    dst?;
+
+                    circuit.relay(src_stream, dst_stream).await
+                }
+                .map(move |result| RelayEvent::CircuitClosed {
+                    circuit_id,
+                    dst_peer_id,
+                    error: result.err(),
+                })
+                .boxed();
+
+                Ok(Accepted {
+                    circuit_id,
+                    dst_peer_id,
+                    bytes,
+                    circuit,
+                })
+            }
+            .boxed(),
+        );
+    }
+
+    fn connection_keep_alive(&self) -> bool {
+        self.inner.connection_keep_alive()
+            || !self.accepting.is_empty()
+            || !self.circuits.is_empty()
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<
+        ConnectionHandlerEvent<Self::OutboundProtocol, Self::OutboundOpenInfo, Self::ToBehaviour>,
+    > {
+        if let Poll::Ready(event) = self.inner.poll(cx) {
+            return Poll::Ready(event.map_custom(Event::Relay));
+        }
+
+        if let Poll::Ready(Some(result)) = self.accepting.poll_next_unpin(cx) {
+            let event = match result {
+                Ok(Accepted {
+                    circuit_id,
+                    dst_peer_id,
+                    bytes,
+                    circuit,
+                }) => {
+                    self.circuits.push(circuit);
+                    Event::CircuitAccepted {
+                        circuit_id,
+                        dst_peer_id,
+                        bytes,
+                    }
+                }
+                Err(event) => Event::Relay(Either::Left(event)),
+            };
+            return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(event));
+        }
+
+        if let Poll::Ready(Some(event)) = self.circuits.poll_next_unpin(cx) {
+            return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(Event::Relay(
+                Either::Left(event),
+            )));
+        }
+
+        Poll::Pending
+    }
+
+    fn poll_close(&mut self, cx: &mut Context<'_>) -> Poll<Option<Self::ToBehaviour>> {
+        self.inner
+            .poll_close(cx)
+            .map(|event| event.map(Event::Relay))
+    }
+
+    fn on_connection_event(
+        &mut self,
+        event: ConnectionEvent<
+            Self::InboundProtocol,
+            Self::OutboundProtocol,
+            Self::InboundOpenInfo,
+            Self::OutboundOpenInfo,
+        >,
+    ) {
+        self.inner.on_connection_event(event);
+    }
+}
+
+/// Limits and byte counts of a circuit, see [`Self::relay`].
+struct Circuit {
+    limits: CircuitLimits,
+    bytes: Arc<AtomicU64>,
+    relayed_bytes: Counter,
+}
+
+impl Circuit {
+    fn new(limits: CircuitLimits, bytes: Arc<AtomicU64>, relayed_bytes: Counter) -> Self {
+        Self {
+            limits,
+            bytes,
+            relayed_bytes,
+        }
+    }
+
+    /// Relays data between the streams of the circuit in both directions, until both
+    /// are closed or a limit is reached.
+    fn relay(self, src: Stream, dst: Stream) -> Relay {
+        Relay {
+            src: BufReader::new(src),
+            dst: BufReader::new(dst),
+            deadline: Box::pin(time::sleep(self.limits.duration)),
+            max_bytes: self.limits.bytes,
+            bytes: self.bytes,
+            relayed_bytes: self.relayed_bytes,
+        }
+    }
+}
+
+struct Relay {
+    src: BufReader<Stream>,
+    dst: BufReader<Stream>,
+    deadline: Pin<Box<Sleep>>,
+    max_bytes: u64,
+    bytes: Arc<AtomicU64>,
+    /// Bytes relayed over all circuits.
+    relayed_bytes: Counter,
+}
+
+impl Future for Relay {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+
+        loop {
+            let bytes = this.bytes.load(Ordering::Relaxed);
+            if this.max_bytes > 0 && bytes > this.max_bytes {
+                return Poll::Ready(Err(io::Error::other("Max circuit bytes reached.")));
+            }
+
+            let src = forward(&mut this.src, &mut this.dst, cx)?;
+            let dst = forward(&mut this.dst, &mut this.src, cx)?;
+
+            if let (Poll::Ready(0), Poll::Ready(0)) = (src, dst) {
+                return Poll::Ready(Ok(()));
+            }
+
+            let sent: u64 = [src, dst]
+                .into_iter()
+                .map(|sent| match sent {
+                    Poll::Ready(sent) => sent,
+                    Poll::Pending => 0,
+                })
+                .sum();
+            if sent == 0 {
+                break;
+            }
+            this.bytes.fetch_add(sent, Ordering::Relaxed);
+            this.relayed_bytes.inc_by(sent);
+        }
+
+        if this.deadline.poll_unpin(cx).is_ready() {
+            return Poll::Ready(Err(io::ErrorKind::TimedOut.into()));
+        }
+
+        Poll::Pending
+    }
+}
+
+/// Forwards buffered data from `src` to `dst`, closing `dst` once `src` is closed.
+///
+/// Returns the number of bytes forwarded, zero once done.
+fn forward(
+    src: &mut BufReader<Stream>,
+    dst: &mut BufReader<Stream>,
+    cx: &mut Context<'_>,
+) -> Poll<io::Result<u64>> {
+    let buffer = match Pin::new(&mut *src).poll_fill_buf(cx)? {
+        Poll::Ready(buffer) => buffer,
+        Poll::Pending => {
+            let _ = Pin::new(&mut *dst).poll_flush(cx)?;
+            return Poll::Pending;
+        }
+    };
+
+    if buffer.is_empty() {
+        ready!(Pin::new(&mut *dst).poll_flush(cx))?;
+        ready!(Pin::new(&mut *dst).poll_close(cx))?;
+        return Poll::Ready(Ok(0));
+    }
+
+    let sent = ready!(Pin::new(&mut *dst).poll_write(cx, buffer))?;
+    if sent == 0 {
+        return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+    }
+    Pin::new(src).consume(sent);
+
+    Poll::Ready(Ok(sent as u64))
+}
@@ -0,0 +1,272 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use libp2p::core::Endpoint;
+use libp2p::futures::FutureExt;
+use libp2p::swarm::{
+    dummy, CloseConnection, ConnectionClosed, ConnectionDenied, ConnectionId, FromSwarm,
+    NetworkBehaviour, THandler, THandlerInEvent, THandlerOutEvent, ToSwarm,
+};
+use libp2p::{identify, relay, Multiaddr, PeerId};
+use tokio::time::{self, Sleep};
+use tracing::{debug, warn};
+
+pub use boot_node_common::compat::{Incompatible, Policy, Requirement};
+
+/// Time during which inbound connections of a peer found incompatible are denied, after
+/// which it is identified again in case it was upgraded.
+const INCOMPATIBLE_PEER_BACKOFF: Duration = Duration::from_secs(600);
+
+/// Time relay and rendezvous requests of a peer are held for it to be identified,
+/// below the request timeout of the rendezvous protocol.
+const VERIFICATION_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, thiserror::Error)]
+#[error("peer was found incompatible: {0}")]
+pub struct Denied(pub Incompatible);
+
+/// Connected peers identified as compatible with the [`Policy`], shared with the relay
+/// and rendezvous servers to only serve those.
+///
+/// Without requirements in the policy every peer is considered compatible.
+#[derive(Clone, Default)]
+pub(crate) struct Verified {
+    enforced: bool,
+    peers: Arc<RwLock<HashSet<PeerId>>>,
+}
+
+impl Verified {
+    pub(crate) fn contains(&self, peer: &PeerId) -> bool {
+        !self.enforced
+            || self
+                .peers
+                .read()
+                .expect("verified peers lock to not be poisoned")
+                .contains(peer)
+    }
+
+    fn set(&self, peer: PeerId, verified: bool) {
+        let mut peers = self
+            .peers
+            .write()
+            .expect("verified peers lock to not be poisoned");
+        if verified {
+            peers.insert(peer);
+        } else {
+            peers.remove(&peer);
+        }
+    }
+}
+
+/// Requests of peers not [`Verified`] yet, held until they are or until the
+/// verification timeout passes.
+pub(crate) struct Parked<T> {
+    verified: Verified,
+    requests: VecDeque<(PeerId, Instant, T)>,
+    timer: Pin<Box<Sleep>>,
+}
+
+impl<T> Parked<T> {
+    pub(crate) fn new(verified: Verified) -> Self {
+        Self {
+            verified,
+            requests: Default::default(),
+            timer: Box::pin(time::sleep(Duration::ZERO)),
+        }
+    }
+
+    /// Parks the request of `peer`, unless the peer is verified already in which case
+    /// the request is returned.
+    pub(crate) fn park(&mut self, peer: PeerId, request: T) -> Option<T> {
+        if self.verified.contains(&peer) {
+            return Some(request);
+        }
+
+        debug!(%peer, "Holding request until the peer is identified");
+        self.requests
+            .push_back((peer, Instant::now() + VERIFICATION_TIMEOUT, request));
+        None
+    }
+
+    /// Drops the parked requests not matching `f`, e.g. of closed connections.
+    pub(crate) fn retain(&mut self, mut f: impl FnMut(&PeerId, &T) -> bool) {
+        self.requests.retain(|(peer, _, request)| f(peer, request));
+    }
+
+    /// Releases a request once its peer is verified, with `true`, or once the
+    /// verification timed out, with `false`.
+    pub(crate) fn poll(&mut self, cx: &mut Context<'_>) -> Poll<(PeerId, T, bool)> {
+        if let Some(index) = self
+            .requests
+            .iter()
+            .position(|(peer, _, _)| self.verified.contains(peer))
+        {
+            let (peer, _, request) = self.requests.remove(index).expect("index to be valid");
+            return Poll::Ready((peer, request, true));
+        }
+
+        // Requests are parked for the same time, so the oldest one times out first.
+        let Some((_, deadline, _)) = self.requests.front() else {
+            return Poll::Pending;
+        };
+
+        let deadline = time::Instant::from_std(*deadline);
+        if self.timer.deadline() != deadline {
+            self.timer.as_mut().reset(deadline);
+        }
+        if self.timer.poll_unpin(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        let (peer, _, request) = self.requests.pop_front().expect("request to be parked");
+        warn!(%peer, "Refusing request of a peer not identified in time");
+        Poll::Ready((peer, request, false))
+    }
+}
+
+/// Enforces the [`Policy`] on identified peers.
+///
+/// Peers found incompatible are disconnected, which also drops their relay reservations
+/// and circuits, and their inbound connections are denied for a while so that they are
+/// not served relay and rendezvous requests before being identified again.
+///
+/// Relay and rendezvous requests are only served once the peer making them was
+/// identified as compatible, see [`Verified`] and [`Parked`], as peers may make them
+/// before identifying themselves.
+pub struct Behaviour {
+    policy: Policy,
+    verified: Verified,
+    incompatible: HashMap<PeerId, (Incompatible, Instant)>,
+    pending_disconnects: VecDeque<PeerId>,
+}
+
+impl Behaviour {
+    pub fn new(policy: Policy) -> Self {
+        Self {
+            verified: Verified {
+                enforced: !policy.is_empty(),
+                peers: Default::default(),
+            },
+            policy,
+            incompatible: Default::default(),
+            pending_disconnects: Default::default(),
+        }
+    }
+
+    /// Peers identified as compatible, while connected.
+    pub(crate) fn verified(&self) -> Verified {
+        self.verified.clone()
+    }
+
+    /// Rate limiter denying relay reservations and circuits of peers not identified as
+    /// compatible.
+    pub fn relay_filter(&self) -> Box<dyn relay::RateLimiter> {
+        Box::new(RelayFilter(self.verified.clone()))
+    }
+
+    /// Checks the versions of an identified peer, disconnecting it if incompatible.
+    pub fn on_identify_info(&mut self, peer_id: PeerId, info: &identify::Info) {
+        match self
+            .policy
+            .check(&info.protocol_version, &info.agent_version)
+        {
+            Ok(()) => {
+                self.verified.set(peer_id, true);
+                self.incompatible.remove(&peer_id);
+            }
+            Err(reason) => {
+                self.verified.set(peer_id, false);
+                warn!(%peer_id, %reason, "Disconnecting peer with incompatible version");
+                self.incompatible.insert(
+                    peer_id,
+                    (reason, Instant::now() + INCOMPATIBLE_PEER_BACKOFF),
+                );
+                self.pending_disconnects.push_back(peer_id);
+            }
+        }
+    }
+
+    /// Reason the peer was found incompatible, unless the backoff has passed.
+    fn incompatible(&mut self, peer_id: &PeerId) -> Option<Incompatible> {
+        let now = Instant::now();
+        self.incompatible.retain(|_, (_, until)| *until > now);
+
+        self.incompatible
+            .get(peer_id)
+            .map(|(reason, _)| reason.clone())
+    }
+}
+
+impl NetworkBehaviour for Behaviour {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Infallible;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        _: ConnectionId,
+        peer: PeerId,
+        _: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        if let Some(reason) = self.incompatible(&peer) {
+            debug!(%peer, %remote_addr, %reason, "Denied inbound connection");
+            return Err(ConnectionDenied::new(Denied(reason)));
+        }
+
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        _: PeerId,
+        _: &Multiaddr,
+        _: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        // Peers are identified again on their next connection.
+        if let FromSwarm::ConnectionClosed(ConnectionClosed {
+            peer_id,
+            remaining_established: 0,
+            ..
+        }) = event
+        {
+            self.verified.set(peer_id, false);
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _: PeerId,
+        _: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        match event {}
+    }
+
+    fn poll(&mut self, _: &mut Context<'_>) -> Poll<ToSwarm<Infallible, THandlerInEvent<Self>>> {
+        if let Some(peer_id) = self.pending_disconnects.pop_front() {
+            return Poll::Ready(ToSwarm::CloseConnection {
+                peer_id,
+                connection: CloseConnection::All,
+            });
+        }
+
+        Poll::Pending
+    }
+}
+
+struct RelayFilter(Verified);
+
+impl relay::RateLimiter for RelayFilter {
+    fn try_next(&mut self, peer: PeerId, _: &Multiaddr, _: Instant) -> bool {
+        self.0.contains(&peer)
+    }
+}
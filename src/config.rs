@@ -0,0 +1,612 @@
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::str::FromStr;
+use std::time::Duration;
+
+use camino::Utf8PathBuf;
+use clap::Args;
+use libp2p::{autonat, identify, identity, kad, ping, relay};
+
+use crate::{circuits, classes, limits, rendezvous, token, transport};
+
+mod file;
+
+pub use file::{load, render};
+
+/// Settings of the Kademlia DHT server.
+///
+/// Defaults match the ones of [`kad::Config::default`], except for the record store limits.
+#[derive(Debug, Args)]
+pub struct KadOpt {
+    /// Time in seconds after which Kademlia records expire
+    #[clap(long = "kad-record-ttl", value_name = "SECONDS")]
+    #[clap(env = "RELAY_SERVER_KAD_RECORD_TTL", default_value = "129600")]
+    pub record_ttl: u64,
+
+    /// Time in seconds after which Kademlia provider records expire
+    #[clap(long = "kad-provider-record-ttl", value_name = "SECONDS")]
+    #[clap(env = "RELAY_SERVER_KAD_PROVIDER_RECORD_TTL", default_value = "86400")]
+    pub provider_record_ttl: u64,
+
+    /// Maximum number of Kademlia records stored
+    #[clap(long = "kad-max-records", value_name = "COUNT", default_value = "1024")]
+    #[clap(env = "RELAY_SERVER_KAD_MAX_RECORDS")]
+    pub max_records: usize,
+
+    /// Maximum size in bytes of a stored Kademlia record value
+    #[clap(long = "kad-max-value-bytes", value_name = "BYTES")]
+    #[clap(env = "RELAY_SERVER_KAD_MAX_VALUE_BYTES", default_value = "66560")]
+    pub max_value_bytes: usize,
+
+    /// Maximum number of providers stored per Kademlia key
+    #[clap(long = "kad-max-providers-per-key", value_name = "COUNT")]
+    #[clap(env = "RELAY_SERVER_KAD_MAX_PROVIDERS_PER_KEY", default_value = "20")]
+    pub max_providers_per_key: usize,
+
+    /// Maximum number of Kademlia keys provided by the boot node itself
+    #[clap(long = "kad-max-provided-keys", value_name = "COUNT")]
+    #[clap(env = "RELAY_SERVER_KAD_MAX_PROVIDED_KEYS", default_value = "1024")]
+    pub max_provided_keys: usize,
+
+    /// Time in seconds after which a Kademlia query is aborted
+    #[clap(long = "kad-query-timeout", value_name = "SECONDS")]
+    #[clap(env = "RELAY_SERVER_KAD_QUERY_TIMEOUT", default_value = "60")]
+    pub query_timeout: u64,
+
+    /// Number of peers Kademlia records are replicated to
+    #[clap(long = "kad-replication-factor", value_name = "COUNT")]
+    #[clap(env = "RELAY_SERVER_KAD_REPLICATION_FACTOR", default_value = "20")]
+    pub replication_factor: NonZeroUsize,
+
+    /// Number of concurrent requests of a Kademlia query
+    #[clap(long = "kad-parallelism", value_name = "COUNT", default_value = "3")]
+    #[clap(env = "RELAY_SERVER_KAD_PARALLELISM")]
+    pub parallelism: NonZeroUsize,
+}
+
+impl KadOpt {
+    pub fn to_config(&self) -> kad::Config {
+        let mut config = kad::Config::default();
+        config
+            .set_record_ttl(Some(Duration::from_secs(self.record_ttl)))
+            .set_provider_record_ttl(Some(Duration::from_secs(self.provider_record_ttl)))
+            .set_query_timeout(Duration::from_secs(self.query_timeout))
+            .set_replication_factor(self.replication_factor)
+            .set_parallelism(self.parallelism);
+
+        config
+    }
+
+    pub fn to_store_config(&self) -> kad::store::MemoryStoreConfig {
+        kad::store::MemoryStoreConfig {
+            max_records: self.max_records,
+            max_value_bytes: self.max_value_bytes,
+            max_providers_per_key: self.max_providers_per_key,
+            max_provided_keys: self.max_provided_keys,
+        }
+    }
+}
+
+/// Settings of AutoNAT, probing whether the boot node is publicly reachable.
+///
+/// Defaults match the ones of [`autonat::Config::default`].
+#[derive(Debug, Args)]
+pub struct AutonatOpt {
+    /// Time in seconds after which an AutoNAT dial-back is aborted
+    #[clap(
+        id = "autonat_timeout",
+        long = "autonat-timeout",
+        value_name = "SECONDS"
+    )]
+    #[clap(env = "RELAY_SERVER_AUTONAT_TIMEOUT", default_value = "30")]
+    pub timeout: u64,
+
+    /// Delay in seconds before the first AutoNAT probe
+    #[clap(long = "autonat-boot-delay", value_name = "SECONDS")]
+    #[clap(env = "RELAY_SERVER_AUTONAT_BOOT_DELAY", default_value = "15")]
+    pub boot_delay: u64,
+
+    /// Interval in seconds between AutoNAT probes once the NAT status is confident
+    #[clap(long = "autonat-refresh-interval", value_name = "SECONDS")]
+    #[clap(env = "RELAY_SERVER_AUTONAT_REFRESH_INTERVAL", default_value = "900")]
+    pub refresh_interval: u64,
+
+    /// Interval in seconds between AutoNAT probes while the NAT status is uncertain
+    #[clap(long = "autonat-retry-interval", value_name = "SECONDS")]
+    #[clap(env = "RELAY_SERVER_AUTONAT_RETRY_INTERVAL", default_value = "90")]
+    pub retry_interval: u64,
+
+    /// Number of consistent AutoNAT probes after which the NAT status is confident
+    #[clap(long = "autonat-confidence-max", value_name = "COUNT")]
+    #[clap(env = "RELAY_SERVER_AUTONAT_CONFIDENCE_MAX", default_value = "3")]
+    pub confidence_max: usize,
+
+    /// Maximum number of AutoNAT dial-backs served at once per period
+    #[clap(long = "autonat-max-dial-backs", value_name = "COUNT")]
+    #[clap(env = "RELAY_SERVER_AUTONAT_MAX_DIAL_BACKS", default_value = "30")]
+    pub max_dial_backs: usize,
+
+    /// Maximum number of AutoNAT dial-backs served per peer and period
+    #[clap(long = "autonat-max-dial-backs-per-peer", value_name = "COUNT")]
+    #[clap(
+        env = "RELAY_SERVER_AUTONAT_MAX_DIAL_BACKS_PER_PEER",
+        default_value = "3"
+    )]
+    pub max_dial_backs_per_peer: usize,
+
+    /// Period in seconds over which AutoNAT dial-backs are limited
+    #[clap(long = "autonat-dial-back-period", value_name = "SECONDS")]
+    #[clap(env = "RELAY_SERVER_AUTONAT_DIAL_BACK_PERIOD", default_value = "1")]
+    pub dial_back_period: u64,
+
+    /// Dial back private IP addresses too, e.g. for testing on a local network
+    #[clap(long = "autonat-allow-private-ips")]
+    #[clap(env = "RELAY_SERVER_AUTONAT_ALLOW_PRIVATE_IPS")]
+    pub allow_private_ips: bool,
+}
+
+impl AutonatOpt {
+    pub fn to_config(&self) -> autonat::Config {
+        autonat::Config {
+            timeout: Duration::from_secs(self.timeout),
+            boot_delay: Duration::from_secs(self.boot_delay),
+            refresh_interval: Duration::from_secs(self.refresh_interval),
+            retry_interval: Duration::from_secs(self.retry_interval),
+            confidence_max: self.confidence_max,
+            throttle_clients_global_max: self.max_dial_backs,
+            throttle_clients_peer_max: self.max_dial_backs_per_peer,
+            throttle_clients_period: Duration::from_secs(self.dial_back_period),
+            only_global_ips: !self.allow_private_ips,
+            ..Default::default()
+        }
+    }
+}
+
+/// Settings of the identify protocol.
+#[derive(Debug, Args)]
+pub struct IdentifyOpt {
+    /// Interval in seconds between identify requests to connected peers
+    #[clap(
+        id = "identify_interval",
+        long = "identify-interval",
+        value_name = "SECONDS"
+    )]
+    #[clap(env = "RELAY_SERVER_IDENTIFY_INTERVAL", default_value = "300")]
+    pub interval: u64,
+
+    /// Push identify updates to connected peers when the listen addresses change
+    #[clap(long = "identify-push-listen-addr-updates")]
+    #[clap(env = "RELAY_SERVER_IDENTIFY_PUSH_LISTEN_ADDR_UPDATES")]
+    pub push_listen_addr_updates: bool,
+
+    /// Number of peers whose identified addresses are cached
+    #[clap(long = "identify-cache-size", value_name = "COUNT")]
+    #[clap(env = "RELAY_SERVER_IDENTIFY_CACHE_SIZE", default_value = "100")]
+    pub cache_size: usize,
+}
+
+impl IdentifyOpt {
+    pub fn to_config(
+        &self,
+        protocol_version: String,
+        public_key: identity::PublicKey,
+    ) -> identify::Config {
+        identify::Config::new(protocol_version, public_key)
+            .with_interval(Duration::from_secs(self.interval))
+            .with_push_listen_addr_updates(self.push_listen_addr_updates)
+            .with_cache_size(self.cache_size)
+    }
+}
+
+/// Settings of the ping protocol.
+#[derive(Debug, Args)]
+pub struct PingOpt {
+    /// Interval in seconds between pings of connected peers
+    #[clap(id = "ping_interval", long = "ping-interval", value_name = "SECONDS")]
+    #[clap(env = "RELAY_SERVER_PING_INTERVAL", default_value = "15")]
+    pub interval: u64,
+
+    /// Time in seconds after which a ping fails
+    #[clap(id = "ping_timeout", long = "ping-timeout", value_name = "SECONDS")]
+    #[clap(env = "RELAY_SERVER_PING_TIMEOUT", default_value = "20")]
+    pub timeout: u64,
+}
+
+impl PingOpt {
+    pub fn to_config(&self) -> ping::Config {
+        ping::Config::new()
+            .with_interval(Duration::from_secs(self.interval))
+            .with_timeout(Duration::from_secs(self.timeout))
+    }
+}
+
+/// Resource limits of the circuit relay v2 server.
+///
+/// Defaults match the ones of [`relay::Config::default`].
+#[derive(Debug, Args)]
+pub struct RelayOpt {
+    /// Maximum number of active relay reservations
+    #[clap(long = "relay-max-reservations", value_name = "COUNT")]
+    #[clap(env = "RELAY_SERVER_RELAY_MAX_RESERVATIONS", default_value = "128")]
+    pub max_reservations: usize,
+
+    /// Maximum number of active relay reservations per peer
+    #[clap(long = "relay-max-reservations-per-peer", value_name = "COUNT")]
+    #[clap(
+        env = "RELAY_SERVER_RELAY_MAX_RESERVATIONS_PER_PEER",
+        default_value = "4"
+    )]
+    pub max_reservations_per_peer: usize,
+
+    /// Time in seconds after which a relay reservation has to be renewed
+    #[clap(long = "relay-reservation-duration", value_name = "SECONDS")]
+    #[clap(
+        env = "RELAY_SERVER_RELAY_RESERVATION_DURATION",
+        default_value = "3600"
+    )]
+    pub reservation_duration: u64,
+
+    /// Maximum number of active relayed circuits
+    #[clap(long = "relay-max-circuits", value_name = "COUNT")]
+    #[clap(env = "RELAY_SERVER_RELAY_MAX_CIRCUITS", default_value = "16")]
+    pub max_circuits: usize,
+
+    /// Maximum number of active relayed circuits per peer
+    #[clap(long = "relay-max-circuits-per-peer", value_name = "COUNT")]
+    #[clap(env = "RELAY_SERVER_RELAY_MAX_CIRCUITS_PER_PEER", default_value = "4")]
+    pub max_circuits_per_peer: usize,
+
+    /// Time in seconds after which a relayed circuit is closed
+    #[clap(long = "relay-max-circuit-duration", value_name = "SECONDS")]
+    #[clap(env = "RELAY_SERVER_RELAY_MAX_CIRCUIT_DURATION", default_value = "120")]
+    pub max_circuit_duration: u64,
+
+    /// Number of bytes in each direction after which a relayed circuit is closed
+    #[clap(long = "relay-max-circuit-bytes", value_name = "BYTES")]
+    #[clap(env = "RELAY_SERVER_RELAY_MAX_CIRCUIT_BYTES", default_value = "131072")]
+    pub max_circuit_bytes: u64,
+
+    /// Rate limit of reservation requests per peer, as LIMIT:SECONDS or "none"
+    #[clap(long = "relay-reservation-rate-per-peer", value_name = "LIMIT:SECONDS")]
+    #[clap(
+        env = "RELAY_SERVER_RELAY_RESERVATION_RATE_PER_PEER",
+        default_value = "30:120"
+    )]
+    pub reservation_rate_per_peer: RateLimit,
+
+    /// Rate limit of reservation requests per IP address, as LIMIT:SECONDS or "none"
+    #[clap(long = "relay-reservation-rate-per-ip", value_name = "LIMIT:SECONDS")]
+    #[clap(
+        env = "RELAY_SERVER_RELAY_RESERVATION_RATE_PER_IP",
+        default_value = "60:60"
+    )]
+    pub reservation_rate_per_ip: RateLimit,
+
+    /// Rate limit of circuit requests per source peer, as LIMIT:SECONDS or "none"
+    #[clap(long = "relay-circuit-rate-per-peer", value_name = "LIMIT:SECONDS")]
+    #[clap(
+        env = "RELAY_SERVER_RELAY_CIRCUIT_RATE_PER_PEER",
+        default_value = "30:120"
+    )]
+    pub circuit_rate_per_peer: RateLimit,
+
+    /// Rate limit of circuit requests per source IP address, as LIMIT:SECONDS or "none"
+    #[clap(long = "relay-circuit-rate-per-ip", value_name = "LIMIT:SECONDS")]
+    #[clap(
+        env = "RELAY_SERVER_RELAY_CIRCUIT_RATE_PER_IP",
+        default_value = "60:60"
+    )]
+    pub circuit_rate_per_ip: RateLimit,
+
+    /// Hex encoded public key of an operator trusted to issue relay tokens, restricting
+    /// relay reservations to peers presenting a valid token
+    #[clap(long = "relay-token-issuer", value_name = "PUBLIC_KEY")]
+    #[clap(env = "RELAY_SERVER_RELAY_TOKEN_ISSUERS", value_delimiter = ',')]
+    pub token_issuers: Vec<token::Issuer>,
+
+    /// Only relay circuits between peers registered under a shared rendezvous namespace
+    #[clap(long = "relay-require-shared-namespace")]
+    #[clap(env = "RELAY_SERVER_RELAY_REQUIRE_SHARED_NAMESPACE")]
+    pub require_shared_namespace: bool,
+
+    /// Pair of rendezvous namespace patterns whose peers are relayed as if they shared a
+    /// namespace, with --relay-require-shared-namespace
+    #[clap(long = "relay-link-namespaces", value_name = "PATTERN=PATTERN")]
+    #[clap(env = "RELAY_SERVER_RELAY_LINKED_NAMESPACES", value_delimiter = ',')]
+    pub linked_namespaces: Vec<circuits::Link>,
+
+    /// TOML file of peer classes served with their own relay limits, the options above
+    /// then being the limits of anonymous peers
+    #[clap(long = "relay-classes", value_name = "FILE")]
+    #[clap(env = "RELAY_SERVER_RELAY_CLASSES")]
+    pub classes: Option<Utf8PathBuf>,
+}
+
+impl RelayOpt {
+    pub fn to_config(&self) -> relay::Config {
+        let mut config = relay::Config {
+            max_reservations: self.max_reservations,
+            max_reservations_per_peer: self.max_reservations_per_peer,
+            reservation_duration: Duration::from_secs(self.reservation_duration),
+            reservation_rate_limiters: vec![],
+            max_circuits: self.max_circuits,
+            max_circuits_per_peer: self.max_circuits_per_peer,
+            max_circuit_duration: Duration::from_secs(self.max_circuit_duration),
+            max_circuit_bytes: self.max_circuit_bytes,
+            circuit_src_rate_limiters: vec![],
+        };
+
+        // The rate limits then only apply to anonymous peers.
+        if self.classes.is_some() {
+            return config;
+        }
+
+        if let RateLimit::Limited { limit, interval } = self.reservation_rate_per_peer {
+            config = config.reservation_rate_per_peer(limit, interval);
+        }
+        if let RateLimit::Limited { limit, interval } = self.reservation_rate_per_ip {
+            config = config.reservation_rate_per_ip(limit, interval);
+        }
+        if let RateLimit::Limited { limit, interval } = self.circuit_rate_per_peer {
+            config = config.circuit_src_per_peer(limit, interval);
+        }
+        if let RateLimit::Limited { limit, interval } = self.circuit_rate_per_ip {
+            config = config.circuit_src_per_ip(limit, interval);
+        }
+
+        config
+    }
+
+    /// Relay limits of peers belonging to no class.
+    pub fn to_limits(&self) -> classes::Limits {
+        classes::Limits {
+            max_reservations: self.max_reservations,
+            max_circuits_per_peer: self.max_circuits_per_peer,
+            max_circuit_duration: Duration::from_secs(self.max_circuit_duration),
+            max_circuit_bytes: self.max_circuit_bytes,
+            reservation_rate_per_peer: self.reservation_rate_per_peer,
+            reservation_rate_per_ip: self.reservation_rate_per_ip,
+            circuit_rate_per_peer: self.circuit_rate_per_peer,
+            circuit_rate_per_ip: self.circuit_rate_per_ip,
+        }
+    }
+
+    pub fn to_circuit_policy(&self) -> circuits::Policy {
+        if !self.require_shared_namespace {
+            return circuits::Policy::Open;
+        }
+
+        circuits::Policy::SharedNamespace {
+            links: self.linked_namespaces.clone(),
+        }
+    }
+}
+
+/// Connection limits of the boot node.
+#[derive(Debug, Args)]
+pub struct ConnectionLimitsOpt {
+    /// Maximum number of inbound connections being established
+    #[clap(long, value_name = "COUNT")]
+    #[clap(env = "RELAY_SERVER_MAX_PENDING_INCOMING", default_value = "256")]
+    pub max_pending_incoming: usize,
+
+    /// Maximum number of outbound connections being established
+    #[clap(long, value_name = "COUNT")]
+    #[clap(env = "RELAY_SERVER_MAX_PENDING_OUTGOING", default_value = "256")]
+    pub max_pending_outgoing: usize,
+
+    /// Maximum number of established inbound connections
+    #[clap(long, value_name = "COUNT")]
+    #[clap(env = "RELAY_SERVER_MAX_INCOMING", default_value = "4096")]
+    pub max_incoming: usize,
+
+    /// Maximum number of established outbound connections
+    #[clap(long, value_name = "COUNT")]
+    #[clap(env = "RELAY_SERVER_MAX_OUTGOING", default_value = "1024")]
+    pub max_outgoing: usize,
+
+    /// Maximum number of established connections per peer
+    #[clap(long, value_name = "COUNT")]
+    #[clap(env = "RELAY_SERVER_MAX_CONNECTIONS_PER_PEER", default_value = "8")]
+    pub max_connections_per_peer: usize,
+
+    /// Maximum number of pending and established inbound connections per IP address
+    #[clap(long, value_name = "COUNT")]
+    #[clap(env = "RELAY_SERVER_MAX_INCOMING_PER_IP", default_value = "32")]
+    pub max_incoming_per_ip: usize,
+}
+
+impl ConnectionLimitsOpt {
+    pub fn to_limits(&self) -> limits::Limits {
+        limits::Limits {
+            max_pending_incoming: self.max_pending_incoming,
+            max_pending_outgoing: self.max_pending_outgoing,
+            max_established_incoming: self.max_incoming,
+            max_established_outgoing: self.max_outgoing,
+            max_established_per_peer: self.max_connections_per_peer,
+            max_incoming_per_ip: self.max_incoming_per_ip,
+        }
+    }
+}
+
+/// Policy of the rendezvous server.
+#[derive(Debug, Args)]
+pub struct RendezvousOpt {
+    /// Namespace pattern peers may register in, e.g. /calimero/*/** (default: any namespace)
+    #[clap(long = "rendezvous-namespace", value_name = "PATTERN")]
+    #[clap(env = "RELAY_SERVER_RENDEZVOUS_NAMESPACES", value_delimiter = ',')]
+    pub namespaces: Vec<rendezvous::NamespacePattern>,
+
+    /// Maximum number of rendezvous registrations per namespace
+    #[clap(
+        long = "rendezvous-max-registrations-per-namespace",
+        value_name = "COUNT"
+    )]
+    #[clap(
+        env = "RELAY_SERVER_RENDEZVOUS_MAX_REGISTRATIONS_PER_NAMESPACE",
+        default_value = "1024"
+    )]
+    pub max_registrations_per_namespace: usize,
+
+    /// Maximum number of namespaces a peer can be registered in
+    #[clap(long = "rendezvous-max-registrations-per-peer", value_name = "COUNT")]
+    #[clap(
+        env = "RELAY_SERVER_RENDEZVOUS_MAX_REGISTRATIONS_PER_PEER",
+        default_value = "16"
+    )]
+    pub max_registrations_per_peer: usize,
+
+    /// Minimum TTL in seconds of rendezvous registrations
+    #[clap(long = "rendezvous-min-ttl", value_name = "SECONDS")]
+    #[clap(env = "RELAY_SERVER_RENDEZVOUS_MIN_TTL", default_value = "7200")]
+    pub min_ttl: u64,
+
+    /// Maximum TTL in seconds of rendezvous registrations
+    #[clap(long = "rendezvous-max-ttl", value_name = "SECONDS")]
+    #[clap(env = "RELAY_SERVER_RENDEZVOUS_MAX_TTL", default_value = "259200")]
+    pub max_ttl: u64,
+}
+
+impl RendezvousOpt {
+    pub fn to_config(&self) -> eyre::Result<rendezvous::Config> {
+        if self.min_ttl > self.max_ttl {
+            eyre::bail!(
+                "Rendezvous minimum TTL {} exceeds the maximum TTL {}",
+                self.min_ttl,
+                self.max_ttl
+            );
+        }
+
+        Ok(rendezvous::Config {
+            min_ttl: self.min_ttl,
+            max_ttl: self.max_ttl,
+            namespaces: self.namespaces.clone(),
+            max_registrations_per_namespace: self.max_registrations_per_namespace,
+            max_registrations_per_peer: self.max_registrations_per_peer,
+        })
+    }
+}
+
+/// Optional listeners for transports reachable from browsers, and the key of a private
+/// network.
+///
+/// Each browser transport is only available if boot-node was built with the cargo
+/// feature of the same name. None can be used in a private network. WebTransport is
+/// not supported, its flag only exists to fail clearly.
+#[derive(Debug, Args)]
+pub struct TransportOpt {
+    /// Port to accept WebSocket connections on, on all interfaces
+    #[clap(long, value_name = "PORT")]
+    #[clap(env = "RELAY_SERVER_WEBSOCKET_PORT")]
+    pub websocket_port: Option<u16>,
+
+    /// Port to accept TLS secured WebSocket connections on, on all interfaces
+    #[clap(long, value_name = "PORT")]
+    #[clap(env = "RELAY_SERVER_WEBSOCKET_TLS_PORT")]
+    #[clap(requires_all = ["websocket_tls_cert", "websocket_tls_key"])]
+    pub websocket_tls_port: Option<u16>,
+
+    /// PEM file with the certificate chain for secure WebSocket connections
+    #[clap(long, value_name = "FILE")]
+    #[clap(env = "RELAY_SERVER_WEBSOCKET_TLS_CERT")]
+    pub websocket_tls_cert: Option<Utf8PathBuf>,
+
+    /// PEM file with the private key of the certificate for secure WebSocket connections
+    #[clap(long, value_name = "FILE")]
+    #[clap(env = "RELAY_SERVER_WEBSOCKET_TLS_KEY")]
+    pub websocket_tls_key: Option<Utf8PathBuf>,
+
+    /// UDP port to accept WebRTC-direct connections on, on all interfaces
+    #[clap(long, value_name = "PORT")]
+    #[clap(env = "RELAY_SERVER_WEBRTC_PORT")]
+    pub webrtc_port: Option<u16>,
+
+    /// Not supported yet, setting it fails at startup: rust-libp2p only implements
+    /// WebTransport for browsers, which can use WebRTC-direct or WebSocket instead
+    #[clap(long, value_name = "PORT")]
+    #[clap(env = "RELAY_SERVER_WEBTRANSPORT_PORT")]
+    pub webtransport_port: Option<u16>,
+
+    /// Swarm key file making the boot node part of a private network, reachable over TCP
+    /// only by peers with the same key. It holds the lines /key/swarm/psk/1.0.0/, /base16/
+    /// and 64 hex digits of the key
+    #[clap(long, value_name = "FILE")]
+    #[clap(env = "RELAY_SERVER_SWARM_KEY")]
+    pub swarm_key: Option<Utf8PathBuf>,
+}
+
+impl TransportOpt {
+    pub fn to_config(&self) -> transport::Config {
+        transport::Config {
+            websocket_port: self.websocket_port,
+            websocket_tls_port: self.websocket_tls_port,
+            websocket_tls_cert: self.websocket_tls_cert.clone(),
+            websocket_tls_key: self.websocket_tls_key.clone(),
+            webrtc_port: self.webrtc_port,
+            webtransport_port: self.webtransport_port,
+            swarm_key: self.swarm_key.clone(),
+        }
+    }
+}
+
+/// Token bucket rate limit as used by the relay: up to `limit` requests in a burst,
+/// with one more request allowed every `interval`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateLimit {
+    Unlimited,
+    Limited {
+        limit: NonZeroU32,
+        interval: Duration,
+    },
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("none") {
+            return Ok(RateLimit::Unlimited);
+        }
+
+        let (limit, interval) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected LIMIT:SECONDS or \"none\", got {s:?}"))?;
+
+        let limit = limit
+            .parse::<NonZeroU32>()
+            .map_err(|err| format!("invalid limit {limit:?}: {err}"))?;
+        let interval = interval
+            .parse::<u64>()
+            .map_err(|err| format!("invalid interval {interval:?}: {err}"))?;
+
+        if interval == 0 {
+            return Err("interval must be at least one second".to_owned());
+        }
+
+        Ok(RateLimit::Limited {
+            limit,
+            interval: Duration::from_secs(interval),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rate_limit() {
+        assert_eq!(
+            "30:120".parse::<RateLimit>(),
+            Ok(RateLimit::Limited {
+                limit: NonZeroU32::new(30).unwrap(),
+                interval: Duration::from_secs(120),
+            })
+        );
+        assert_eq!("none".parse::<RateLimit>(), Ok(RateLimit::Unlimited));
+
+        assert!("30".parse::<RateLimit>().is_err());
+        assert!("0:60".parse::<RateLimit>().is_err());
+        assert!("30:0".parse::<RateLimit>().is_err());
+        assert!("thirty:60".parse::<RateLimit>().is_err());
+    }
+}
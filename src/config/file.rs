@@ -0,0 +1,259 @@
+use std::ffi::OsString;
+
+use camino::Utf8PathBuf;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use eyre::{OptionExt, WrapErr};
+
+/// Sections of the configuration file, with the clap group of the flattened options
+/// they hold and the prefix of those options' flags.
+///
+/// Options outside of any section are top-level keys, named like their flag.
+const SECTIONS: &[(&str, &str, &str)] = &[
+    ("kad", "KadOpt", "kad-"),
+    ("autonat", "AutonatOpt", "autonat-"),
+    ("identify", "IdentifyOpt", "identify-"),
+    ("ping", "PingOpt", "ping-"),
+    ("connection-limits", "ConnectionLimitsOpt", ""),
+    ("relay", "RelayOpt", "relay-"),
+    ("rendezvous", "RendezvousOpt", "rendezvous-"),
+    ("transport", "TransportOpt", ""),
+];
+
+/// Options not settable from the configuration file.
+const IGNORED: &[&str] = &["config", "help", "version"];
+
+/// Adds the options of the TOML file given with `--config` as defaults of `cmd`.
+///
+/// Values from the file thereby take precedence over the built-in defaults only, and
+/// are overridden by environment variables and command line flags.
+pub fn load(cmd: Command, args: &[OsString]) -> eyre::Result<Command> {
+    let Some(path) = cmd
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(args)
+        .ok()
+        .and_then(|matches| matches.get_one::<Utf8PathBuf>("config").cloned())
+    else {
+        return Ok(cmd);
+    };
+
+    let contents =
+        std::fs::read_to_string(&path).wrap_err_with(|| format!("Failed to read {path}"))?;
+    let table = contents
+        .parse::<toml::Table>()
+        .wrap_err_with(|| format!("Failed to parse {path}"))?;
+
+    apply(cmd, table).wrap_err_with(|| format!("Invalid configuration file {path}"))
+}
+
+fn apply(mut cmd: Command, table: toml::Table) -> eyre::Result<Command> {
+    for (key, value) in table {
+        if let Some((_, group, prefix)) = SECTIONS.iter().find(|(name, ..)| *name == key) {
+            let toml::Value::Table(section) = value else {
+                eyre::bail!("`{key}` must be a table");
+            };
+
+            for (name, value) in section {
+                let id = section_args(&cmd, group)
+                    .into_iter()
+                    .find(|arg| section_key(arg, prefix) == Some(name.as_str()))
+                    .map(|arg| arg.get_id().clone())
+                    .ok_or_eyre(format!("unknown key `{key}.{name}`"))?;
+                cmd = set_default(cmd, id, &format!("{key}.{name}"), value)?;
+            }
+        } else {
+            let id = top_level_args(&cmd)
+                .into_iter()
+                .find(|arg| arg.get_long() == Some(key.as_str()))
+                .map(|arg| arg.get_id().clone())
+                .ok_or_eyre(format!("unknown key `{key}`"))?;
+            cmd = set_default(cmd, id, &key, value)?;
+        }
+    }
+
+    Ok(cmd)
+}
+
+fn set_default(cmd: Command, id: clap::Id, key: &str, value: toml::Value) -> eyre::Result<Command> {
+    let values = match value {
+        toml::Value::Array(values) => values
+            .into_iter()
+            .map(|value| to_string(key, value))
+            .collect::<eyre::Result<Vec<_>>>()?,
+        value => vec![to_string(key, value)?],
+    };
+
+    Ok(cmd.mut_arg(id, |arg| arg.default_values(values).required(false)))
+}
+
+fn to_string(key: &str, value: toml::Value) -> eyre::Result<String> {
+    match value {
+        toml::Value::String(value) => Ok(value),
+        toml::Value::Integer(value) => Ok(value.to_string()),
+        toml::Value::Float(value) => Ok(value.to_string()),
+        toml::Value::Boolean(value) => Ok(value.to_string()),
+        toml::Value::Datetime(value) => Ok(value.to_string()),
+        toml::Value::Array(_) | toml::Value::Table(_) => {
+            eyre::bail!("`{key}` must be a string, number, boolean or array of those")
+        }
+    }
+}
+
+/// Renders the effective options of `matches` as a configuration file, loadable again
+/// with `--config`.
+pub fn render(cmd: &Command, matches: &ArgMatches) -> String {
+    let mut out = String::new();
+
+    for arg in top_level_args(cmd) {
+        if let Some(key) = arg.get_long() {
+            render_arg(&mut out, arg, key, matches);
+        }
+    }
+
+    for (name, group, prefix) in SECTIONS {
+        out.push_str(&format!("\n[{name}]\n"));
+        for arg in section_args(cmd, group) {
+            if let Some(key) = section_key(arg, prefix) {
+                render_arg(&mut out, arg, key, matches);
+            }
+        }
+    }
+
+    out
+}
+
+fn render_arg(out: &mut String, arg: &Arg, key: &str, matches: &ArgMatches) {
+    if let Some(help) = arg.get_help() {
+        out.push_str(&format!("# {help}\n"));
+    }
+
+    let Some(values) = matches.get_raw(arg.get_id().as_str()) else {
+        out.push_str(&format!("# {key} =\n"));
+        return;
+    };
+
+    let mut values = values.map(|value| {
+        let value = value.to_string_lossy();
+        match arg.get_action() {
+            ArgAction::SetTrue | ArgAction::SetFalse => value.parse().map_or_else(
+                |_| toml::Value::String(value.to_string()),
+                toml::Value::Boolean,
+            ),
+            _ => value.parse().map_or_else(
+                |_| toml::Value::String(value.to_string()),
+                toml::Value::Integer,
+            ),
+        }
+    });
+
+    let value = match arg.get_action() {
+        ArgAction::Append => toml::Value::Array(values.collect()),
+        _ => values.next().unwrap_or(toml::Value::Array(vec![])),
+    };
+
+    out.push_str(&format!("{key} = {value}\n"));
+}
+
+fn section_args<'a>(cmd: &'a Command, group: &str) -> Vec<&'a Arg> {
+    let ids = cmd
+        .get_groups()
+        .find(|g| g.get_id() == group)
+        .map(|g| g.get_args().collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    cmd.get_arguments()
+        .filter(|arg| ids.contains(&arg.get_id()))
+        .collect()
+}
+
+fn top_level_args(cmd: &Command) -> Vec<&Arg> {
+    let grouped: Vec<_> = SECTIONS
+        .iter()
+        .flat_map(|(_, group, _)| section_args(cmd, group))
+        .map(Arg::get_id)
+        .collect();
+
+    cmd.get_arguments()
+        .filter(|arg| !grouped.contains(&arg.get_id()))
+        .filter(|arg| !IGNORED.contains(&arg.get_id().as_str()))
+        .collect()
+}
+
+fn section_key<'a>(arg: &'a Arg, prefix: &str) -> Option<&'a str> {
+    arg.get_long()?.strip_prefix(prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{Args, Parser};
+
+    use super::*;
+
+    #[derive(Debug, Parser)]
+    struct Opt {
+        #[clap(long)]
+        config: Option<Utf8PathBuf>,
+
+        #[clap(long, default_value = "4001")]
+        port: u16,
+
+        #[clap(long = "listen", value_delimiter = ',')]
+        listen: Vec<String>,
+
+        #[clap(flatten)]
+        kad: KadOpt,
+    }
+
+    #[derive(Debug, Args)]
+    struct KadOpt {
+        #[clap(long = "kad-record-ttl", default_value = "60")]
+        record_ttl: u64,
+    }
+
+    fn parse(table: &str, args: &[&str]) -> eyre::Result<Opt> {
+        use clap::{CommandFactory, FromArgMatches};
+
+        let cmd = apply(Opt::command(), table.parse()?)?;
+        let matches =
+            cmd.try_get_matches_from(std::iter::once("test").chain(args.iter().copied()))?;
+
+        Ok(Opt::from_arg_matches(&matches)?)
+    }
+
+    #[test]
+    fn test_file_overridden_by_flags() {
+        let table = "port = 5000\nlisten = [\"a\", \"b\"]\n[kad]\nrecord-ttl = 120\n";
+
+        let opt = parse(table, &[]).unwrap();
+        assert_eq!(opt.port, 5000);
+        assert_eq!(opt.listen, ["a", "b"]);
+        assert_eq!(opt.kad.record_ttl, 120);
+
+        let opt = parse(table, &["--port", "6000", "--kad-record-ttl", "180"]).unwrap();
+        assert_eq!(opt.port, 6000);
+        assert_eq!(opt.kad.record_ttl, 180);
+    }
+
+    #[test]
+    fn test_reject_unknown_keys() {
+        assert!(parse("record-ttl = 120\n", &[]).is_err());
+        assert!(parse("[kad]\nport = 5000\n", &[]).is_err());
+        assert!(parse("kad = 1\n", &[]).is_err());
+        assert!(parse("config = \"other.toml\"\n", &[]).is_err());
+    }
+
+    #[test]
+    fn test_render_round_trip() {
+        use clap::CommandFactory;
+
+        let matches = Opt::command()
+            .try_get_matches_from(["test", "--port", "5000", "--listen", "a,b"])
+            .unwrap();
+        let rendered = render(&Opt::command(), &matches);
+
+        let opt = parse(&rendered, &[]).unwrap();
+        assert_eq!(opt.port, 5000);
+        assert_eq!(opt.listen, ["a", "b"]);
+        assert_eq!(opt.kad.record_ttl, 60);
+    }
+}
@@ -0,0 +1,436 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, Instant};
+
+use ipnet::IpNet;
+use libp2p::autonat::NatStatus;
+use libp2p::{Multiaddr, PeerId};
+
+/// Prefix lengths of the subnets whose peers count as a single observer, so that a
+/// single host or network cannot confirm an address with many peer ids.
+const OBSERVER_PREFIX_V4: u8 = 24;
+const OBSERVER_PREFIX_V6: u8 = 56;
+
+/// Time a promoted address is kept once it is no longer confirmed by enough observers,
+/// and during which an address AutoNAT found unreachable is not promoted again.
+const UNCONFIRMED_TTL: Duration = Duration::from_secs(1800);
+
+/// Decides which addresses observed by remote peers are advertised as external
+/// addresses of the boot node.
+///
+/// Peers report the address they see us on through identify, which is trivially
+/// spoofed. An observed address is therefore only promoted once it has been reported
+/// by peers connected from `min_confirmations` distinct subnets, and never if it is
+/// not globally routable. Addresses configured by the operator disable promotion
+/// altogether.
+///
+/// Promoted addresses are dropped again once they went unconfirmed for a while, see
+/// [`Self::expire`], or once AutoNAT found the boot node unreachable, see
+/// [`Self::on_nat_status`].
+#[derive(Debug)]
+pub struct ExternalAddrs {
+    configured: Vec<Multiaddr>,
+    min_confirmations: usize,
+    /// Subnet each connected peer is directly connected from.
+    observers: HashMap<PeerId, IpNet>,
+    /// Latest address observed by each connected peer.
+    observations: HashMap<PeerId, Multiaddr>,
+    /// Number of peers of each subnet observing each address.
+    confirmations: HashMap<Multiaddr, HashMap<IpNet, usize>>,
+    /// Promoted addresses, with the time they were last confirmed.
+    promoted: HashMap<Multiaddr, Instant>,
+    /// Addresses found unreachable by AutoNAT, with the time until which they are not
+    /// promoted again.
+    demoted: HashMap<Multiaddr, Instant>,
+}
+
+impl ExternalAddrs {
+    pub fn new(configured: Vec<Multiaddr>, min_confirmations: usize) -> Self {
+        Self {
+            configured,
+            min_confirmations: min_confirmations.max(1),
+            observers: Default::default(),
+            observations: Default::default(),
+            confirmations: Default::default(),
+            promoted: Default::default(),
+            demoted: Default::default(),
+        }
+    }
+
+    /// Adds an address configured by the operator after startup.
+    pub fn add_configured(&mut self, addr: Multiaddr) {
+        if !self.configured.contains(&addr) {
+            self.configured.push(addr);
+        }
+    }
+
+    /// Records the address a connection of `peer_id` comes from, making the peer an
+    /// observer if it is a direct IP connection.
+    pub fn on_connection_established(&mut self, peer_id: PeerId, remote_addr: &Multiaddr) {
+        if remote_addr
+            .iter()
+            .any(|protocol| protocol == multiaddr::Protocol::P2pCircuit)
+        {
+            return;
+        }
+
+        let ip = match remote_addr.iter().next() {
+            Some(multiaddr::Protocol::Ip4(ip)) => IpAddr::V4(ip),
+            Some(multiaddr::Protocol::Ip6(ip)) => IpAddr::V6(ip),
+            _ => return,
+        };
+        let prefix = match ip {
+            IpAddr::V4(_) => OBSERVER_PREFIX_V4,
+            IpAddr::V6(_) => OBSERVER_PREFIX_V6,
+        };
+        let subnet = IpNet::new(ip, prefix).expect("prefix to be valid").trunc();
+
+        // The observation of the peer counts for the subnet it comes from now.
+        let observation = self.observations.get(&peer_id).cloned();
+        self.remove_observation(&peer_id);
+        self.observers.insert(peer_id, subnet);
+        if let Some(addr) = observation {
+            self.add_observation(peer_id, addr);
+        }
+    }
+
+    /// Records the address `peer_id` observed us on, returning it once it has been
+    /// confirmed by enough observers to be added as an external address.
+    pub fn on_observed_addr(&mut self, peer_id: PeerId, addr: Multiaddr) -> Option<Multiaddr> {
+        if !self.configured.is_empty() || !is_global(&addr) {
+            return None;
+        }
+
+        if self.observations.get(&peer_id) == Some(&addr) {
+            return None;
+        }
+
+        self.remove_observation(&peer_id);
+        self.add_observation(peer_id, addr.clone());
+
+        let now = Instant::now();
+        if !self.is_confirmed(&addr)
+            || self.promoted.contains_key(&addr)
+            || self.demoted.get(&addr).is_some_and(|until| *until > now)
+        {
+            return None;
+        }
+
+        self.demoted.remove(&addr);
+        self.promoted.insert(addr.clone(), now);
+        Some(addr)
+    }
+
+    /// Forgets the observation of a peer that disconnected.
+    pub fn on_peer_disconnected(&mut self, peer_id: &PeerId) {
+        self.remove_observation(peer_id);
+        self.observers.remove(peer_id);
+    }
+
+    /// Returns the promoted addresses to remove as AutoNAT found the boot node
+    /// unreachable, or none.
+    pub fn on_nat_status(&mut self, status: &NatStatus) -> Vec<Multiaddr> {
+        match status {
+            NatStatus::Private => {
+                let until = Instant::now() + UNCONFIRMED_TTL;
+                self.promoted
+                    .drain()
+                    .map(|(addr, _)| {
+                        self.demoted.insert(addr.clone(), until);
+                        addr
+                    })
+                    .collect()
+            }
+            NatStatus::Public(_) => {
+                self.demoted.clear();
+                vec![]
+            }
+            NatStatus::Unknown => vec![],
+        }
+    }
+
+    /// Returns the promoted addresses to remove as they have not been confirmed by
+    /// enough observers for a while.
+    pub fn expire(&mut self, now: Instant) -> Vec<Multiaddr> {
+        self.demoted.retain(|_, until| *until > now);
+
+        let mut expired = vec![];
+        for (addr, confirmed_at) in &mut self.promoted {
+            if self
+                .confirmations
+                .get(addr)
+                .is_some_and(|subnets| subnets.len() >= self.min_confirmations)
+            {
+                *confirmed_at = now;
+            } else if now.saturating_duration_since(*confirmed_at) >= UNCONFIRMED_TTL {
+                expired.push(addr.clone());
+            }
+        }
+
+        for addr in &expired {
+            self.promoted.remove(addr);
+        }
+
+        expired
+    }
+
+    /// Whether an external address confirmed by other means, e.g. AutoNAT, is kept.
+    pub fn accepts_confirmed(&self, addr: &Multiaddr) -> bool {
+        self.configured.contains(addr) || is_global(addr)
+    }
+
+    fn is_confirmed(&self, addr: &Multiaddr) -> bool {
+        self.confirmations
+            .get(addr)
+            .is_some_and(|subnets| subnets.len() >= self.min_confirmations)
+    }
+
+    /// Records the observation of a peer, counting it if the peer is an observer.
+    fn add_observation(&mut self, peer_id: PeerId, addr: Multiaddr) {
+        if let Some(subnet) = self.observers.get(&peer_id) {
+            *self
+                .confirmations
+                .entry(addr.clone())
+                .or_default()
+                .entry(*subnet)
+                .or_default() += 1;
+        }
+        self.observations.insert(peer_id, addr);
+    }
+
+    fn remove_observation(&mut self, peer_id: &PeerId) {
+        let Some(addr) = self.observations.remove(peer_id) else {
+            return;
+        };
+        let Some(subnet) = self.observers.get(peer_id) else {
+            return;
+        };
+
+        if let Some(subnets) = self.confirmations.get_mut(&addr) {
+            if let Some(count) = subnets.get_mut(subnet) {
+                *count -= 1;
+                if *count == 0 {
+                    subnets.remove(subnet);
+                }
+            }
+            if subnets.is_empty() {
+                self.confirmations.remove(&addr);
+            }
+        }
+    }
+}
+
+/// Whether the address starts with a globally routable IP address.
+///
+/// DNS addresses are accepted since they cannot be classified without resolving them.
+pub fn is_global(addr: &Multiaddr) -> bool {
+    match addr.iter().next() {
+        Some(multiaddr::Protocol::Ip4(ip)) => is_global_ipv4(ip),
+        Some(multiaddr::Protocol::Ip6(ip)) => is_global_ipv6(ip),
+        Some(
+            multiaddr::Protocol::Dns(_)
+            | multiaddr::Protocol::Dns4(_)
+            | multiaddr::Protocol::Dns6(_)
+            | multiaddr::Protocol::Dnsaddr(_),
+        ) => true,
+        _ => false,
+    }
+}
+
+fn is_global_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // Shared address space of carrier-grade NATs, 100.64.0.0/10
+        || (a == 100 && (b & 0b1100_0000) == 64)
+        // Benchmarking, 198.18.0.0/15
+        || (a == 198 && (b & 0b1111_1110) == 18)
+        // Reserved, 240.0.0.0/4
+        || a >= 240)
+}
+
+fn is_global_ipv6(ip: Ipv6Addr) -> bool {
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return is_global_ipv4(ip);
+    }
+
+    let [a, b, ..] = ip.segments();
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local, fc00::/7
+        || (a & 0xfe00) == 0xfc00
+        // Link local, fe80::/10
+        || (a & 0xffc0) == 0xfe80
+        // Documentation, 2001:db8::/32
+        || (a == 0x2001 && b == 0x0db8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_global() {
+        for addr in [
+            "/ip4/1.1.1.1/tcp/4001",
+            "/ip6/2606:4700::1111/udp/4001/quic-v1",
+            "/dns4/boot.example.com/tcp/4001",
+        ] {
+            assert!(is_global(&addr.parse().unwrap()), "{addr}");
+        }
+
+        for addr in [
+            "/ip4/127.0.0.1/tcp/4001",
+            "/ip4/10.0.0.1/tcp/4001",
+            "/ip4/172.16.0.1/tcp/4001",
+            "/ip4/192.168.1.1/tcp/4001",
+            "/ip4/100.64.0.1/tcp/4001",
+            "/ip4/169.254.0.1/tcp/4001",
+            "/ip4/0.0.0.0/tcp/4001",
+            "/ip6/::1/tcp/4001",
+            "/ip6/fd00::1/tcp/4001",
+            "/ip6/fe80::1/tcp/4001",
+            "/ip6/::ffff:192.168.1.1/tcp/4001",
+            "/p2p-circuit",
+        ] {
+            assert!(!is_global(&addr.parse().unwrap()), "{addr}");
+        }
+    }
+
+    /// Returns a peer connected from `ip`.
+    fn observer(addrs: &mut ExternalAddrs, ip: &str) -> PeerId {
+        let peer_id = PeerId::random();
+        let remote_addr = format!("/ip4/{ip}/tcp/4001").parse().unwrap();
+        addrs.on_connection_established(peer_id, &remote_addr);
+        peer_id
+    }
+
+    #[test]
+    fn test_promote_after_confirmations() {
+        let mut addrs = ExternalAddrs::new(vec![], 2);
+        let addr: Multiaddr = "/ip4/1.1.1.1/tcp/4001".parse().unwrap();
+        let peer_a = observer(&mut addrs, "2.2.2.2");
+        let peer_b = observer(&mut addrs, "3.3.3.3");
+
+        assert_eq!(addrs.on_observed_addr(peer_a, addr.clone()), None);
+        // Repeated observations of the same peer do not count.
+        assert_eq!(addrs.on_observed_addr(peer_a, addr.clone()), None);
+        assert_eq!(
+            addrs.on_observed_addr(peer_b, addr.clone()),
+            Some(addr.clone())
+        );
+        // Promoted only once.
+        let peer_c = observer(&mut addrs, "4.4.4.4");
+        assert_eq!(addrs.on_observed_addr(peer_c, addr.clone()), None);
+    }
+
+    #[test]
+    fn test_count_distinct_subnets() {
+        let mut addrs = ExternalAddrs::new(vec![], 2);
+        let addr: Multiaddr = "/ip4/1.1.1.1/tcp/4001".parse().unwrap();
+
+        // Peers of the same subnet count as a single observer.
+        for ip in ["2.2.2.2", "2.2.2.3", "2.2.2.4"] {
+            let peer_id = observer(&mut addrs, ip);
+            assert_eq!(addrs.on_observed_addr(peer_id, addr.clone()), None);
+        }
+
+        // Peers without a direct IP connection are not observers.
+        let relayed = PeerId::random();
+        let remote_addr = "/ip4/5.5.5.5/tcp/4001/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN/p2p-circuit".parse().unwrap();
+        addrs.on_connection_established(relayed, &remote_addr);
+        assert_eq!(addrs.on_observed_addr(relayed, addr.clone()), None);
+        assert_eq!(addrs.on_observed_addr(PeerId::random(), addr.clone()), None);
+
+        let peer_id = observer(&mut addrs, "3.3.3.3");
+        assert_eq!(addrs.on_observed_addr(peer_id, addr.clone()), Some(addr));
+    }
+
+    #[test]
+    fn test_forget_disconnected_peers() {
+        let mut addrs = ExternalAddrs::new(vec![], 2);
+        let addr: Multiaddr = "/ip4/1.1.1.1/tcp/4001".parse().unwrap();
+        let peer_a = observer(&mut addrs, "2.2.2.2");
+        let peer_b = observer(&mut addrs, "3.3.3.3");
+
+        assert_eq!(addrs.on_observed_addr(peer_a, addr.clone()), None);
+        addrs.on_peer_disconnected(&peer_a);
+        assert_eq!(addrs.on_observed_addr(peer_b, addr.clone()), None);
+        addrs.on_connection_established(peer_a, &"/ip4/2.2.2.2/tcp/4001".parse().unwrap());
+        assert_eq!(addrs.on_observed_addr(peer_a, addr.clone()), Some(addr));
+    }
+
+    #[test]
+    fn test_expire_unconfirmed() {
+        let mut addrs = ExternalAddrs::new(vec![], 2);
+        let addr: Multiaddr = "/ip4/1.1.1.1/tcp/4001".parse().unwrap();
+        let peer_a = observer(&mut addrs, "2.2.2.2");
+        let peer_b = observer(&mut addrs, "3.3.3.3");
+
+        addrs.on_observed_addr(peer_a, addr.clone());
+        assert_eq!(
+            addrs.on_observed_addr(peer_b, addr.clone()),
+            Some(addr.clone())
+        );
+
+        // Kept while confirmed.
+        let now = Instant::now() + UNCONFIRMED_TTL;
+        assert!(addrs.expire(now).is_empty());
+
+        addrs.on_peer_disconnected(&peer_b);
+        assert!(addrs.expire(now).is_empty());
+        assert_eq!(addrs.expire(now + UNCONFIRMED_TTL), vec![addr.clone()]);
+
+        // Promoted again once confirmed again.
+        let peer_c = observer(&mut addrs, "4.4.4.4");
+        assert_eq!(addrs.on_observed_addr(peer_c, addr.clone()), Some(addr));
+    }
+
+    #[test]
+    fn test_demote_unreachable() {
+        let mut addrs = ExternalAddrs::new(vec![], 1);
+        let addr: Multiaddr = "/ip4/1.1.1.1/tcp/4001".parse().unwrap();
+        let peer_a = observer(&mut addrs, "2.2.2.2");
+
+        assert_eq!(
+            addrs.on_observed_addr(peer_a, addr.clone()),
+            Some(addr.clone())
+        );
+        assert_eq!(addrs.on_nat_status(&NatStatus::Private), vec![addr.clone()]);
+
+        // Not promoted again while AutoNAT reports the node unreachable.
+        let peer_b = observer(&mut addrs, "3.3.3.3");
+        assert_eq!(addrs.on_observed_addr(peer_b, addr.clone()), None);
+
+        assert!(addrs
+            .on_nat_status(&NatStatus::Public(addr.clone()))
+            .is_empty());
+        let peer_c = observer(&mut addrs, "4.4.4.4");
+        assert_eq!(addrs.on_observed_addr(peer_c, addr.clone()), Some(addr));
+    }
+
+    #[test]
+    fn test_reject_private_and_configured() {
+        let mut addrs = ExternalAddrs::new(vec![], 1);
+        let private: Multiaddr = "/ip4/192.168.1.1/tcp/4001".parse().unwrap();
+        assert_eq!(
+            addrs.on_observed_addr(PeerId::random(), private.clone()),
+            None
+        );
+        assert!(!addrs.accepts_confirmed(&private));
+
+        let configured: Multiaddr = "/ip4/10.0.0.1/tcp/4001".parse().unwrap();
+        let mut addrs = ExternalAddrs::new(vec![configured.clone()], 1);
+        let global: Multiaddr = "/ip4/1.1.1.1/tcp/4001".parse().unwrap();
+        assert_eq!(addrs.on_observed_addr(PeerId::random(), global), None);
+        assert!(addrs.accepts_confirmed(&configured));
+    }
+}
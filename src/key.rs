@@ -0,0 +1,140 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::{Args, ValueEnum};
+use eyre::WrapErr;
+use libp2p::identity;
+use tracing::info;
+
+#[derive(Debug, Args)]
+pub struct KeygenOpt {
+    /// The file to write the protobuf encoded private key to, must not exist yet
+    #[clap(long, value_name = "FILE")]
+    out: Utf8PathBuf,
+
+    /// The type of the generated key
+    #[clap(long = "type", value_name = "TYPE", value_enum, default_value_t)]
+    key_type: KeyType,
+}
+
+#[derive(Debug, Args)]
+pub struct InspectKeyOpt {
+    /// The file with the protobuf encoded private key
+    #[clap(value_name = "FILE")]
+    file: Utf8PathBuf,
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum KeyType {
+    #[default]
+    Ed25519,
+    Secp256k1,
+    Ecdsa,
+}
+
+impl KeyType {
+    fn generate(self) -> identity::Keypair {
+        match self {
+            KeyType::Ed25519 => identity::Keypair::generate_ed25519(),
+            KeyType::Secp256k1 => identity::Keypair::generate_secp256k1(),
+            KeyType::Ecdsa => identity::Keypair::generate_ecdsa(),
+        }
+    }
+}
+
+pub fn keygen(opt: KeygenOpt) -> eyre::Result<()> {
+    let keypair = opt.key_type.generate();
+    write(&opt.out, &keypair)?;
+    print_key(&keypair);
+
+    Ok(())
+}
+
+pub fn inspect_key(opt: InspectKeyOpt) -> eyre::Result<()> {
+    let keypair = read(&opt.file)?;
+    print_key(&keypair);
+
+    Ok(())
+}
+
+/// Reads the keypair from `path`, generating and writing a new ed25519 keypair first
+/// if `generate_if_missing` is set and the file does not exist.
+pub fn load(path: &Utf8Path, generate_if_missing: bool) -> eyre::Result<identity::Keypair> {
+    if generate_if_missing && !path.try_exists()? {
+        let keypair = KeyType::default().generate();
+        write(path, &keypair)?;
+        info!(%path, "Generated a new private key");
+        return Ok(keypair);
+    }
+
+    read(path)
+}
+
+fn read(path: &Utf8Path) -> eyre::Result<identity::Keypair> {
+    let bytes =
+        std::fs::read(path).wrap_err_with(|| format!("Failed to read private key {path}"))?;
+
+    identity::Keypair::from_protobuf_encoding(&bytes)
+        .wrap_err_with(|| format!("Failed to decode private key {path}"))
+}
+
+/// Writes the protobuf encoded keypair to a new file readable by the owner only.
+fn write(path: &Utf8Path, keypair: &identity::Keypair) -> eyre::Result<()> {
+    let bytes = keypair.to_protobuf_encoding()?;
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options
+        .open(path)
+        .wrap_err_with(|| format!("Failed to create private key {path}"))?;
+    file.write_all(&bytes)?;
+    file.sync_all()?;
+
+    Ok(())
+}
+
+fn print_key(keypair: &identity::Keypair) {
+    let public = keypair.public();
+
+    println!("Key type:   {}", public.key_type());
+    println!("Peer id:    {}", public.to_peer_id());
+    println!("Public key: {}", hex::encode(public.encode_protobuf()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_and_read_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Utf8PathBuf::try_from(dir.path().join("key")).unwrap();
+
+        for key_type in KeyType::value_variants() {
+            let keypair = key_type.generate();
+            write(&path, &keypair).unwrap();
+            assert!(write(&path, &keypair).is_err(), "existing key overwritten");
+
+            let read = read(&path).unwrap();
+            assert_eq!(read.public(), keypair.public());
+
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_load_generate_if_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Utf8PathBuf::try_from(dir.path().join("key")).unwrap();
+
+        assert!(load(&path, false).is_err());
+
+        let generated = load(&path, true).unwrap();
+        let loaded = load(&path, true).unwrap();
+        assert_eq!(generated.public(), loaded.public());
+    }
+}
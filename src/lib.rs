@@ -0,0 +1,26 @@
+//! Relay, rendezvous and Kademlia boot node of the Calimero network.
+//!
+//! The [`RelayServer`] runs the boot node inside other binaries and tests, the
+//! `boot-node` binary is a command line interface on top of it.
+
+mod access;
+mod admin;
+pub mod audit;
+pub mod circuits;
+pub mod classes;
+pub mod compat;
+pub mod config;
+mod external_addrs;
+pub mod key;
+pub mod limits;
+pub mod logging;
+mod metrics;
+pub mod rendezvous;
+mod server;
+pub mod shutdown;
+mod state;
+mod store;
+pub mod token;
+pub mod transport;
+
+pub use server::{Event, RelayServer, RelayServerBuilder, PROTOCOL_VERSION};
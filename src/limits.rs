@@ -0,0 +1,326 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::fmt;
+use std::net::IpAddr;
+use std::task::{Context, Poll};
+
+use libp2p::core::{ConnectedPoint, Endpoint};
+use libp2p::swarm::{
+    behaviour::ConnectionEstablished, dummy, ConnectionClosed, ConnectionDenied, ConnectionId,
+    DialFailure, FromSwarm, ListenFailure, NetworkBehaviour, THandler, THandlerInEvent,
+    THandlerOutEvent, ToSwarm,
+};
+use libp2p::{Multiaddr, PeerId};
+
+use crate::access::ip;
+
+/// Maximum numbers of connections of the boot node.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub max_pending_incoming: usize,
+    pub max_pending_outgoing: usize,
+    pub max_established_incoming: usize,
+    pub max_established_outgoing: usize,
+    pub max_established_per_peer: usize,
+    /// Maximum number of pending and established inbound connections from one IP address.
+    pub max_incoming_per_ip: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_pending_incoming: 256,
+            max_pending_outgoing: 256,
+            max_established_incoming: 4096,
+            max_established_outgoing: 1024,
+            max_established_per_peer: 8,
+            max_incoming_per_ip: 32,
+        }
+    }
+}
+
+/// Limit that caused a connection to be denied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Limit {
+    PendingIncoming,
+    PendingOutgoing,
+    EstablishedIncoming,
+    EstablishedOutgoing,
+    EstablishedPerPeer,
+    IncomingPerIp,
+}
+
+impl Limit {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Limit::PendingIncoming => "pending_incoming",
+            Limit::PendingOutgoing => "pending_outgoing",
+            Limit::EstablishedIncoming => "established_incoming",
+            Limit::EstablishedOutgoing => "established_outgoing",
+            Limit::EstablishedPerPeer => "established_per_peer",
+            Limit::IncomingPerIp => "incoming_per_ip",
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("connection limit {limit} of {max} exceeded")]
+pub struct Exceeded {
+    pub limit: Limit,
+    pub max: usize,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Denies connections exceeding the [`Limits`].
+///
+/// Works like [`libp2p::connection_limits`], with an additional limit per remote IP
+/// address and errors telling which limit was exceeded, so that rejections can be
+/// counted per limit.
+#[derive(Debug)]
+pub struct Behaviour {
+    limits: Limits,
+    pending_incoming: HashSet<ConnectionId>,
+    pending_outgoing: HashSet<ConnectionId>,
+    established_incoming: HashSet<ConnectionId>,
+    established_outgoing: HashSet<ConnectionId>,
+    established_per_peer: HashMap<PeerId, HashSet<ConnectionId>>,
+    /// IP address of pending and established inbound connections.
+    incoming_ips: HashMap<ConnectionId, IpAddr>,
+}
+
+impl Behaviour {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            pending_incoming: Default::default(),
+            pending_outgoing: Default::default(),
+            established_incoming: Default::default(),
+            established_outgoing: Default::default(),
+            established_per_peer: Default::default(),
+            incoming_ips: Default::default(),
+        }
+    }
+
+    fn check_per_peer(&self, peer: &PeerId) -> Result<(), ConnectionDenied> {
+        check(
+            Limit::EstablishedPerPeer,
+            self.limits.max_established_per_peer,
+            self.established_per_peer.get(peer).map_or(0, HashSet::len),
+        )
+    }
+}
+
+fn check(limit: Limit, max: usize, current: usize) -> Result<(), ConnectionDenied> {
+    if current >= max {
+        return Err(ConnectionDenied::new(Exceeded { limit, max }));
+    }
+
+    Ok(())
+}
+
+impl NetworkBehaviour for Behaviour {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Infallible;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        _: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        check(
+            Limit::PendingIncoming,
+            self.limits.max_pending_incoming,
+            self.pending_incoming.len(),
+        )?;
+
+        if let Some(ip) = ip(remote_addr) {
+            check(
+                Limit::IncomingPerIp,
+                self.limits.max_incoming_per_ip,
+                self.incoming_ips
+                    .values()
+                    .filter(|other| **other == ip)
+                    .count(),
+            )?;
+            self.incoming_ips.insert(connection_id, ip);
+        }
+
+        self.pending_incoming.insert(connection_id);
+
+        Ok(())
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        _: &Multiaddr,
+        _: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.pending_incoming.remove(&connection_id);
+
+        check(
+            Limit::EstablishedIncoming,
+            self.limits.max_established_incoming,
+            self.established_incoming.len(),
+        )?;
+        self.check_per_peer(&peer)?;
+
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        _: Option<PeerId>,
+        _: &[Multiaddr],
+        _: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        check(
+            Limit::PendingOutgoing,
+            self.limits.max_pending_outgoing,
+            self.pending_outgoing.len(),
+        )?;
+
+        self.pending_outgoing.insert(connection_id);
+
+        Ok(vec![])
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        _: &Multiaddr,
+        _: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.pending_outgoing.remove(&connection_id);
+
+        check(
+            Limit::EstablishedOutgoing,
+            self.limits.max_established_outgoing,
+            self.established_outgoing.len(),
+        )?;
+        self.check_per_peer(&peer)?;
+
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        match event {
+            FromSwarm::ConnectionEstablished(ConnectionEstablished {
+                peer_id,
+                connection_id,
+                endpoint,
+                ..
+            }) => {
+                match endpoint {
+                    ConnectedPoint::Listener { .. } => {
+                        self.established_incoming.insert(connection_id);
+                    }
+                    ConnectedPoint::Dialer { .. } => {
+                        self.established_outgoing.insert(connection_id);
+                    }
+                }
+
+                self.established_per_peer
+                    .entry(peer_id)
+                    .or_default()
+                    .insert(connection_id);
+            }
+            FromSwarm::ConnectionClosed(ConnectionClosed {
+                peer_id,
+                connection_id,
+                ..
+            }) => {
+                self.established_incoming.remove(&connection_id);
+                self.established_outgoing.remove(&connection_id);
+                self.incoming_ips.remove(&connection_id);
+
+                if let Some(connections) = self.established_per_peer.get_mut(&peer_id) {
+                    connections.remove(&connection_id);
+                    if connections.is_empty() {
+                        self.established_per_peer.remove(&peer_id);
+                    }
+                }
+            }
+            FromSwarm::DialFailure(DialFailure { connection_id, .. }) => {
+                self.pending_outgoing.remove(&connection_id);
+            }
+            FromSwarm::ListenFailure(ListenFailure { connection_id, .. }) => {
+                self.pending_incoming.remove(&connection_id);
+                self.incoming_ips.remove(&connection_id);
+            }
+            _ => {}
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _: PeerId,
+        _: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        match event {}
+    }
+
+    fn poll(&mut self, _: &mut Context<'_>) -> Poll<ToSwarm<Infallible, THandlerInEvent<Self>>> {
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::swarm::ListenError;
+
+    use super::*;
+
+    #[test]
+    fn test_limit_incoming_per_ip() {
+        let mut behaviour = Behaviour::new(Limits {
+            max_pending_incoming: 16,
+            max_pending_outgoing: 16,
+            max_established_incoming: 16,
+            max_established_outgoing: 16,
+            max_established_per_peer: 16,
+            max_incoming_per_ip: 2,
+        });
+        let local_addr: Multiaddr = "/ip4/0.0.0.0/tcp/4001".parse().unwrap();
+        let host: Multiaddr = "/ip4/1.1.1.1/tcp/50000".parse().unwrap();
+        let other_host: Multiaddr = "/ip4/2.2.2.2/tcp/50000".parse().unwrap();
+
+        let connection_ids: Vec<_> = (0..4).map(ConnectionId::new_unchecked).collect();
+        for connection_id in &connection_ids[..2] {
+            behaviour
+                .handle_pending_inbound_connection(*connection_id, &local_addr, &host)
+                .unwrap();
+        }
+
+        let denied = behaviour
+            .handle_pending_inbound_connection(connection_ids[2], &local_addr, &host)
+            .unwrap_err();
+        assert_eq!(
+            denied.downcast_ref::<Exceeded>().unwrap().limit,
+            Limit::IncomingPerIp
+        );
+        behaviour
+            .handle_pending_inbound_connection(connection_ids[2], &local_addr, &other_host)
+            .unwrap();
+
+        behaviour.on_swarm_event(FromSwarm::ListenFailure(ListenFailure {
+            local_addr: &local_addr,
+            send_back_addr: &host,
+            error: &ListenError::Aborted,
+            connection_id: connection_ids[0],
+        }));
+        behaviour
+            .handle_pending_inbound_connection(connection_ids[3], &local_addr, &host)
+            .unwrap();
+    }
+}
@@ -0,0 +1,423 @@
+use libp2p::autonat::{InboundProbeEvent, NatStatus, OutboundProbeEvent};
+use libp2p::{autonat, identify, kad, relay};
+use tracing::{debug, info, warn};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::EnvFilter;
+
+use crate::rendezvous;
+
+/// Output format of the logs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human readable lines
+    Text,
+    /// One JSON object per line, with the event fields as keys
+    Json,
+}
+
+/// Installs the global tracing subscriber, filtered by `RUST_LOG` on top of `info`.
+pub fn init(format: LogFormat) -> eyre::Result<()> {
+    let filter = EnvFilter::builder().parse(format!(
+        "info,{}",
+        std::env::var("RUST_LOG").unwrap_or_default()
+    ))?;
+
+    let registry = tracing_subscriber::registry().with(filter);
+    match format {
+        LogFormat::Text => registry.with(tracing_subscriber::fmt::layer()).init(),
+        LogFormat::Json => registry
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .flatten_event(true)
+                    .with_current_span(false),
+            )
+            .init(),
+    }
+
+    Ok(())
+}
+
+/// Logs a relay event, with the bytes relayed over the circuit it closed if any.
+#[allow(deprecated)] // Failure events are still emitted by libp2p-relay 0.17.
+pub fn relay_event(event: &relay::Event, bytes: Option<u64>) {
+    match event {
+        relay::Event::ReservationReqAccepted {
+            src_peer_id,
+            renewed,
+        } => info!(
+            event = "reservation_accepted",
+            peer_id = %src_peer_id,
+            renewed,
+            "Accepted relay reservation"
+        ),
+        relay::Event::ReservationReqAcceptFailed { src_peer_id, error } => warn!(
+            event = "reservation_accept_failed",
+            peer_id = %src_peer_id,
+            %error,
+            "Failed to accept relay reservation"
+        ),
+        relay::Event::ReservationReqDenied { src_peer_id } => info!(
+            event = "reservation_denied",
+            peer_id = %src_peer_id,
+            "Denied relay reservation"
+        ),
+        relay::Event::ReservationReqDenyFailed { src_peer_id, error } => warn!(
+            event = "reservation_deny_failed",
+            peer_id = %src_peer_id,
+            %error,
+            "Failed to deny relay reservation"
+        ),
+        relay::Event::ReservationTimedOut { src_peer_id } => info!(
+            event = "reservation_timed_out",
+            peer_id = %src_peer_id,
+            "Relay reservation timed out"
+        ),
+        relay::Event::CircuitReqDenied {
+            src_peer_id,
+            dst_peer_id,
+        } => info!(
+            event = "circuit_denied",
+            peer_id = %src_peer_id,
+            %dst_peer_id,
+            "Denied relay circuit"
+        ),
+        relay::Event::CircuitReqDenyFailed {
+            src_peer_id,
+            dst_peer_id,
+            error,
+        } => warn!(
+            event = "circuit_deny_failed",
+            peer_id = %src_peer_id,
+            %dst_peer_id,
+            %error,
+            "Failed to deny relay circuit"
+        ),
+        relay::Event::CircuitReqAccepted {
+            src_peer_id,
+            dst_peer_id,
+        } => info!(
+            event = "circuit_accepted",
+            peer_id = %src_peer_id,
+            %dst_peer_id,
+            "Accepted relay circuit"
+        ),
+        relay::Event::CircuitReqOutboundConnectFailed {
+            src_peer_id,
+            dst_peer_id,
+            error,
+        } => warn!(
+            event = "circuit_connect_failed",
+            peer_id = %src_peer_id,
+            %dst_peer_id,
+            %error,
+            "Failed to connect relay circuit to destination"
+        ),
+        relay::Event::CircuitReqAcceptFailed {
+            src_peer_id,
+            dst_peer_id,
+            error,
+        } => warn!(
+            event = "circuit_accept_failed",
+            peer_id = %src_peer_id,
+            %dst_peer_id,
+            %error,
+            "Failed to accept relay circuit"
+        ),
+        relay::Event::CircuitClosed {
+            src_peer_id,
+            dst_peer_id,
+            error,
+        } => info!(
+            event = "circuit_closed",
+            peer_id = %src_peer_id,
+            %dst_peer_id,
+            error = error.as_ref().map(tracing::field::display),
+            bytes,
+            "Closed relay circuit"
+        ),
+    }
+}
+
+pub fn rendezvous_event(event: &rendezvous::Event) {
+    let kind = event.name();
+
+    match event {
+        rendezvous::Event::DiscoverServed {
+            enquirer,
+            registrations,
+        } => info!(
+            event = kind,
+            peer_id = %enquirer,
+            num_registrations = registrations.len(),
+            "Served rendezvous discovery"
+        ),
+        rendezvous::Event::DiscoverNotServed { enquirer, error } => info!(
+            event = kind,
+            peer_id = %enquirer,
+            error_code = rendezvous::error_code(error),
+            "Refused rendezvous discovery"
+        ),
+        rendezvous::Event::PeerRegistered { peer, registration } => info!(
+            event = kind,
+            peer_id = %peer,
+            namespace = %registration.namespace,
+            ttl = registration.ttl,
+            "Registered peer at rendezvous"
+        ),
+        rendezvous::Event::PeerNotRegistered {
+            peer,
+            namespace,
+            error,
+        } => info!(
+            event = kind,
+            peer_id = %peer,
+            %namespace,
+            error_code = rendezvous::error_code(error),
+            "Refused rendezvous registration"
+        ),
+        rendezvous::Event::PeerUnregistered { peer, namespace } => info!(
+            event = kind,
+            peer_id = %peer,
+            %namespace,
+            "Unregistered peer from rendezvous"
+        ),
+        rendezvous::Event::RegistrationExpired(registration) => info!(
+            event = kind,
+            peer_id = %registration.record.peer_id(),
+            namespace = %registration.namespace,
+            "Rendezvous registration expired"
+        ),
+        rendezvous::Event::RegistrationReplicated { from, registration } => info!(
+            event = kind,
+            peer_id = %registration.record.peer_id(),
+            namespace = %registration.namespace,
+            ttl = registration.ttl,
+            federation_peer_id = %from,
+            "Replicated rendezvous registration"
+        ),
+        rendezvous::Event::ReplicaUnregistered {
+            from,
+            peer,
+            namespace,
+        } => info!(
+            event = kind,
+            peer_id = %peer,
+            %namespace,
+            federation_peer_id = %from,
+            "Unregistered replicated rendezvous registration"
+        ),
+    }
+}
+
+pub fn autonat_event(event: &autonat::Event) {
+    match event {
+        autonat::Event::InboundProbe(InboundProbeEvent::Request {
+            probe_id,
+            peer,
+            addresses,
+        }) => debug!(
+            event = "inbound_probe_request",
+            ?probe_id,
+            peer_id = %peer,
+            num_addresses = addresses.len(),
+            "Received AutoNAT probe"
+        ),
+        autonat::Event::InboundProbe(InboundProbeEvent::Response {
+            probe_id,
+            peer,
+            address,
+        }) => info!(
+            event = "inbound_probe_response",
+            ?probe_id,
+            peer_id = %peer,
+            %address,
+            "Dialed back AutoNAT probe"
+        ),
+        autonat::Event::InboundProbe(InboundProbeEvent::Error {
+            probe_id,
+            peer,
+            error,
+        }) => info!(
+            event = "inbound_probe_error",
+            ?probe_id,
+            peer_id = %peer,
+            ?error,
+            "Failed to serve AutoNAT probe"
+        ),
+        autonat::Event::OutboundProbe(OutboundProbeEvent::Request { probe_id, peer }) => debug!(
+            event = "outbound_probe_request",
+            ?probe_id,
+            peer_id = %peer,
+            "Sent AutoNAT probe"
+        ),
+        autonat::Event::OutboundProbe(OutboundProbeEvent::Response {
+            probe_id,
+            peer,
+            address,
+        }) => info!(
+            event = "outbound_probe_response",
+            ?probe_id,
+            peer_id = %peer,
+            %address,
+            "AutoNAT probe succeeded"
+        ),
+        autonat::Event::OutboundProbe(OutboundProbeEvent::Error {
+            probe_id,
+            peer,
+            error,
+        }) => info!(
+            event = "outbound_probe_error",
+            ?probe_id,
+            peer_id = peer.as_ref().map(tracing::field::display),
+            ?error,
+            "AutoNAT probe failed"
+        ),
+        autonat::Event::StatusChanged { old, new } => info!(
+            event = "nat_status_changed",
+            old = nat_status(old),
+            new = nat_status(new),
+            public_address = match new {
+                NatStatus::Public(address) => Some(tracing::field::display(address)),
+                _ => None,
+            },
+            "NAT status changed"
+        ),
+    }
+}
+
+fn nat_status(status: &NatStatus) -> &'static str {
+    match status {
+        NatStatus::Public(_) => "public",
+        NatStatus::Private => "private",
+        NatStatus::Unknown => "unknown",
+    }
+}
+
+pub fn identify_event(event: &identify::Event) {
+    match event {
+        identify::Event::Received { peer_id, info } => info!(
+            event = "received",
+            %peer_id,
+            agent_version = info.agent_version,
+            protocol_version = info.protocol_version,
+            observed_addr = %info.observed_addr,
+            num_listen_addrs = info.listen_addrs.len(),
+            num_protocols = info.protocols.len(),
+            "Identified peer"
+        ),
+        identify::Event::Sent { peer_id } => debug!(
+            event = "sent",
+            %peer_id,
+            "Sent identify info"
+        ),
+        identify::Event::Pushed { peer_id, .. } => debug!(
+            event = "pushed",
+            %peer_id,
+            "Pushed identify info"
+        ),
+        identify::Event::Error { peer_id, error } => info!(
+            event = "error",
+            %peer_id,
+            %error,
+            "Failed to identify peer"
+        ),
+    }
+}
+
+pub fn kad_event(event: &kad::Event) {
+    match event {
+        kad::Event::InboundRequest { request } => match request {
+            kad::InboundRequest::FindNode { num_closer_peers } => info!(
+                event = "find_node",
+                num_closer_peers, "Served Kademlia request"
+            ),
+            kad::InboundRequest::GetProvider {
+                num_closer_peers,
+                num_provider_peers,
+            } => info!(
+                event = "get_providers",
+                num_closer_peers, num_provider_peers, "Served Kademlia request"
+            ),
+            kad::InboundRequest::AddProvider { record } => info!(
+                event = "add_provider",
+                peer_id = record
+                    .as_ref()
+                    .map(|record| tracing::field::display(record.provider)),
+                "Served Kademlia request"
+            ),
+            kad::InboundRequest::GetRecord {
+                num_closer_peers,
+                present_locally,
+            } => info!(
+                event = "get_record",
+                num_closer_peers, present_locally, "Served Kademlia request"
+            ),
+            kad::InboundRequest::PutRecord { source, record, .. } => info!(
+                event = "put_record",
+                peer_id = %source,
+                value_bytes = record.as_ref().map(|record| record.value.len()),
+                "Served Kademlia request"
+            ),
+        },
+        kad::Event::OutboundQueryProgressed {
+            id, result, step, ..
+        } => info!(
+            event = "query_progressed",
+            query_id = ?id,
+            query = query_kind(result),
+            step = step.count,
+            last = step.last,
+            "Kademlia query progressed"
+        ),
+        kad::Event::RoutingUpdated {
+            peer,
+            is_new_peer,
+            addresses,
+            old_peer,
+            ..
+        } => info!(
+            event = "routing_updated",
+            peer_id = %peer,
+            is_new_peer,
+            num_addresses = addresses.len(),
+            evicted_peer_id = old_peer.as_ref().map(tracing::field::display),
+            "Updated Kademlia routing table"
+        ),
+        kad::Event::UnroutablePeer { peer } => info!(
+            event = "unroutable_peer",
+            peer_id = %peer,
+            "Peer is not routable"
+        ),
+        kad::Event::RoutablePeer { peer, address } => info!(
+            event = "routable_peer",
+            peer_id = %peer,
+            %address,
+            "Peer is routable"
+        ),
+        kad::Event::PendingRoutablePeer { peer, address } => info!(
+            event = "pending_routable_peer",
+            peer_id = %peer,
+            %address,
+            "Peer is pending to be routable"
+        ),
+        kad::Event::ModeChanged { new_mode } => info!(
+            event = "mode_changed",
+            mode = %new_mode,
+            "Kademlia mode changed"
+        ),
+    }
+}
+
+fn query_kind(result: &kad::QueryResult) -> &'static str {
+    match result {
+        kad::QueryResult::Bootstrap(_) => "bootstrap",
+        kad::QueryResult::GetClosestPeers(_) => "get_closest_peers",
+        kad::QueryResult::GetProviders(_) => "get_providers",
+        kad::QueryResult::StartProviding(_) => "start_providing",
+        kad::QueryResult::RepublishProvider(_) => "republish_provider",
+        kad::QueryResult::GetRecord(_) => "get_record",
+        kad::QueryResult::PutRecord(_) => "put_record",
+        kad::QueryResult::RepublishRecord(_) => "republish_record",
+    }
+}
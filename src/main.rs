@@ -1,161 +1,328 @@
-use std::net::Ipv4Addr;
-
-use clap::Parser;
-use libp2p::futures::prelude::*;
-use libp2p::swarm::{NetworkBehaviour, SwarmEvent};
-use libp2p::{
-    autonat, identify, identity, kad, ping, relay, rendezvous, Multiaddr, StreamProtocol, Swarm,
-};
-use tracing::info;
-use tracing_subscriber::prelude::*;
-use tracing_subscriber::EnvFilter;
-
-const PROTOCOL_VERSION: &str = concat!("/", env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
-const CALIMERO_KAD_PROTO_NAME: StreamProtocol = StreamProtocol::new("/calimero/kad/1.0.0");
-
-#[derive(NetworkBehaviour)]
-struct Behaviour {
-    autonat: autonat::Behaviour,
-    identify: identify::Behaviour,
-    kad: kad::Behaviour<kad::store::MemoryStore>,
-    ping: ping::Behaviour,
-    relay: relay::Behaviour,
-    rendezvous: rendezvous::server::Behaviour,
-}
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use boot_node::{audit, classes, compat, config, key, logging, shutdown, token, RelayServer};
+use clap::{CommandFactory, FromArgMatches, Parser};
+use libp2p::Multiaddr;
+use tracing::{info, warn};
 
 #[derive(Debug, Parser)]
 #[clap(name = "calimero relay")]
+#[clap(subcommand_negates_reqs = true)]
 struct Opt {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// TOML file with options, overridden by environment variables and flags
+    #[clap(long, value_name = "FILE")]
+    #[clap(env = "RELAY_SERVER_CONFIG")]
+    config: Option<camino::Utf8PathBuf>,
+
+    /// Format of the log output
+    #[clap(long, value_name = "FORMAT", default_value = "text")]
+    #[clap(env = "RELAY_SERVER_LOG_FORMAT")]
+    log_format: logging::LogFormat,
+
     /// The file with the protobuf encoded private key used to derive PeerId and sign network activity
-    #[clap(long, value_name = "PRIVATE_KEY")]
+    #[clap(long, value_name = "PRIVATE_KEY", required = true)]
     #[clap(env = "RELAY_SERVER_PRIVATE_KEY", hide_env_values = true)]
-    private_key: camino::Utf8PathBuf,
+    private_key: Option<camino::Utf8PathBuf>,
 
-    /// The port used to listen on all interfaces
+    /// Generate a new ed25519 private key if the private key file does not exist
+    #[clap(long)]
+    #[clap(env = "RELAY_SERVER_GENERATE_IF_MISSING")]
+    generate_if_missing: bool,
+
+    /// Address to listen on, replacing the default listen addresses on all interfaces
+    #[clap(long = "listen", value_name = "MULTIADDR")]
+    #[clap(env = "RELAY_SERVER_LISTEN", value_delimiter = ',')]
+    listen_addrs: Vec<Multiaddr>,
+
+    /// The port used for the default TCP and QUIC listen addresses
     #[clap(long, value_name = "PORT", default_value = "4001")]
     #[clap(env = "RELAY_SERVER_PORT", hide_env_values = true)]
     port: u16,
+
+    /// The port used for the default TCP listen addresses, overriding --port
+    #[clap(long, value_name = "PORT")]
+    #[clap(env = "RELAY_SERVER_TCP_PORT")]
+    tcp_port: Option<u16>,
+
+    /// The port used for the default QUIC listen addresses, overriding --port
+    #[clap(long, value_name = "PORT")]
+    #[clap(env = "RELAY_SERVER_QUIC_PORT")]
+    quic_port: Option<u16>,
+
+    /// Publicly reachable address of the boot node, disabling the promotion of observed addresses
+    #[clap(long = "external-address", value_name = "MULTIADDR")]
+    #[clap(env = "RELAY_SERVER_EXTERNAL_ADDRESSES", value_delimiter = ',')]
+    external_addrs: Vec<Multiaddr>,
+
+    /// Number of peers from distinct subnets that must observe an address before it is advertised
+    /// as external
+    #[clap(long, value_name = "COUNT", default_value = "3")]
+    #[clap(env = "RELAY_SERVER_EXTERNAL_ADDRESS_CONFIRMATIONS")]
+    external_address_confirmations: usize,
+
+    /// Address of another boot node to join the DHT through, including its /p2p/<PeerId> suffix
+    #[clap(long = "bootstrap-peer", value_name = "MULTIADDR")]
+    #[clap(env = "RELAY_SERVER_BOOTSTRAP_PEERS", value_delimiter = ',')]
+    bootstrap_peers: Vec<Multiaddr>,
+
+    /// Address of another boot node to replicate rendezvous registrations with, including its
+    /// /p2p/<PeerId> suffix
+    #[clap(long = "federation-peer", value_name = "MULTIADDR")]
+    #[clap(env = "RELAY_SERVER_FEDERATION_PEERS", value_delimiter = ',')]
+    federation_peers: Vec<Multiaddr>,
+
+    /// Interval in seconds between periodic Kademlia bootstraps
+    #[clap(long, value_name = "SECONDS", default_value = "300")]
+    #[clap(env = "RELAY_SERVER_BOOTSTRAP_INTERVAL")]
+    bootstrap_interval: u64,
+
+    /// Version requirement on the software of remote peers as advertised via identify, e.g.
+    /// chat-example=^0.4, disconnecting peers not meeting it. Separated by ';' in the environment
+    #[clap(long = "require-version", value_name = "NAME=REQUIREMENT")]
+    #[clap(env = "RELAY_SERVER_REQUIRE_VERSIONS", value_delimiter = ';')]
+    version_requirements: Vec<compat::Requirement>,
+
+    /// Directory for state persisted across restarts, kept in memory only if unset
+    #[clap(long, value_name = "DIR")]
+    #[clap(env = "RELAY_SERVER_DATA_DIR")]
+    data_dir: Option<camino::Utf8PathBuf>,
+
+    /// File with peer ids and IP networks allowed or denied to connect and use the relay,
+    /// reloaded on SIGHUP and when modified
+    #[clap(long, value_name = "FILE")]
+    #[clap(env = "RELAY_SERVER_ACCESS_LIST")]
+    access_list: Option<camino::Utf8PathBuf>,
+
+    /// Time in seconds to let relayed circuits drain on shutdown before closing them
+    #[clap(long, value_name = "SECONDS", default_value = "30")]
+    #[clap(env = "RELAY_SERVER_DRAIN_TIMEOUT")]
+    drain_timeout: u64,
+
+    /// JSON lines file to append an audit record of relay and rendezvous activity to
+    #[clap(long, value_name = "FILE")]
+    #[clap(env = "RELAY_SERVER_AUDIT_LOG")]
+    audit_log: Option<camino::Utf8PathBuf>,
+
+    /// Size in bytes after which the audit log is rotated
+    #[clap(long, value_name = "BYTES", default_value = "104857600")]
+    #[clap(env = "RELAY_SERVER_AUDIT_LOG_MAX_BYTES")]
+    audit_log_max_bytes: u64,
+
+    /// Age in seconds after which the audit log is rotated
+    #[clap(long, value_name = "SECONDS", default_value = "86400")]
+    #[clap(env = "RELAY_SERVER_AUDIT_LOG_MAX_AGE")]
+    audit_log_max_age: u64,
+
+    /// Address to serve Prometheus metrics on, e.g. 127.0.0.1:9090
+    #[clap(long, value_name = "ADDR")]
+    #[clap(env = "RELAY_SERVER_METRICS_LISTEN")]
+    metrics_listen: Option<SocketAddr>,
+
+    /// Address to serve the read-only admin JSON API on, e.g. 127.0.0.1:9091
+    #[clap(long, value_name = "ADDR")]
+    #[clap(env = "RELAY_SERVER_ADMIN_LISTEN")]
+    admin_listen: Option<SocketAddr>,
+
+    #[clap(flatten)]
+    kad: config::KadOpt,
+
+    #[clap(flatten)]
+    autonat: config::AutonatOpt,
+
+    #[clap(flatten)]
+    identify: config::IdentifyOpt,
+
+    #[clap(flatten)]
+    ping: config::PingOpt,
+
+    #[clap(flatten)]
+    connection_limits: config::ConnectionLimitsOpt,
+
+    #[clap(flatten)]
+    relay: config::RelayOpt,
+
+    #[clap(flatten)]
+    rendezvous: config::RendezvousOpt,
+
+    #[clap(flatten)]
+    transport: config::TransportOpt,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Generate a new private key
+    Keygen(key::KeygenOpt),
+    /// Print the peer id and public key of a private key
+    InspectKey(key::InspectKeyOpt),
+    /// Issue a token granting a peer relay reservations, printed hex encoded
+    IssueToken(token::IssueTokenOpt),
+    /// Print the effective configuration, merged from the file, environment and flags
+    PrintConfig,
 }
 
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
-    tracing_subscriber::registry()
-        .with(EnvFilter::builder().parse(format!(
-            "info,{}",
-            std::env::var("RUST_LOG").unwrap_or_default()
-        ))?)
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
-    let opt = Opt::parse();
-
-    let mut bytes = std::fs::read(opt.private_key)?;
-    let keypair = identity::Keypair::from_protobuf_encoding(&mut bytes)?;
+    let cmd = config::load(Opt::command(), &std::env::args_os().collect::<Vec<_>>())?;
+    let matches = cmd.get_matches();
+    let opt = Opt::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+
+    logging::init(opt.log_format)?;
+
+    match opt.command {
+        Some(Command::Keygen(opt)) => return key::keygen(opt),
+        Some(Command::InspectKey(opt)) => return key::inspect_key(opt),
+        Some(Command::IssueToken(opt)) => return token::issue(opt),
+        Some(Command::PrintConfig) => {
+            print!("{}", config::render(&Opt::command(), &matches));
+            return Ok(());
+        }
+        None => {}
+    }
+
+    let private_key = opt
+        .private_key
+        .expect("required unless a subcommand is given");
+    let keypair = key::load(&private_key, opt.generate_if_missing)?;
     let peer_id = keypair.public().to_peer_id();
 
-    info!("Peer id: {:?}", peer_id);
-
-    let mut swarm = libp2p::SwarmBuilder::with_existing_identity(keypair)
-        .with_tokio()
-        .with_tcp(
-            Default::default(),
-            (libp2p::tls::Config::new, libp2p::noise::Config::new),
-            libp2p::yamux::Config::default,
-        )?
-        .with_quic()
-        .with_behaviour(|keypair| Behaviour {
-            autonat: autonat::Behaviour::new(peer_id.clone(), Default::default()),
-            identify: identify::Behaviour::new(identify::Config::new(
-                PROTOCOL_VERSION.to_owned(),
-                keypair.public(),
-            )),
-            kad: {
-                let mut kademlia_config = kad::Config::default();
-                kademlia_config.set_protocol_names(vec![CALIMERO_KAD_PROTO_NAME]);
-                // Instantly remove records and provider records.
-                // TODO: figure out what to do with these values, ref: https://github.com/libp2p/rust-libp2p/blob/1aa016e1c7e3976748a726eab37af44d1c5b7a6e/misc/server/src/behaviour.rs#L38
-                kademlia_config.set_record_ttl(Some(std::time::Duration::from_secs(0)));
-                kademlia_config.set_provider_record_ttl(Some(std::time::Duration::from_secs(0)));
-
-                let mut kademlia = kad::Behaviour::with_config(
-                    peer_id,
-                    kad::store::MemoryStore::new(peer_id),
-                    kademlia_config,
-                );
-                kademlia.set_mode(Some(kad::Mode::Server));
-                // TODO: implement support for adding bootstrap peers
-                // for peer in opt.bootstrap_peers.iter() {
-                //     kademlia.add_address(&PeerId::from_str(peer).unwrap(), bootaddr.clone());
-                // }
-                // if let Err(err) = kademlia.bootstrap() {
-                //     warn!(%err, "Failed to bootstrap Kademlia");
-                // };
-
-                kademlia
+    info!(%peer_id, "Starting boot node");
+
+    let identify = opt
+        .identify
+        .to_config(boot_node::PROTOCOL_VERSION.to_owned(), keypair.public());
+
+    let mut builder = RelayServer::builder(keypair)
+        .external_address_confirmations(opt.external_address_confirmations)
+        .bootstrap_interval(Duration::from_secs(opt.bootstrap_interval))
+        .kad(opt.kad.to_config())
+        .kad_store(opt.kad.to_store_config())
+        .autonat(opt.autonat.to_config())
+        .identify(identify)
+        .ping(opt.ping.to_config())
+        .relay(opt.relay.to_config())
+        .circuit_policy(opt.relay.to_circuit_policy())
+        .rendezvous(opt.rendezvous.to_config()?)
+        .version_policy(compat::Policy::new(opt.version_requirements))
+        .connection_limits(opt.connection_limits.to_limits())
+        .transport(opt.transport.to_config())
+        .drain_timeout(Duration::from_secs(opt.drain_timeout));
+
+    if opt.listen_addrs.is_empty() {
+        // QUIC cannot be used in a private network.
+        let quic_port = opt
+            .transport
+            .swarm_key
+            .is_none()
+            .then(|| opt.quic_port.unwrap_or(opt.port));
+        for addr in default_listen_addrs(opt.tcp_port.unwrap_or(opt.port), quic_port) {
+            builder = builder.listen_on(addr);
+        }
+    }
+    for addr in opt.listen_addrs {
+        builder = builder.listen_on(addr);
+    }
+    for addr in opt.external_addrs {
+        builder = builder.external_address(addr);
+    }
+    for addr in opt.bootstrap_peers {
+        builder = builder.bootstrap_peer(addr);
+    }
+    for addr in opt.federation_peers {
+        builder = builder.federation_peer(addr);
+    }
+    if let Some(path) = &opt.relay.classes {
+        builder = builder.relay_classes(classes::Config::load(path, opt.relay.to_limits())?);
+    }
+    for issuer in opt.relay.token_issuers {
+        builder = builder.relay_token_issuer(issuer.0);
+    }
+    if let Some(dir) = opt.data_dir {
+        builder = builder.data_dir(dir);
+    }
+    if let Some(path) = opt.access_list {
+        builder = builder.access_list(path);
+    }
+    if let Some(path) = opt.audit_log {
+        builder = builder.audit_log(
+            path,
+            audit::Rotation {
+                max_bytes: opt.audit_log_max_bytes,
+                max_age: Duration::from_secs(opt.audit_log_max_age),
             },
-            ping: ping::Behaviour::new(ping::Config::new()),
-            rendezvous: rendezvous::server::Behaviour::new(rendezvous::server::Config::default()),
-            relay: relay::Behaviour::new(keypair.public().to_peer_id(), Default::default()),
-        })?
-        .build();
-
-    // Listen on all interfaces
-    let listen_addr_tcp = Multiaddr::empty()
-        .with(multiaddr::Protocol::from(Ipv4Addr::UNSPECIFIED))
-        .with(multiaddr::Protocol::Tcp(opt.port));
-    swarm.listen_on(listen_addr_tcp)?;
-
-    let listen_addr_quic = Multiaddr::empty()
-        .with(multiaddr::Protocol::from(Ipv4Addr::UNSPECIFIED))
-        .with(multiaddr::Protocol::Udp(opt.port))
-        .with(multiaddr::Protocol::QuicV1);
-    swarm.listen_on(listen_addr_quic)?;
+        );
+    }
+    if let Some(addr) = opt.metrics_listen {
+        builder = builder.metrics_listen(addr);
+    }
+    if let Some(addr) = opt.admin_listen {
+        builder = builder.admin_listen(addr);
+    }
+
+    let mut server = builder.start()?;
+    let mut reload = ReloadSignal::new();
+    let mut signal = std::pin::pin!(shutdown::signal());
 
     loop {
-        let event = swarm.next().await;
-        handle_swarm_event(&mut swarm, event.expect("Swarm stream to be infinite.")).await;
+        tokio::select! {
+            _ = reload.recv() => server.reload_access_list(),
+            signal = &mut signal => {
+                info!(signal, "Shutting down");
+                break;
+            }
+            result = server.stopped() => return result,
+        }
     }
-}
 
-async fn handle_swarm_event(swarm: &mut Swarm<Behaviour>, event: SwarmEvent<BehaviourEvent>) {
-    match event {
-        SwarmEvent::Behaviour(event) => {
-            handle_swarm_behaviour_event(swarm, event).await;
-        }
-        SwarmEvent::NewListenAddr { address, .. } => {
-            info!("Listening on {address:?}");
+    server.request_shutdown();
+
+    tokio::select! {
+        result = server.stopped() => return result,
+        signal = shutdown::signal() => {
+            warn!(signal, "Received another termination signal, closing remaining circuits");
+            server.request_shutdown();
         }
-        _ => {}
     }
+
+    server.stopped().await
 }
 
-async fn handle_swarm_behaviour_event(swarm: &mut Swarm<Behaviour>, event: BehaviourEvent) {
-    match event {
-        BehaviourEvent::Autonat(event) => {
-            info!("AutoNat event: {event:?}");
-        }
-        BehaviourEvent::Identify(event) => {
-            info!("Identify event: {event:?}");
-            match event {
-                identify::Event::Received {
-                    info: identify::Info { observed_addr, .. },
-                    ..
-                } => {
-                    info!("Adding external address: {observed_addr:?}");
-                    swarm.add_external_address(observed_addr);
-                }
-                _ => {}
-            }
-        }
-        BehaviourEvent::Kad(event) => {
-            info!("Kad event: {event:?}");
-        }
-        BehaviourEvent::Relay(event) => {
-            info!("Relay event: {event:?}");
+/// TCP and QUIC listen addresses, without the IP address.
+fn default_listen_addrs(tcp_port: u16, quic_port: Option<u16>) -> Vec<Multiaddr> {
+    let tcp = Multiaddr::empty().with(multiaddr::Protocol::Tcp(tcp_port));
+    let quic = quic_port.map(|port| {
+        Multiaddr::empty()
+            .with(multiaddr::Protocol::Udp(port))
+            .with(multiaddr::Protocol::QuicV1)
+    });
+
+    [tcp].into_iter().chain(quic).collect()
+}
+
+/// Stream of SIGHUP signals, requesting a reload of the access list.
+struct ReloadSignal {
+    #[cfg(unix)]
+    hangup: tokio::signal::unix::Signal,
+}
+
+impl ReloadSignal {
+    fn new() -> Self {
+        Self {
+            #[cfg(unix)]
+            hangup: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+                .expect("SIGHUP handler to be installable"),
         }
-        BehaviourEvent::Rendezvous(event) => {
-            info!("Rendezvous event: {event:?}");
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if self.hangup.recv().await.is_some() {
+            return;
         }
-        _ => {}
+
+        std::future::pending().await
     }
 }
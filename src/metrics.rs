@@ -0,0 +1,315 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use libp2p::metrics::{Metrics as Libp2pMetrics, Recorder};
+use libp2p::rendezvous::Namespace;
+use libp2p::swarm::{DialError, ListenError, SwarmEvent};
+use libp2p::{identify, kad, ping, relay};
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::registry::Registry;
+use tracing::{error, info};
+
+use crate::state::NodeState;
+use crate::{limits, rendezvous};
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Prometheus metrics of the boot node.
+///
+/// Wraps the libp2p swarm and protocol metrics and adds gauges for the state held by
+/// the relay and rendezvous servers, which are updated from the [`NodeState`] and, for
+/// the namespaces changed by each rendezvous event, from the rendezvous
+/// [`Registrations`](rendezvous::Registrations).
+pub struct Metrics {
+    libp2p: Libp2pMetrics,
+    relay_reservations: Gauge,
+    relay_circuits: Gauge,
+    relay_circuit_bytes: Counter,
+    rendezvous_registrations: Family<NamespaceLabels, Gauge>,
+    rendezvous_events: Family<RendezvousEventLabels, Counter>,
+    connection_limit_rejections: Family<ConnectionLimitLabels, Counter>,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct NamespaceLabels {
+    namespace: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RendezvousEventLabels {
+    event: &'static str,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ConnectionLimitLabels {
+    limit: &'static str,
+}
+
+impl Metrics {
+    pub fn new(registry: &mut Registry) -> Self {
+        let libp2p = Libp2pMetrics::new(registry);
+
+        let registry = registry.sub_registry_with_prefix("boot_node");
+
+        let relay_reservations = Gauge::default();
+        registry.register(
+            "relay_reservations",
+            "Number of peers holding an active relay reservation",
+            relay_reservations.clone(),
+        );
+
+        let relay_circuits = Gauge::default();
+        registry.register(
+            "relay_circuits",
+            "Number of active relayed circuits",
+            relay_circuits.clone(),
+        );
+
+        let relay_circuit_bytes = Counter::default();
+        registry.register(
+            "relay_circuit_bytes",
+            "Bytes relayed over circuits in both directions",
+            relay_circuit_bytes.clone(),
+        );
+
+        let rendezvous_registrations = Family::default();
+        registry.register(
+            "rendezvous_registrations",
+            "Number of active rendezvous registrations per namespace",
+            rendezvous_registrations.clone(),
+        );
+
+        let rendezvous_events = Family::default();
+        registry.register(
+            "rendezvous_events",
+            "Events emitted by the rendezvous server",
+            rendezvous_events.clone(),
+        );
+
+        let connection_limit_rejections = Family::default();
+        registry.register(
+            "connection_limit_rejections",
+            "Connections denied for exceeding a connection limit",
+            connection_limit_rejections.clone(),
+        );
+
+        Self {
+            libp2p,
+            relay_reservations,
+            relay_circuits,
+            relay_circuit_bytes,
+            rendezvous_registrations,
+            rendezvous_events,
+            connection_limit_rejections,
+        }
+    }
+
+    pub fn record_swarm_event<TBvEv>(&mut self, event: &SwarmEvent<TBvEv>) {
+        self.libp2p.record(event);
+
+        let cause = match event {
+            SwarmEvent::IncomingConnectionError {
+                error: ListenError::Denied { cause },
+                ..
+            }
+            | SwarmEvent::OutgoingConnectionError {
+                error: DialError::Denied { cause },
+                ..
+            } => cause,
+            _ => return,
+        };
+
+        if let Some(exceeded) = cause.downcast_ref::<limits::Exceeded>() {
+            self.connection_limit_rejections
+                .get_or_create(&ConnectionLimitLabels {
+                    limit: exceeded.limit.as_str(),
+                })
+                .inc();
+        }
+    }
+
+    pub fn record_identify_event(&mut self, event: &identify::Event) {
+        self.libp2p.record(event);
+    }
+
+    pub fn record_kad_event(&mut self, event: &kad::Event) {
+        self.libp2p.record(event);
+    }
+
+    pub fn record_ping_event(&mut self, event: &ping::Event) {
+        self.libp2p.record(event);
+    }
+
+    pub fn record_relay_event(&mut self, event: &relay::Event) {
+        self.libp2p.record(event);
+    }
+
+    /// Counter of the bytes relayed over circuits, incremented by the relay as it
+    /// relays them.
+    pub fn relay_circuit_bytes(&self) -> Counter {
+        self.relay_circuit_bytes.clone()
+    }
+
+    /// Records a rendezvous event, updating the registration gauge of the namespace it
+    /// changed from the `registrations` it left.
+    pub fn record_rendezvous_event(
+        &mut self,
+        event: &rendezvous::Event,
+        registrations: &rendezvous::Registrations,
+    ) {
+        self.rendezvous_events
+            .get_or_create(&RendezvousEventLabels {
+                event: event.name(),
+            })
+            .inc();
+
+        let namespace = match event {
+            rendezvous::Event::PeerRegistered { registration, .. }
+            | rendezvous::Event::RegistrationExpired(registration)
+            | rendezvous::Event::RegistrationReplicated { registration, .. } => {
+                &registration.namespace
+            }
+            rendezvous::Event::PeerUnregistered { namespace, .. }
+            | rendezvous::Event::ReplicaUnregistered { namespace, .. } => namespace,
+            _ => return,
+        };
+        self.observe_namespace(namespace, registrations);
+    }
+
+    /// Sets the registration gauges of all namespaces, for the registrations restored
+    /// on startup.
+    pub fn observe_registrations(&mut self, registrations: &rendezvous::Registrations) {
+        self.rendezvous_registrations.clear();
+        for entry in registrations.iter() {
+            self.rendezvous_registrations
+                .get_or_create(&NamespaceLabels {
+                    namespace: entry.registration.namespace.to_string(),
+                })
+                .inc();
+        }
+    }
+
+    /// Updates the gauges mirroring the relay server state.
+    pub fn observe_state(&mut self, state: &NodeState) {
+        self.relay_reservations.set(state.num_reservations() as i64);
+        self.relay_circuits.set(state.num_circuits() as i64);
+    }
+
+    fn observe_namespace(
+        &mut self,
+        namespace: &Namespace,
+        registrations: &rendezvous::Registrations,
+    ) {
+        let labels = NamespaceLabels {
+            namespace: namespace.to_string(),
+        };
+
+        // Abandoned namespaces are removed so that they do not linger in the output.
+        match registrations.namespace_len(namespace) {
+            0 => {
+                self.rendezvous_registrations.remove(&labels);
+            }
+            len => {
+                self.rendezvous_registrations
+                    .get_or_create(&labels)
+                    .set(len as i64);
+            }
+        }
+    }
+}
+
+/// Serves the metrics of the registry on `/metrics` until the process exits.
+pub async fn serve(addr: SocketAddr, registry: Registry) -> eyre::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+
+    info!(
+        "Serving metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+
+    let app = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .with_state(Arc::new(registry));
+
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
+
+async fn metrics_handler(State(registry): State<Arc<Registry>>) -> impl IntoResponse {
+    let mut buffer = String::new();
+
+    match prometheus_client::encoding::text::encode(&mut buffer, &registry) {
+        Ok(()) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, CONTENT_TYPE)],
+            buffer,
+        ),
+        Err(err) => {
+            error!(%err, "Failed to encode metrics");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                [(header::CONTENT_TYPE, "text/plain")],
+                String::new(),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use libp2p::core::PeerRecord;
+    use libp2p::identity::Keypair;
+    use libp2p::rendezvous::Registration;
+
+    use super::*;
+
+    fn encode(registry: &Registry) -> String {
+        let mut buffer = String::new();
+        prometheus_client::encoding::text::encode(&mut buffer, registry).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_namespace_registrations() {
+        let mut registry = Registry::default();
+        let mut metrics = Metrics::new(&mut registry);
+        let mut registrations = rendezvous::Registrations::default();
+
+        let keypair = Keypair::generate_ed25519();
+        let registration = Registration {
+            namespace: Namespace::from_static("ns"),
+            record: PeerRecord::new(&keypair, vec!["/ip4/1.1.1.1/tcp/4001".parse().unwrap()])
+                .unwrap(),
+            ttl: 60,
+        };
+        let peer = keypair.public().to_peer_id();
+
+        registrations.add(rendezvous::Entry::new(registration.clone(), Instant::now()));
+        metrics.record_rendezvous_event(
+            &rendezvous::Event::PeerRegistered { peer, registration },
+            &registrations,
+        );
+        assert!(
+            encode(&registry).contains("boot_node_rendezvous_registrations{namespace=\"ns\"} 1\n")
+        );
+
+        let namespace = Namespace::from_static("ns");
+        registrations.remove(&namespace, &peer);
+        metrics.record_rendezvous_event(
+            &rendezvous::Event::PeerUnregistered { peer, namespace },
+            &registrations,
+        );
+        assert!(!encode(&registry).contains("namespace=\"ns\""));
+    }
+}
@@ -0,0 +1,965 @@
+use std::iter;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use camino::Utf8PathBuf;
+use eyre::WrapErr;
+use libp2p::core::{Endpoint, PeerRecord, SignedEnvelope};
+use libp2p::futures::FutureExt;
+use libp2p::rendezvous::{Cookie, ErrorCode, Namespace, Registration, Ttl};
+use libp2p::swarm::{
+    ConnectionClosed, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler,
+    THandlerInEvent, THandlerOutEvent, ToSwarm,
+};
+use libp2p::{request_response, Multiaddr, PeerId, StreamProtocol};
+use serde::{Deserialize, Serialize};
+use tokio::{task, time};
+use tracing::{debug, info, warn};
+
+use crate::compat;
+use crate::store::{
+    from_unix_millis, system_time_from_unix_millis, system_time_to_unix_millis, to_unix_millis,
+    write_bytes,
+};
+
+mod codec;
+pub mod federation;
+mod policy;
+mod registrations;
+
+pub use policy::NamespacePattern;
+pub use registrations::{Entry, Registrations};
+
+const PROTOCOL_NAME: StreamProtocol = StreamProtocol::new("/rendezvous/1.0.0");
+
+const SNAPSHOT_FILE: &str = "registrations.json";
+
+/// Policy of the rendezvous server.
+///
+/// Registrations violating it are rejected with the matching rendezvous error code:
+/// `E_INVALID_NAMESPACE` for namespaces not matching any of `namespaces`,
+/// `E_INVALID_TTL` for TTLs outside of `min_ttl..=max_ttl` and `E_UNAVAILABLE` once a
+/// quota is exhausted.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub min_ttl: Ttl,
+    pub max_ttl: Ttl,
+    /// Namespaces peers may register in and discover, any if empty.
+    pub namespaces: Vec<NamespacePattern>,
+    pub max_registrations_per_namespace: usize,
+    pub max_registrations_per_peer: usize,
+}
+
+impl Config {
+    fn allows(&self, namespace: &Namespace) -> bool {
+        self.namespaces.is_empty()
+            || self
+                .namespaces
+                .iter()
+                .any(|pattern| pattern.matches(namespace))
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            min_ttl: libp2p::rendezvous::MIN_TTL,
+            max_ttl: libp2p::rendezvous::MAX_TTL,
+            namespaces: vec![],
+            max_registrations_per_namespace: 1024,
+            max_registrations_per_peer: 16,
+        }
+    }
+}
+
+/// Events of the rendezvous server, mirroring the ones of [`libp2p::rendezvous::server`].
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum Event {
+    DiscoverServed {
+        enquirer: PeerId,
+        registrations: Vec<Registration>,
+    },
+    DiscoverNotServed {
+        enquirer: PeerId,
+        error: ErrorCode,
+    },
+    PeerRegistered {
+        peer: PeerId,
+        registration: Registration,
+    },
+    PeerNotRegistered {
+        peer: PeerId,
+        namespace: Namespace,
+        error: ErrorCode,
+    },
+    PeerUnregistered {
+        peer: PeerId,
+        namespace: Namespace,
+    },
+    RegistrationExpired(Registration),
+    /// Registration replicated from the federation peer `from`.
+    RegistrationReplicated {
+        from: PeerId,
+        registration: Registration,
+    },
+    /// Registration replicated from the federation peer `from` was unregistered there.
+    ReplicaUnregistered {
+        from: PeerId,
+        peer: PeerId,
+        namespace: Namespace,
+    },
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::DiscoverServed { .. } => "discover_served",
+            Event::DiscoverNotServed { .. } => "discover_not_served",
+            Event::PeerRegistered { .. } => "peer_registered",
+            Event::PeerNotRegistered { .. } => "peer_not_registered",
+            Event::PeerUnregistered { .. } => "peer_unregistered",
+            Event::RegistrationExpired(_) => "registration_expired",
+            Event::RegistrationReplicated { .. } => "registration_replicated",
+            Event::ReplicaUnregistered { .. } => "replica_unregistered",
+        }
+    }
+}
+
+/// Name of an error code as in the rendezvous protocol specification.
+pub fn error_code(code: &ErrorCode) -> &'static str {
+    match code {
+        ErrorCode::InvalidNamespace => "E_INVALID_NAMESPACE",
+        ErrorCode::InvalidSignedPeerRecord => "E_INVALID_SIGNED_PEER_RECORD",
+        ErrorCode::InvalidTtl => "E_INVALID_TTL",
+        ErrorCode::InvalidCookie => "E_INVALID_COOKIE",
+        ErrorCode::NotAuthorized => "E_NOT_AUTHORIZED",
+        ErrorCode::InternalError => "E_INTERNAL_ERROR",
+        ErrorCode::Unavailable => "E_UNAVAILABLE",
+    }
+}
+
+/// Rendezvous server compatible with [`libp2p::rendezvous::server::Behaviour`], whose
+/// registrations are kept private and thus cannot be persisted.
+///
+/// Registrations are snapshotted to a JSON file with their signed peer records and
+/// expiration times, and restored with their remaining TTL when the server is opened.
+///
+/// Registration and discovery requests are held until the requesting peer was
+/// identified as compatible with the version policy, and refused with
+/// `E_NOT_AUTHORIZED` if it is not identified in time.
+pub struct Behaviour {
+    inner: request_response::Behaviour<codec::Codec>,
+    config: Config,
+    registrations: Registrations,
+    expiration_timer: Pin<Box<time::Sleep>>,
+    snapshot_path: Option<Utf8PathBuf>,
+    /// Whether the registrations changed since the last snapshot.
+    dirty: bool,
+    /// Snapshot being written to disk off the event loop.
+    writing: Option<task::JoinHandle<eyre::Result<()>>>,
+    /// Requests of peers yet to be identified.
+    parked: compat::Parked<(
+        codec::Request,
+        request_response::ResponseChannel<codec::Response>,
+    )>,
+}
+
+impl Behaviour {
+    /// Creates the server, restoring the registrations snapshotted to `dir`.
+    ///
+    /// Without a directory registrations are kept in memory only. Requests are served
+    /// once their peer is `verified`.
+    pub(crate) fn open(
+        config: Config,
+        dir: Option<Utf8PathBuf>,
+        verified: compat::Verified,
+    ) -> eyre::Result<Self> {
+        let mut behaviour = Self {
+            inner: request_response::Behaviour::with_codec(
+                codec::Codec,
+                iter::once((PROTOCOL_NAME, request_response::ProtocolSupport::Inbound)),
+                request_response::Config::default(),
+            ),
+            config,
+            registrations: Registrations::default(),
+            expiration_timer: Box::pin(time::sleep(time::Duration::ZERO)),
+            snapshot_path: None,
+            dirty: false,
+            writing: None,
+            parked: compat::Parked::new(verified),
+        };
+
+        let Some(dir) = dir else {
+            return Ok(behaviour);
+        };
+
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(SNAPSHOT_FILE);
+
+        match std::fs::read(&path) {
+            Ok(bytes) => {
+                let snapshot: Snapshot = serde_json::from_slice(&bytes)?;
+                let num_registrations = snapshot.registrations.len();
+                for stored in snapshot.registrations {
+                    match stored.restore() {
+                        Ok(Some(entry))
+                            if behaviour.config.allows(&entry.registration.namespace) =>
+                        {
+                            behaviour.registrations.add(entry)
+                        }
+                        Ok(_) => {}
+                        Err(err) => warn!(%err, "Skipping unreadable persisted registration"),
+                    }
+                }
+
+                // Registrations in namespaces no longer allowed are dropped as well.
+                info!(
+                    %path,
+                    num_registrations = behaviour.registrations.len(),
+                    num_dropped = num_registrations - behaviour.registrations.len(),
+                    "Loaded persisted rendezvous registrations"
+                );
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        behaviour.snapshot_path = Some(path);
+
+        Ok(behaviour)
+    }
+
+    pub fn registrations(&self) -> &Registrations {
+        &self.registrations
+    }
+
+    /// Writes the registrations to disk if they changed since the last snapshot,
+    /// blocking until they are synced.
+    pub fn snapshot(&mut self) -> eyre::Result<()> {
+        match self.take_snapshot()? {
+            Some(snapshot) => snapshot.write(),
+            None => Ok(()),
+        }
+    }
+
+    /// Serializes the registrations if they changed since the last snapshot and writes
+    /// them on the blocking thread pool, so that the event loop does not wait for the
+    /// disk. Skipped while the previous snapshot is still being written.
+    pub fn snapshot_in_background(&mut self) {
+        if let Some(writing) = self.writing.take() {
+            if !writing.is_finished() {
+                self.writing = Some(writing);
+                return;
+            }
+            if let Some(result) = writing.now_or_never() {
+                self.on_written(result);
+            }
+        }
+
+        match self.take_snapshot() {
+            Ok(Some(snapshot)) => {
+                self.writing = Some(task::spawn_blocking(move || snapshot.write()));
+            }
+            Ok(None) => {}
+            Err(err) => warn!(?err, "Failed to snapshot rendezvous registrations"),
+        }
+    }
+
+    /// Waits for the snapshot being written in the background, then writes the
+    /// registrations if they changed since, see [`Self::snapshot`].
+    pub async fn flush(&mut self) -> eyre::Result<()> {
+        if let Some(writing) = self.writing.take() {
+            self.on_written(writing.await);
+        }
+
+        self.snapshot()
+    }
+
+    fn on_written(&mut self, result: Result<eyre::Result<()>, task::JoinError>) {
+        let err = match result {
+            Ok(Ok(())) => return,
+            Ok(Err(err)) => err,
+            Err(err) => err.into(),
+        };
+
+        warn!(?err, "Failed to snapshot rendezvous registrations");
+        // Written again with the next snapshot.
+        self.dirty = true;
+    }
+
+    /// Serializes the registrations if they changed since the last snapshot.
+    fn take_snapshot(&mut self) -> eyre::Result<Option<PendingSnapshot>> {
+        let Some(path) = &self.snapshot_path else {
+            return Ok(None);
+        };
+
+        if !self.dirty {
+            return Ok(None);
+        }
+
+        let snapshot = Snapshot {
+            registrations: self
+                .registrations
+                .iter()
+                .map(StoredRegistration::new)
+                .collect(),
+        };
+
+        let pending = PendingSnapshot {
+            path: path.clone(),
+            bytes: serde_json::to_vec(&snapshot)?,
+            num_registrations: snapshot.registrations.len(),
+        };
+        self.dirty = false;
+
+        Ok(Some(pending))
+    }
+
+    /// Adds a registration replicated from the federation peer `from`, returning it
+    /// if it was added or changed.
+    ///
+    /// The registration is subject to the same policy as the ones made with this boot
+    /// node, with TTLs above the maximum capped instead of rejected since the TTL is the
+    /// remaining lifetime. Existing registrations are only replaced by a newer peer
+    /// record, so that replicas do not override registrations renewed in the meantime.
+    pub fn replicate(
+        &mut self,
+        from: PeerId,
+        registration: Registration,
+    ) -> Result<Option<Registration>, ErrorCode> {
+        if !self.config.allows(&registration.namespace) {
+            return Err(ErrorCode::InvalidNamespace);
+        }
+
+        if registration.ttl == 0 {
+            return Err(ErrorCode::InvalidTtl);
+        }
+
+        let peer_id = registration.record.peer_id();
+        match self.registrations.get(&registration.namespace, &peer_id) {
+            Some(entry) if entry.registration.record.seq() >= registration.record.seq() => {
+                return Ok(None);
+            }
+            Some(_) => {}
+            None if self.registrations.namespace_len(&registration.namespace)
+                >= self.config.max_registrations_per_namespace
+                || self.registrations.peer_len(&peer_id)
+                    >= self.config.max_registrations_per_peer =>
+            {
+                return Err(ErrorCode::Unavailable);
+            }
+            None => {}
+        }
+
+        let registration = Registration {
+            ttl: registration.ttl.min(self.config.max_ttl),
+            ..registration
+        };
+
+        self.registrations.add(Entry {
+            replicated_from: Some(from),
+            ..Entry::new(registration.clone(), Instant::now())
+        });
+        self.dirty = true;
+
+        Ok(Some(registration))
+    }
+
+    /// Removes the registration of `peer_id` in `namespace` if it was replicated from
+    /// the federation peer `from`.
+    pub fn unreplicate(&mut self, from: PeerId, namespace: &Namespace, peer_id: &PeerId) -> bool {
+        match self.registrations.get(namespace, peer_id) {
+            Some(entry) if entry.replicated_from == Some(from) => {}
+            _ => return false,
+        }
+
+        self.registrations.remove(namespace, peer_id);
+        self.dirty = true;
+
+        true
+    }
+
+    fn handle_request(
+        &mut self,
+        peer_id: PeerId,
+        request: codec::Request,
+    ) -> (Event, Option<codec::Response>) {
+        match request {
+            codec::Request::Register {
+                namespace,
+                record,
+                ttl,
+            } => match self.register(peer_id, namespace.clone(), record, ttl) {
+                Ok(registration) => (
+                    Event::PeerRegistered {
+                        peer: peer_id,
+                        registration: registration.clone(),
+                    },
+                    Some(codec::Response::Register(Ok(registration.ttl))),
+                ),
+                Err(error) => (
+                    Event::PeerNotRegistered {
+                        peer: peer_id,
+                        namespace,
+                        error,
+                    },
+                    Some(codec::Response::Register(Err(error))),
+                ),
+            },
+            codec::Request::Unregister { namespace } => {
+                if self.registrations.remove(&namespace, &peer_id).is_some() {
+                    self.dirty = true;
+                }
+
+                // Unregistering is not acknowledged.
+                (
+                    Event::PeerUnregistered {
+                        peer: peer_id,
+                        namespace,
+                    },
+                    None,
+                )
+            }
+            codec::Request::Discover {
+                namespace,
+                cookie,
+                limit,
+            } => match self.discover(namespace, cookie, limit) {
+                Ok((registrations, cookie)) => (
+                    Event::DiscoverServed {
+                        enquirer: peer_id,
+                        registrations: registrations.clone(),
+                    },
+                    Some(codec::Response::Discover(Ok((registrations, cookie)))),
+                ),
+                Err(error) => (
+                    Event::DiscoverNotServed {
+                        enquirer: peer_id,
+                        error,
+                    },
+                    Some(codec::Response::Discover(Err(error))),
+                ),
+            },
+        }
+    }
+
+    /// Refuses the request of a peer which was not identified as compatible.
+    fn refuse_request(
+        &mut self,
+        peer_id: PeerId,
+        request: codec::Request,
+    ) -> (Event, Option<codec::Response>) {
+        let error = ErrorCode::NotAuthorized;
+        match request {
+            codec::Request::Register { namespace, .. } => (
+                Event::PeerNotRegistered {
+                    peer: peer_id,
+                    namespace,
+                    error,
+                },
+                Some(codec::Response::Register(Err(error))),
+            ),
+            codec::Request::Discover { .. } => (
+                Event::DiscoverNotServed {
+                    enquirer: peer_id,
+                    error,
+                },
+                Some(codec::Response::Discover(Err(error))),
+            ),
+            // Unregistering is never held back.
+            codec::Request::Unregister { .. } => self.handle_request(peer_id, request),
+        }
+    }
+
+    fn respond(
+        &mut self,
+        peer_id: PeerId,
+        channel: request_response::ResponseChannel<codec::Response>,
+        response: Option<codec::Response>,
+    ) {
+        if let Some(response) = response {
+            if self.inner.send_response(channel, response).is_err() {
+                debug!(%peer_id, "Rendezvous response channel closed");
+            }
+        }
+    }
+
+    fn register(
+        &mut self,
+        peer_id: PeerId,
+        namespace: Namespace,
+        record: PeerRecord,
+        ttl: Option<Ttl>,
+    ) -> Result<Registration, ErrorCode> {
+        if record.peer_id() != peer_id {
+            return Err(ErrorCode::NotAuthorized);
+        }
+
+        if !self.config.allows(&namespace) {
+            return Err(ErrorCode::InvalidNamespace);
+        }
+
+        let ttl = ttl.unwrap_or_else(|| {
+            libp2p::rendezvous::DEFAULT_TTL.clamp(self.config.min_ttl, self.config.max_ttl)
+        });
+        if !(self.config.min_ttl..=self.config.max_ttl).contains(&ttl) {
+            return Err(ErrorCode::InvalidTtl);
+        }
+
+        // Renewing a registration does not count against the quotas.
+        if !self.registrations.contains(&namespace, &peer_id)
+            && (self.registrations.namespace_len(&namespace)
+                >= self.config.max_registrations_per_namespace
+                || self.registrations.peer_len(&peer_id) >= self.config.max_registrations_per_peer)
+        {
+            return Err(ErrorCode::Unavailable);
+        }
+
+        let registration = Registration {
+            namespace,
+            record,
+            ttl,
+        };
+
+        self.registrations
+            .add(Entry::new(registration.clone(), Instant::now()));
+        self.dirty = true;
+
+        Ok(registration)
+    }
+
+    fn discover(
+        &mut self,
+        namespace: Option<Namespace>,
+        cookie: Option<Cookie>,
+        limit: Option<u64>,
+    ) -> Result<(Vec<Registration>, Cookie), ErrorCode> {
+        if namespace
+            .as_ref()
+            .is_some_and(|namespace| !self.config.allows(namespace))
+        {
+            return Err(ErrorCode::InvalidNamespace);
+        }
+
+        self.registrations
+            .discover(namespace, cookie, limit, Instant::now())
+    }
+
+    fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<Registration> {
+        loop {
+            if let Some(registration) = self.registrations.pop_expired(Instant::now()) {
+                self.dirty = true;
+                return Poll::Ready(registration);
+            }
+
+            let Some(next_expiration) = self.registrations.next_expiration() else {
+                return Poll::Pending;
+            };
+
+            let deadline = time::Instant::from_std(next_expiration);
+            if self.expiration_timer.deadline() != deadline {
+                self.expiration_timer.as_mut().reset(deadline);
+            }
+
+            if self.expiration_timer.poll_unpin(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+impl NetworkBehaviour for Behaviour {
+    type ConnectionHandler =
+        <request_response::Behaviour<codec::Codec> as NetworkBehaviour>::ConnectionHandler;
+    type ToSwarm = Event;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner.handle_established_inbound_connection(
+            connection_id,
+            peer,
+            local_addr,
+            remote_addr,
+        )
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner
+            .handle_established_outbound_connection(connection_id, peer, addr, role_override)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        if let FromSwarm::ConnectionClosed(ConnectionClosed {
+            peer_id,
+            remaining_established: 0,
+            ..
+        }) = event
+        {
+            self.parked.retain(|peer, _| *peer != peer_id);
+        }
+
+        self.inner.on_swarm_event(event);
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        self.inner
+            .on_connection_handler_event(peer_id, connection_id, event);
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<ToSwarm<Event, THandlerInEvent<Self>>> {
+        if let Poll::Ready(registration) = self.poll_expired(cx) {
+            return Poll::Ready(ToSwarm::GenerateEvent(Event::RegistrationExpired(
+                registration,
+            )));
+        }
+
+        if let Poll::Ready((peer, (request, channel), verified)) = self.parked.poll(cx) {
+            let (event, response) = if verified {
+                self.handle_request(peer, request)
+            } else {
+                self.refuse_request(peer, request)
+            };
+            self.respond(peer, channel, response);
+
+            return Poll::Ready(ToSwarm::GenerateEvent(event));
+        }
+
+        loop {
+            let event = match self.inner.poll(cx) {
+                Poll::Ready(ToSwarm::GenerateEvent(event)) => event,
+                Poll::Ready(to_swarm) => {
+                    return Poll::Ready(to_swarm.map_out(|_| unreachable!("mapped above")));
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+
+            match event {
+                request_response::Event::Message {
+                    peer,
+                    message:
+                        request_response::Message::Request {
+                            request, channel, ..
+                        },
+                } => {
+                    let (request, channel) = match request {
+                        codec::Request::Unregister { .. } => (request, channel),
+                        request => match self.parked.park(peer, (request, channel)) {
+                            Some(request) => request,
+                            None => continue,
+                        },
+                    };
+
+                    let (event, response) = self.handle_request(peer, request);
+                    self.respond(peer, channel, response);
+
+                    return Poll::Ready(ToSwarm::GenerateEvent(event));
+                }
+                request_response::Event::InboundFailure { peer, error, .. } => {
+                    debug!(%peer, %error, "Failed to serve rendezvous request");
+                }
+                request_response::Event::Message {
+                    message: request_response::Message::Response { .. },
+                    ..
+                }
+                | request_response::Event::OutboundFailure { .. }
+                | request_response::Event::ResponseSent { .. } => {}
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot {
+    registrations: Vec<StoredRegistration>,
+}
+
+/// Serialized snapshot, yet to be written to disk.
+struct PendingSnapshot {
+    path: Utf8PathBuf,
+    bytes: Vec<u8>,
+    num_registrations: usize,
+}
+
+impl PendingSnapshot {
+    fn write(self) -> eyre::Result<()> {
+        let path = &self.path;
+        write_bytes(path, &self.bytes).wrap_err_with(|| format!("Failed to write {path}"))?;
+
+        debug!(
+            %path,
+            num_registrations = self.num_registrations,
+            "Snapshotted rendezvous registrations"
+        );
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredRegistration {
+    namespace: String,
+    /// Hex encoded signed envelope of the peer record.
+    signed_peer_record: String,
+    ttl: Ttl,
+    registered_at: u64,
+    expires_at: u64,
+    /// Peer id of the federation peer the registration was replicated from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    replicated_from: Option<String>,
+}
+
+impl StoredRegistration {
+    fn new(entry: &Entry) -> Self {
+        Self {
+            namespace: entry.registration.namespace.to_string(),
+            signed_peer_record: hex::encode(
+                entry
+                    .registration
+                    .record
+                    .to_signed_envelope()
+                    .into_protobuf_encoding(),
+            ),
+            ttl: entry.registration.ttl,
+            registered_at: system_time_to_unix_millis(entry.registered_at),
+            expires_at: to_unix_millis(entry.expires_at),
+            replicated_from: entry.replicated_from.map(|peer_id| peer_id.to_string()),
+        }
+    }
+
+    /// Restores the registration, returning `None` if it has already expired.
+    fn restore(self) -> eyre::Result<Option<Entry>> {
+        let Some(expires_at) = from_unix_millis(self.expires_at) else {
+            return Ok(None);
+        };
+
+        let envelope =
+            SignedEnvelope::from_protobuf_encoding(&hex::decode(self.signed_peer_record)?)?;
+
+        Ok(Some(Entry {
+            registration: Registration {
+                namespace: Namespace::new(self.namespace)?,
+                record: PeerRecord::from_signed_envelope(envelope)?,
+                ttl: self.ttl,
+            },
+            registered_at: system_time_from_unix_millis(self.registered_at),
+            expires_at,
+            replicated_from: self
+                .replicated_from
+                .map(|peer_id| peer_id.parse())
+                .transpose()?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::identity::Keypair;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_registrations_survive_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Utf8PathBuf::try_from(dir.path().to_path_buf()).unwrap();
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = keypair.public().to_peer_id();
+        let record =
+            PeerRecord::new(&keypair, vec!["/ip4/1.1.1.1/tcp/4001".parse().unwrap()]).unwrap();
+
+        let mut behaviour =
+            Behaviour::open(Config::default(), Some(path.clone()), Default::default()).unwrap();
+        behaviour
+            .register(peer_id, Namespace::from_static("ns"), record.clone(), None)
+            .unwrap();
+        behaviour.snapshot().unwrap();
+        let registered_at = behaviour
+            .registrations()
+            .iter()
+            .next()
+            .unwrap()
+            .registered_at;
+        drop(behaviour);
+
+        let behaviour = Behaviour::open(Config::default(), Some(path), Default::default()).unwrap();
+        let entry = behaviour.registrations().iter().next().unwrap();
+        assert_eq!(entry.registration.record, record);
+        assert_eq!(entry.registration.ttl, libp2p::rendezvous::DEFAULT_TTL);
+        assert_eq!(
+            system_time_to_unix_millis(entry.registered_at),
+            system_time_to_unix_millis(registered_at)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_in_background() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Utf8PathBuf::try_from(dir.path().to_path_buf()).unwrap();
+        let namespace = Namespace::from_static("ns");
+
+        let mut behaviour =
+            Behaviour::open(Config::default(), Some(path.clone()), Default::default()).unwrap();
+        for _ in 0..2 {
+            let keypair = Keypair::generate_ed25519();
+            let record =
+                PeerRecord::new(&keypair, vec!["/ip4/1.1.1.1/tcp/4001".parse().unwrap()]).unwrap();
+            behaviour
+                .register(
+                    keypair.public().to_peer_id(),
+                    namespace.clone(),
+                    record,
+                    None,
+                )
+                .unwrap();
+            behaviour.snapshot_in_background();
+        }
+        // Waits for the first snapshot and writes the registration made since.
+        behaviour.flush().await.unwrap();
+        drop(behaviour);
+
+        let behaviour = Behaviour::open(Config::default(), Some(path), Default::default()).unwrap();
+        assert_eq!(behaviour.registrations().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_reject_registrations_violating_policy() {
+        let config = Config {
+            min_ttl: 60,
+            max_ttl: 600,
+            namespaces: vec!["/calimero/*/**".parse().unwrap()],
+            max_registrations_per_namespace: 2,
+            max_registrations_per_peer: 2,
+        };
+        let mut behaviour = Behaviour::open(config, None, Default::default()).unwrap();
+
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = keypair.public().to_peer_id();
+        let record =
+            PeerRecord::new(&keypair, vec!["/ip4/1.1.1.1/tcp/4001".parse().unwrap()]).unwrap();
+        let register = |behaviour: &mut Behaviour, namespace: &'static str, ttl| {
+            behaviour.register(
+                peer_id,
+                Namespace::from_static(namespace),
+                record.clone(),
+                ttl,
+            )
+        };
+
+        assert_eq!(
+            register(&mut behaviour, "/other/devnet", None).unwrap_err(),
+            ErrorCode::InvalidNamespace
+        );
+        assert_eq!(
+            register(&mut behaviour, "/calimero/devnet", Some(601)).unwrap_err(),
+            ErrorCode::InvalidTtl
+        );
+        // The default TTL is capped to the maximum TTL.
+        assert_eq!(
+            register(&mut behaviour, "/calimero/devnet", None)
+                .unwrap()
+                .ttl,
+            600
+        );
+        register(&mut behaviour, "/calimero/testnet", None).unwrap();
+        assert_eq!(
+            register(&mut behaviour, "/calimero/mainnet", None).unwrap_err(),
+            ErrorCode::Unavailable
+        );
+        // Renewals are not subject to the quotas.
+        register(&mut behaviour, "/calimero/devnet", Some(60)).unwrap();
+
+        assert_eq!(
+            behaviour
+                .discover(Some(Namespace::from_static("/other/devnet")), None, None)
+                .unwrap_err(),
+            ErrorCode::InvalidNamespace
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replicate_registrations() {
+        let config = Config {
+            min_ttl: 60,
+            max_ttl: 600,
+            namespaces: vec!["/calimero/*/**".parse().unwrap()],
+            ..Config::default()
+        };
+        let mut behaviour = Behaviour::open(config, None, Default::default()).unwrap();
+        let federation_peer = PeerId::random();
+        let namespace = Namespace::from_static("/calimero/devnet");
+
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = keypair.public().to_peer_id();
+        let record =
+            PeerRecord::new(&keypair, vec!["/ip4/1.1.1.1/tcp/4001".parse().unwrap()]).unwrap();
+        let replica = |namespace: &Namespace, ttl| Registration {
+            namespace: namespace.clone(),
+            record: record.clone(),
+            ttl,
+        };
+
+        assert_eq!(
+            behaviour
+                .replicate(
+                    federation_peer,
+                    replica(&Namespace::from_static("/other/devnet"), 60)
+                )
+                .unwrap_err(),
+            ErrorCode::InvalidNamespace
+        );
+
+        // The TTL is capped to the maximum TTL.
+        let replicated = behaviour
+            .replicate(federation_peer, replica(&namespace, 7200))
+            .unwrap()
+            .unwrap();
+        assert_eq!(replicated.ttl, 600);
+        let entry = behaviour.registrations().get(&namespace, &peer_id).unwrap();
+        assert_eq!(entry.replicated_from, Some(federation_peer));
+
+        // Replicating the same peer record again changes nothing.
+        assert_eq!(
+            behaviour
+                .replicate(federation_peer, replica(&namespace, 60))
+                .unwrap(),
+            None
+        );
+
+        // Only the federation peer the registration was replicated from may remove it.
+        assert!(!behaviour.unreplicate(PeerId::random(), &namespace, &peer_id));
+        assert!(behaviour.unreplicate(federation_peer, &namespace, &peer_id));
+        assert!(behaviour.registrations().is_empty());
+
+        // Registrations made with this boot node are not replaced by replicas of the
+        // same peer record, nor removed by unregistrations of federation peers.
+        behaviour
+            .register(peer_id, namespace.clone(), record.clone(), None)
+            .unwrap();
+        assert_eq!(
+            behaviour
+                .replicate(federation_peer, replica(&namespace, 60))
+                .unwrap(),
+            None
+        );
+        assert!(!behaviour.unreplicate(federation_peer, &namespace, &peer_id));
+        let entry = behaviour.registrations().get(&namespace, &peer_id).unwrap();
+        assert_eq!(entry.replicated_from, None);
+    }
+}
@@ -0,0 +1,622 @@
+use std::io;
+
+use async_trait::async_trait;
+use libp2p::core::{PeerRecord, SignedEnvelope};
+use libp2p::futures::prelude::*;
+use libp2p::rendezvous::{Cookie, ErrorCode, Namespace, Registration, Ttl};
+use libp2p::{request_response, StreamProtocol};
+
+/// Maximum size of a rendezvous message, matching the one of rust-libp2p.
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum Request {
+    Register {
+        namespace: Namespace,
+        record: PeerRecord,
+        ttl: Option<Ttl>,
+    },
+    Unregister {
+        namespace: Namespace,
+    },
+    Discover {
+        namespace: Option<Namespace>,
+        cookie: Option<Cookie>,
+        limit: Option<u64>,
+    },
+}
+
+#[derive(Debug)]
+pub enum Response {
+    Register(Result<Ttl, ErrorCode>),
+    Discover(Result<(Vec<Registration>, Cookie), ErrorCode>),
+}
+
+/// Server side of the `/rendezvous/1.0.0` wire format: varint length prefixed
+/// protobuf messages, one request and at most one response per stream.
+#[derive(Clone, Debug, Default)]
+pub struct Codec;
+
+#[async_trait]
+impl request_response::Codec for Codec {
+    type Protocol = StreamProtocol;
+    type Request = Request;
+    type Response = Response;
+
+    async fn read_request<T>(&mut self, _: &Self::Protocol, io: &mut T) -> io::Result<Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let bytes = read_length_prefixed(io).await?;
+        let message = <proto::Message as prost::Message>::decode(bytes.as_slice())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        Request::try_from(message).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    async fn read_response<T>(&mut self, _: &Self::Protocol, _: &mut T) -> io::Result<Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &Self::Protocol,
+        _: &mut T,
+        _: Request,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        response: Response,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let bytes = prost::Message::encode_length_delimited_to_vec(&proto::Message::from(response));
+        io.write_all(&bytes).await?;
+        io.flush().await
+    }
+}
+
+async fn read_length_prefixed<T>(io: &mut T) -> io::Result<Vec<u8>>
+where
+    T: AsyncRead + Unpin + Send,
+{
+    let mut len = 0usize;
+
+    for shift in (0..).step_by(7) {
+        if shift > 28 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "message length prefix too long",
+            ));
+        }
+
+        let mut byte = [0u8];
+        io.read_exact(&mut byte).await?;
+
+        len |= usize::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+
+    if len > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {len} bytes exceeds the maximum of {MAX_MESSAGE_SIZE} bytes"),
+        ));
+    }
+
+    let mut bytes = vec![0; len];
+    io.read_exact(&mut bytes).await?;
+
+    Ok(bytes)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeError {
+    #[error("unexpected message type {0:?}")]
+    UnexpectedType(Option<i32>),
+    #[error("missing field {0}")]
+    MissingField(&'static str),
+    #[error("namespace too long")]
+    NamespaceTooLong,
+    #[error("invalid signed peer record: {0}")]
+    InvalidSignedPeerRecord(String),
+    #[error("invalid cookie")]
+    InvalidCookie,
+}
+
+impl TryFrom<proto::Message> for Request {
+    type Error = DecodeError;
+
+    fn try_from(message: proto::Message) -> Result<Self, Self::Error> {
+        let message_type = message
+            .r#type
+            .and_then(|r#type| proto::MessageType::try_from(r#type).ok());
+
+        match message_type {
+            Some(proto::MessageType::Register) => {
+                let register = message
+                    .register
+                    .ok_or(DecodeError::MissingField("register"))?;
+
+                let envelope = register
+                    .signed_peer_record
+                    .ok_or(DecodeError::MissingField("signedPeerRecord"))?;
+                let record = SignedEnvelope::from_protobuf_encoding(&envelope)
+                    .map_err(|err| DecodeError::InvalidSignedPeerRecord(err.to_string()))
+                    .and_then(|envelope| {
+                        PeerRecord::from_signed_envelope(envelope)
+                            .map_err(|err| DecodeError::InvalidSignedPeerRecord(err.to_string()))
+                    })?;
+
+                Ok(Request::Register {
+                    namespace: namespace(register.ns)?.ok_or(DecodeError::MissingField("ns"))?,
+                    record,
+                    ttl: register.ttl,
+                })
+            }
+            Some(proto::MessageType::Unregister) => {
+                let unregister = message
+                    .unregister
+                    .ok_or(DecodeError::MissingField("unregister"))?;
+
+                Ok(Request::Unregister {
+                    namespace: namespace(unregister.ns)?.ok_or(DecodeError::MissingField("ns"))?,
+                })
+            }
+            Some(proto::MessageType::Discover) => {
+                let discover = message
+                    .discover
+                    .ok_or(DecodeError::MissingField("discover"))?;
+
+                Ok(Request::Discover {
+                    namespace: namespace(discover.ns)?,
+                    cookie: discover
+                        .cookie
+                        .map(Cookie::from_wire_encoding)
+                        .transpose()
+                        .map_err(|_| DecodeError::InvalidCookie)?,
+                    limit: discover.limit,
+                })
+            }
+            _ => Err(DecodeError::UnexpectedType(message.r#type)),
+        }
+    }
+}
+
+fn namespace(ns: Option<String>) -> Result<Option<Namespace>, DecodeError> {
+    ns.map(Namespace::new)
+        .transpose()
+        .map_err(|_| DecodeError::NamespaceTooLong)
+}
+
+impl From<Response> for proto::Message {
+    fn from(response: Response) -> Self {
+        match response {
+            Response::Register(result) => proto::Message {
+                r#type: Some(proto::MessageType::RegisterResponse.into()),
+                register_response: Some(match result {
+                    Ok(ttl) => proto::RegisterResponse {
+                        status: Some(proto::ResponseStatus::Ok.into()),
+                        status_text: None,
+                        ttl: Some(ttl),
+                    },
+                    Err(error) => proto::RegisterResponse {
+                        status: Some(proto::ResponseStatus::from(error).into()),
+                        status_text: None,
+                        ttl: None,
+                    },
+                }),
+                ..Default::default()
+            },
+            Response::Discover(result) => proto::Message {
+                r#type: Some(proto::MessageType::DiscoverResponse.into()),
+                discover_response: Some(match result {
+                    Ok((registrations, cookie)) => proto::DiscoverResponse {
+                        registrations: registrations
+                            .into_iter()
+                            .map(|registration| proto::Register {
+                                ns: Some(registration.namespace.to_string()),
+                                signed_peer_record: Some(
+                                    registration
+                                        .record
+                                        .into_signed_envelope()
+                                        .into_protobuf_encoding(),
+                                ),
+                                ttl: Some(registration.ttl),
+                            })
+                            .collect(),
+                        cookie: Some(cookie.into_wire_encoding()),
+                        status: Some(proto::ResponseStatus::Ok.into()),
+                        status_text: None,
+                    },
+                    Err(error) => proto::DiscoverResponse {
+                        registrations: vec![],
+                        cookie: None,
+                        status: Some(proto::ResponseStatus::from(error).into()),
+                        status_text: None,
+                    },
+                }),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl From<ErrorCode> for proto::ResponseStatus {
+    fn from(error: ErrorCode) -> Self {
+        match error {
+            ErrorCode::InvalidNamespace => proto::ResponseStatus::EInvalidNamespace,
+            ErrorCode::InvalidSignedPeerRecord => proto::ResponseStatus::EInvalidSignedPeerRecord,
+            ErrorCode::InvalidTtl => proto::ResponseStatus::EInvalidTtl,
+            ErrorCode::InvalidCookie => proto::ResponseStatus::EInvalidCookie,
+            ErrorCode::NotAuthorized => proto::ResponseStatus::ENotAuthorized,
+            ErrorCode::InternalError => proto::ResponseStatus::EInternalError,
+            ErrorCode::Unavailable => proto::ResponseStatus::EUnavailable,
+        }
+    }
+}
+
+/// Messages of the rendezvous protocol as defined in the
+/// [specification](https://github.com/libp2p/specs/blob/master/rendezvous/README.md#protobuf).
+mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Message {
+        #[prost(enumeration = "MessageType", optional, tag = "1")]
+        pub r#type: Option<i32>,
+        #[prost(message, optional, tag = "2")]
+        pub register: Option<Register>,
+        #[prost(message, optional, tag = "3")]
+        pub register_response: Option<RegisterResponse>,
+        #[prost(message, optional, tag = "4")]
+        pub unregister: Option<Unregister>,
+        #[prost(message, optional, tag = "5")]
+        pub discover: Option<Discover>,
+        #[prost(message, optional, tag = "6")]
+        pub discover_response: Option<DiscoverResponse>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Register {
+        #[prost(string, optional, tag = "1")]
+        pub ns: Option<String>,
+        #[prost(bytes = "vec", optional, tag = "2")]
+        pub signed_peer_record: Option<Vec<u8>>,
+        #[prost(uint64, optional, tag = "3")]
+        pub ttl: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct RegisterResponse {
+        #[prost(enumeration = "ResponseStatus", optional, tag = "1")]
+        pub status: Option<i32>,
+        #[prost(string, optional, tag = "2")]
+        pub status_text: Option<String>,
+        #[prost(uint64, optional, tag = "3")]
+        pub ttl: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Unregister {
+        #[prost(string, optional, tag = "1")]
+        pub ns: Option<String>,
+        #[prost(bytes = "vec", optional, tag = "2")]
+        pub id: Option<Vec<u8>>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Discover {
+        #[prost(string, optional, tag = "1")]
+        pub ns: Option<String>,
+        #[prost(uint64, optional, tag = "2")]
+        pub limit: Option<u64>,
+        #[prost(bytes = "vec", optional, tag = "3")]
+        pub cookie: Option<Vec<u8>>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct DiscoverResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub registrations: Vec<Register>,
+        #[prost(bytes = "vec", optional, tag = "2")]
+        pub cookie: Option<Vec<u8>>,
+        #[prost(enumeration = "ResponseStatus", optional, tag = "3")]
+        pub status: Option<i32>,
+        #[prost(string, optional, tag = "4")]
+        pub status_text: Option<String>,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum MessageType {
+        Register = 0,
+        RegisterResponse = 1,
+        Unregister = 2,
+        Discover = 3,
+        DiscoverResponse = 4,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum ResponseStatus {
+        Ok = 0,
+        EInvalidNamespace = 100,
+        EInvalidSignedPeerRecord = 101,
+        EInvalidTtl = 102,
+        EInvalidCookie = 103,
+        ENotAuthorized = 200,
+        EInternalError = 300,
+        EUnavailable = 400,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::futures::io::Cursor;
+    use libp2p::identity::Keypair;
+    use request_response::Codec as _;
+
+    use super::*;
+    use crate::rendezvous::PROTOCOL_NAME as PROTOCOL;
+
+    async fn read_request(bytes: Vec<u8>) -> io::Result<Request> {
+        Codec.read_request(&PROTOCOL, &mut Cursor::new(bytes)).await
+    }
+
+    async fn decode_request(message: proto::Message) -> io::Result<Request> {
+        read_request(prost::Message::encode_length_delimited_to_vec(&message)).await
+    }
+
+    async fn encode_response(response: Response) -> proto::Message {
+        let mut bytes = vec![];
+        Codec
+            .write_response(&PROTOCOL, &mut bytes, response)
+            .await
+            .unwrap();
+        <proto::Message as prost::Message>::decode_length_delimited(bytes.as_slice()).unwrap()
+    }
+
+    fn record() -> PeerRecord {
+        let keypair = Keypair::generate_ed25519();
+        PeerRecord::new(&keypair, vec!["/ip4/1.1.1.1/tcp/4001".parse().unwrap()]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_decode_requests() {
+        let record = record();
+        let request = decode_request(proto::Message {
+            r#type: Some(proto::MessageType::Register.into()),
+            register: Some(proto::Register {
+                ns: Some("ns".to_owned()),
+                signed_peer_record: Some(
+                    record
+                        .clone()
+                        .into_signed_envelope()
+                        .into_protobuf_encoding(),
+                ),
+                ttl: Some(60),
+            }),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert!(matches!(
+            request,
+            Request::Register { namespace, record: decoded, ttl: Some(60) }
+                if namespace == Namespace::from_static("ns") && decoded == record
+        ));
+
+        let request = decode_request(proto::Message {
+            r#type: Some(proto::MessageType::Unregister.into()),
+            unregister: Some(proto::Unregister {
+                ns: Some("ns".to_owned()),
+                id: None,
+            }),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert!(matches!(
+            request,
+            Request::Unregister { namespace } if namespace == Namespace::from_static("ns")
+        ));
+
+        let cookie = Cookie::for_namespace(Namespace::from_static("ns"));
+        let request = decode_request(proto::Message {
+            r#type: Some(proto::MessageType::Discover.into()),
+            discover: Some(proto::Discover {
+                ns: Some("ns".to_owned()),
+                limit: Some(10),
+                cookie: Some(cookie.clone().into_wire_encoding()),
+            }),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert!(matches!(
+            request,
+            Request::Discover { namespace: Some(namespace), cookie: Some(decoded), limit: Some(10) }
+                if namespace == Namespace::from_static("ns") && decoded == cookie
+        ));
+
+        // Discovery in all namespaces.
+        let request = decode_request(proto::Message {
+            r#type: Some(proto::MessageType::Discover.into()),
+            discover: Some(proto::Discover::default()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert!(matches!(
+            request,
+            Request::Discover {
+                namespace: None,
+                cookie: None,
+                limit: None
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reject_invalid_requests() {
+        let invalid = [
+            // Unknown message type.
+            proto::Message {
+                r#type: Some(42),
+                ..Default::default()
+            },
+            // Responses are not requests.
+            proto::Message {
+                r#type: Some(proto::MessageType::RegisterResponse.into()),
+                register_response: Some(proto::RegisterResponse::default()),
+                ..Default::default()
+            },
+            proto::Message {
+                r#type: Some(proto::MessageType::Register.into()),
+                ..Default::default()
+            },
+            proto::Message {
+                r#type: Some(proto::MessageType::Register.into()),
+                register: Some(proto::Register {
+                    ns: Some("ns".to_owned()),
+                    signed_peer_record: Some(b"not a record".to_vec()),
+                    ttl: None,
+                }),
+                ..Default::default()
+            },
+            proto::Message {
+                r#type: Some(proto::MessageType::Unregister.into()),
+                unregister: Some(proto::Unregister {
+                    ns: Some("n".repeat(256)),
+                    id: None,
+                }),
+                ..Default::default()
+            },
+            proto::Message {
+                r#type: Some(proto::MessageType::Discover.into()),
+                discover: Some(proto::Discover {
+                    cookie: Some(b"bad".to_vec()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        ];
+
+        for message in invalid {
+            let err = decode_request(message.clone()).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{message:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reject_oversized_messages() {
+        let mut bytes = vec![];
+        prost::encoding::encode_varint(MAX_MESSAGE_SIZE as u64 + 1, &mut bytes);
+        let err = read_request(bytes).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // A message of the maximum size is read, here failing on the missing bytes.
+        let mut bytes = vec![];
+        prost::encoding::encode_varint(MAX_MESSAGE_SIZE as u64, &mut bytes);
+        let err = read_request(bytes).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let err = read_request(vec![0xff; 6]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn test_encode_responses() {
+        let message = encode_response(Response::Register(Ok(60))).await;
+        assert_eq!(
+            message.r#type,
+            Some(proto::MessageType::RegisterResponse.into())
+        );
+        assert_eq!(
+            message.register_response,
+            Some(proto::RegisterResponse {
+                status: Some(proto::ResponseStatus::Ok.into()),
+                status_text: None,
+                ttl: Some(60),
+            })
+        );
+
+        let record = record();
+        let namespace = Namespace::from_static("ns");
+        let cookie = Cookie::for_namespace(namespace.clone());
+        let message = encode_response(Response::Discover(Ok((
+            vec![Registration {
+                namespace: namespace.clone(),
+                record: record.clone(),
+                ttl: 60,
+            }],
+            cookie.clone(),
+        ))))
+        .await;
+        assert_eq!(
+            message.r#type,
+            Some(proto::MessageType::DiscoverResponse.into())
+        );
+        let response = message.discover_response.unwrap();
+        assert_eq!(response.status, Some(proto::ResponseStatus::Ok.into()));
+        assert_eq!(response.cookie, Some(cookie.into_wire_encoding()));
+        assert_eq!(
+            response.registrations,
+            vec![proto::Register {
+                ns: Some("ns".to_owned()),
+                signed_peer_record: Some(record.into_signed_envelope().into_protobuf_encoding()),
+                ttl: Some(60),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_encode_error_responses() {
+        let errors = [
+            (ErrorCode::InvalidNamespace, 100),
+            (ErrorCode::InvalidSignedPeerRecord, 101),
+            (ErrorCode::InvalidTtl, 102),
+            (ErrorCode::InvalidCookie, 103),
+            (ErrorCode::NotAuthorized, 200),
+            (ErrorCode::InternalError, 300),
+            (ErrorCode::Unavailable, 400),
+        ];
+
+        for (error, status) in errors {
+            let message = encode_response(Response::Register(Err(error))).await;
+            assert_eq!(
+                message.register_response,
+                Some(proto::RegisterResponse {
+                    status: Some(status),
+                    status_text: None,
+                    ttl: None,
+                })
+            );
+
+            let message = encode_response(Response::Discover(Err(error))).await;
+            assert_eq!(
+                message.discover_response,
+                Some(proto::DiscoverResponse {
+                    registrations: vec![],
+                    cookie: None,
+                    status: Some(status),
+                    status_text: None,
+                })
+            );
+        }
+    }
+}
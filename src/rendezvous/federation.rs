@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::iter;
+use std::task::{Context, Poll};
+
+use libp2p::core::{Endpoint, PeerRecord, SignedEnvelope};
+use libp2p::rendezvous::{Namespace, Registration, Ttl};
+use libp2p::swarm::{
+    ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+    THandlerOutEvent, ToSwarm,
+};
+use libp2p::{request_response, Multiaddr, PeerId, StreamProtocol};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+const PROTOCOL_NAME: StreamProtocol = StreamProtocol::new("/calimero/rendezvous-federation/1.0.0");
+
+/// Registrations and unregistrations replicated to another boot node.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Request {
+    pub registrations: Vec<Replica>,
+    pub unregistrations: Vec<Unregistration>,
+}
+
+/// Registration with the signed peer record of the registered peer, so that it cannot
+/// be forged by the boot node replicating it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replica {
+    namespace: String,
+    /// Hex encoded signed envelope of the peer record.
+    signed_peer_record: String,
+    /// Remaining lifetime in seconds.
+    ttl: Ttl,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Unregistration {
+    namespace: String,
+    peer_id: String,
+}
+
+impl Replica {
+    pub fn new(registration: &Registration) -> Self {
+        Self {
+            namespace: registration.namespace.to_string(),
+            signed_peer_record: hex::encode(
+                registration
+                    .record
+                    .to_signed_envelope()
+                    .into_protobuf_encoding(),
+            ),
+            ttl: registration.ttl,
+        }
+    }
+
+    /// Decodes the registration, verifying the signature of its peer record.
+    pub fn decode(self) -> eyre::Result<Registration> {
+        let envelope =
+            SignedEnvelope::from_protobuf_encoding(&hex::decode(self.signed_peer_record)?)?;
+
+        Ok(Registration {
+            namespace: Namespace::new(self.namespace)?,
+            record: PeerRecord::from_signed_envelope(envelope)?,
+            ttl: self.ttl,
+        })
+    }
+}
+
+impl Unregistration {
+    pub fn new(peer_id: &PeerId, namespace: &Namespace) -> Self {
+        Self {
+            namespace: namespace.to_string(),
+            peer_id: peer_id.to_string(),
+        }
+    }
+
+    pub fn decode(self) -> eyre::Result<(PeerId, Namespace)> {
+        Ok((self.peer_id.parse()?, Namespace::new(self.namespace)?))
+    }
+}
+
+#[derive(Debug)]
+pub enum Event {
+    Received { peer: PeerId, request: Request },
+}
+
+/// Replicates rendezvous registrations between the boot nodes of a fleet, so that
+/// discovering through any of them returns the registrations made at all of them.
+///
+/// Only requests of the configured peers are accepted. Registrations are not forwarded
+/// beyond the boot node they are replicated to, the peers thus have to be configured
+/// on every boot node of the fleet.
+pub struct Behaviour {
+    inner: request_response::json::Behaviour<Request, ()>,
+    peers: HashMap<PeerId, Vec<Multiaddr>>,
+}
+
+impl Behaviour {
+    pub fn new(peers: Vec<(PeerId, Multiaddr)>) -> Self {
+        let mut addrs: HashMap<PeerId, Vec<Multiaddr>> = HashMap::new();
+        for (peer_id, addr) in peers {
+            addrs.entry(peer_id).or_default().push(addr);
+        }
+
+        Self {
+            inner: request_response::json::Behaviour::new(
+                iter::once((PROTOCOL_NAME, request_response::ProtocolSupport::Full)),
+                request_response::Config::default(),
+            ),
+            peers: addrs,
+        }
+    }
+
+    pub fn add_peer(&mut self, peer_id: PeerId, addr: Multiaddr) {
+        let addrs = self.peers.entry(peer_id).or_default();
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+
+    pub fn is_peer(&self, peer_id: &PeerId) -> bool {
+        self.peers.contains_key(peer_id)
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.peers.keys()
+    }
+
+    /// Sends the request to `peer_id`, dialing it if not connected.
+    pub fn send(&mut self, peer_id: &PeerId, request: Request) {
+        self.inner.send_request(peer_id, request);
+    }
+
+    /// Sends the request to all peers of the federation.
+    pub fn broadcast(&mut self, request: Request) {
+        for peer_id in self.peers.keys() {
+            self.inner.send_request(peer_id, request.clone());
+        }
+    }
+}
+
+impl NetworkBehaviour for Behaviour {
+    type ConnectionHandler =
+        <request_response::json::Behaviour<Request, ()> as NetworkBehaviour>::ConnectionHandler;
+    type ToSwarm = Event;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner.handle_established_inbound_connection(
+            connection_id,
+            peer,
+            local_addr,
+            remote_addr,
+        )
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        let mut addrs = self.inner.handle_pending_outbound_connection(
+            connection_id,
+            maybe_peer,
+            addresses,
+            effective_role,
+        )?;
+
+        if let Some(configured) = maybe_peer.and_then(|peer| self.peers.get(&peer)) {
+            addrs.extend(configured.iter().cloned());
+        }
+
+        Ok(addrs)
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner
+            .handle_established_outbound_connection(connection_id, peer, addr, role_override)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        self.inner.on_swarm_event(event);
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        self.inner
+            .on_connection_handler_event(peer_id, connection_id, event);
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<ToSwarm<Event, THandlerInEvent<Self>>> {
+        loop {
+            let event = match self.inner.poll(cx) {
+                Poll::Ready(ToSwarm::GenerateEvent(event)) => event,
+                Poll::Ready(to_swarm) => {
+                    return Poll::Ready(to_swarm.map_out(|_| unreachable!("mapped above")));
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+
+            match event {
+                request_response::Event::Message {
+                    peer,
+                    message:
+                        request_response::Message::Request {
+                            request, channel, ..
+                        },
+                } => {
+                    if !self.is_peer(&peer) {
+                        warn!(%peer, "Ignoring replicated registrations of a peer outside the federation");
+                        continue;
+                    }
+
+                    if self.inner.send_response(channel, ()).is_err() {
+                        debug!(%peer, "Federation response channel closed");
+                    }
+
+                    return Poll::Ready(ToSwarm::GenerateEvent(Event::Received { peer, request }));
+                }
+                request_response::Event::OutboundFailure { peer, error, .. } => {
+                    warn!(%peer, %error, "Failed to replicate registrations to federation peer");
+                }
+                request_response::Event::InboundFailure { peer, error, .. } => {
+                    debug!(%peer, %error, "Failed to receive replicated registrations");
+                }
+                request_response::Event::Message {
+                    message: request_response::Message::Response { .. },
+                    ..
+                }
+                | request_response::Event::ResponseSent { .. } => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::identity::Keypair;
+
+    use super::*;
+
+    #[test]
+    fn test_reject_forged_peer_record() {
+        let keypair = Keypair::generate_ed25519();
+        let registration = Registration {
+            namespace: Namespace::from_static("ns"),
+            record: PeerRecord::new(&keypair, vec!["/ip4/1.1.1.1/tcp/4001".parse().unwrap()])
+                .unwrap(),
+            ttl: 60,
+        };
+
+        let replica = Replica::new(&registration);
+        assert_eq!(replica.clone().decode().unwrap(), registration);
+
+        // Pointing the registration to other addresses invalidates the signature.
+        let forged = Replica {
+            signed_peer_record: replica
+                .signed_peer_record
+                .replace(&hex::encode([1, 1, 1, 1]), &hex::encode([6, 6, 6, 6])),
+            ..replica
+        };
+        assert!(forged.decode().is_err());
+    }
+}
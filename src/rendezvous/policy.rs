@@ -0,0 +1,99 @@
+use std::fmt;
+use std::str::FromStr;
+
+use libp2p::rendezvous::Namespace;
+
+/// Pattern of namespaces peers may use, matched segment by segment on `/`.
+///
+/// A `*` segment matches any single segment and a trailing `**` segment matches any
+/// number of remaining segments, e.g. `/calimero/*/**` matches `/calimero/devnet` and
+/// `/calimero/devnet/examples/chat`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NamespacePattern(String);
+
+impl NamespacePattern {
+    pub fn matches(&self, namespace: &Namespace) -> bool {
+        let namespace = namespace.to_string();
+        let mut segments = namespace.split('/');
+
+        for pattern in self.0.split('/') {
+            if pattern == "**" {
+                return true;
+            }
+
+            match segments.next() {
+                Some(segment) if pattern == "*" && !segment.is_empty() => {}
+                Some(segment) if pattern == segment => {}
+                _ => return false,
+            }
+        }
+
+        segments.next().is_none()
+    }
+}
+
+impl FromStr for NamespacePattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("namespace pattern must not be empty".to_owned());
+        }
+
+        if s.len() > libp2p::rendezvous::MAX_NAMESPACE {
+            return Err(format!(
+                "namespace pattern exceeds the maximum namespace length of {}",
+                libp2p::rendezvous::MAX_NAMESPACE
+            ));
+        }
+
+        let mut segments = s.split('/').peekable();
+        while let Some(segment) = segments.next() {
+            if segment == "**" && segments.peek().is_some() {
+                return Err(format!("`**` must be the last segment of {s:?}"));
+            }
+        }
+
+        Ok(Self(s.to_owned()))
+    }
+}
+
+impl fmt::Display for NamespacePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, namespace: &'static str) -> bool {
+        pattern
+            .parse::<NamespacePattern>()
+            .unwrap()
+            .matches(&Namespace::from_static(namespace))
+    }
+
+    #[test]
+    fn test_match_namespace_pattern() {
+        assert!(matches("/calimero/devnet", "/calimero/devnet"));
+        assert!(!matches("/calimero/devnet", "/calimero/devnet/chat"));
+
+        assert!(matches("/calimero/*/chat", "/calimero/devnet/chat"));
+        assert!(!matches("/calimero/*/chat", "/calimero//chat"));
+        assert!(!matches("/calimero/*/chat", "/calimero/devnet/other"));
+
+        assert!(matches("/calimero/*/**", "/calimero/devnet"));
+        assert!(matches("/calimero/*/**", "/calimero/devnet/examples/chat"));
+        assert!(!matches("/calimero/*/**", "/calimero"));
+        assert!(!matches("/calimero/*/**", "/other/devnet/chat"));
+    }
+
+    #[test]
+    fn test_parse_namespace_pattern() {
+        assert!("".parse::<NamespacePattern>().is_err());
+        assert!("/calimero/**/chat".parse::<NamespacePattern>().is_err());
+        assert!("/calimero/**".parse::<NamespacePattern>().is_ok());
+    }
+}
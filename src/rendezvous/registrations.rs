@@ -0,0 +1,327 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::hash::Hash;
+use std::time::{Duration, Instant, SystemTime};
+
+use libp2p::rendezvous::{Cookie, ErrorCode, Namespace, Registration};
+use libp2p::PeerId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct RegistrationId(u64);
+
+/// Active registration together with its bookkeeping.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub registration: Registration,
+    pub registered_at: SystemTime,
+    pub expires_at: Instant,
+    /// Federation peer the registration was replicated from, `None` if the peer
+    /// registered with this boot node.
+    pub replicated_from: Option<PeerId>,
+}
+
+/// Registrations held by the rendezvous server, one per peer and namespace.
+#[derive(Debug, Default)]
+pub struct Registrations {
+    next_id: u64,
+    ids: HashMap<(PeerId, Namespace), RegistrationId>,
+    entries: HashMap<RegistrationId, Entry>,
+    /// Registrations already returned to the holders of each cookie.
+    cookies: HashMap<Cookie, HashSet<RegistrationId>>,
+    expirations: BinaryHeap<Reverse<(Instant, RegistrationId)>>,
+    namespace_counts: HashMap<Namespace, usize>,
+    peer_counts: HashMap<PeerId, usize>,
+}
+
+impl Registrations {
+    /// Adds the registration, replacing the previous one of the peer in the namespace.
+    pub fn add(&mut self, entry: Entry) {
+        let key = (
+            entry.registration.record.peer_id(),
+            entry.registration.namespace.clone(),
+        );
+
+        let id = RegistrationId(self.next_id);
+        self.next_id += 1;
+
+        if let Some(old_id) = self.ids.insert(key.clone(), id) {
+            self.entries.remove(&old_id);
+        } else {
+            *self.peer_counts.entry(key.0).or_default() += 1;
+            *self.namespace_counts.entry(key.1).or_default() += 1;
+        }
+
+        self.expirations.push(Reverse((entry.expires_at, id)));
+        self.entries.insert(id, entry);
+    }
+
+    pub fn remove(&mut self, namespace: &Namespace, peer_id: &PeerId) -> Option<Entry> {
+        let id = self.ids.remove(&(*peer_id, namespace.clone()))?;
+        self.untrack(peer_id, namespace);
+        self.entries.remove(&id)
+    }
+
+    /// Whether `peer_id` holds a registration in `namespace`.
+    pub fn contains(&self, namespace: &Namespace, peer_id: &PeerId) -> bool {
+        self.ids.contains_key(&(*peer_id, namespace.clone()))
+    }
+
+    pub fn get(&self, namespace: &Namespace, peer_id: &PeerId) -> Option<&Entry> {
+        let id = self.ids.get(&(*peer_id, namespace.clone()))?;
+        self.entries.get(id)
+    }
+
+    /// Number of registrations in `namespace`.
+    pub fn namespace_len(&self, namespace: &Namespace) -> usize {
+        self.namespace_counts.get(namespace).copied().unwrap_or(0)
+    }
+
+    /// Number of namespaces `peer_id` is registered in.
+    pub fn peer_len(&self, peer_id: &PeerId) -> usize {
+        self.peer_counts.get(peer_id).copied().unwrap_or(0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns up to `limit` registrations of `namespace`, or of all namespaces if
+    /// unset, which were not yet returned for `cookie`.
+    ///
+    /// The TTL of returned registrations is their remaining lifetime.
+    pub fn discover(
+        &mut self,
+        namespace: Option<Namespace>,
+        cookie: Option<Cookie>,
+        limit: Option<u64>,
+        now: Instant,
+    ) -> Result<(Vec<Registration>, Cookie), ErrorCode> {
+        if let Some(cookie) = &cookie {
+            if cookie.namespace() != namespace.as_ref() {
+                return Err(ErrorCode::InvalidCookie);
+            }
+        }
+
+        let mut returned = cookie
+            .and_then(|cookie| self.cookies.remove(&cookie))
+            .unwrap_or_default();
+
+        let limit = limit.map_or(usize::MAX, |limit| limit as usize);
+
+        let mut registrations = vec![];
+        for (id, entry) in &self.entries {
+            if registrations.len() >= limit {
+                break;
+            }
+
+            if returned.contains(id)
+                || entry.expires_at <= now
+                || namespace
+                    .as_ref()
+                    .is_some_and(|namespace| *namespace != entry.registration.namespace)
+            {
+                continue;
+            }
+
+            returned.insert(*id);
+            registrations.push(Registration {
+                ttl: entry.remaining_ttl(now),
+                ..entry.registration.clone()
+            });
+        }
+
+        let cookie = match namespace {
+            Some(namespace) => Cookie::for_namespace(namespace),
+            None => Cookie::for_all_namespaces(),
+        };
+        self.cookies.insert(cookie.clone(), returned);
+
+        Ok((registrations, cookie))
+    }
+
+    /// Time at which the next registration expires.
+    pub fn next_expiration(&mut self) -> Option<Instant> {
+        self.drop_stale_expirations();
+        self.expirations.peek().map(|Reverse((at, _))| *at)
+    }
+
+    /// Removes and returns a registration which expired at `now`, if any.
+    pub fn pop_expired(&mut self, now: Instant) -> Option<Registration> {
+        self.drop_stale_expirations();
+
+        let Reverse((at, id)) = *self.expirations.peek()?;
+        if at > now {
+            return None;
+        }
+        self.expirations.pop();
+
+        let entry = self.entries.remove(&id)?;
+        let peer_id = entry.registration.record.peer_id();
+        self.ids
+            .remove(&(peer_id, entry.registration.namespace.clone()));
+        self.untrack(&peer_id, &entry.registration.namespace);
+
+        self.cookies.retain(|_, returned| {
+            returned.remove(&id);
+            !returned.is_empty()
+        });
+
+        Some(entry.registration)
+    }
+
+    fn untrack(&mut self, peer_id: &PeerId, namespace: &Namespace) {
+        decrement(&mut self.peer_counts, peer_id);
+        decrement(&mut self.namespace_counts, namespace);
+    }
+
+    /// Drops expirations of registrations which were replaced or removed since.
+    fn drop_stale_expirations(&mut self) {
+        while let Some(Reverse((at, id))) = self.expirations.peek() {
+            match self.entries.get(id) {
+                Some(entry) if entry.expires_at == *at => break,
+                _ => {
+                    self.expirations.pop();
+                }
+            }
+        }
+    }
+}
+
+fn decrement<K: Eq + Hash>(counts: &mut HashMap<K, usize>, key: &K) {
+    if let Some(count) = counts.get_mut(key) {
+        *count -= 1;
+        if *count == 0 {
+            counts.remove(key);
+        }
+    }
+}
+
+impl Entry {
+    pub fn new(registration: Registration, now: Instant) -> Self {
+        let expires_at = now + Duration::from_secs(registration.ttl);
+
+        Self {
+            registration,
+            registered_at: SystemTime::now(),
+            expires_at,
+            replicated_from: None,
+        }
+    }
+
+    /// Remaining lifetime in seconds at `now`, at least one second.
+    pub fn remaining_ttl(&self, now: Instant) -> u64 {
+        self.expires_at
+            .saturating_duration_since(now)
+            .as_secs()
+            .max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::core::PeerRecord;
+    use libp2p::identity::Keypair;
+
+    use super::*;
+
+    fn registration(keypair: &Keypair, namespace: &'static str, ttl: u64) -> Registration {
+        Registration {
+            namespace: Namespace::from_static(namespace),
+            record: PeerRecord::new(keypair, vec!["/ip4/1.1.1.1/tcp/4001".parse().unwrap()])
+                .unwrap(),
+            ttl,
+        }
+    }
+
+    #[test]
+    fn test_replace_registration() {
+        let now = Instant::now();
+        let keypair = Keypair::generate_ed25519();
+        let mut registrations = Registrations::default();
+
+        registrations.add(Entry::new(registration(&keypair, "ns", 60), now));
+        registrations.add(Entry::new(registration(&keypair, "ns", 120), now));
+
+        assert_eq!(registrations.len(), 1);
+        assert_eq!(registrations.peer_len(&keypair.public().to_peer_id()), 1);
+        assert_eq!(
+            registrations.namespace_len(&Namespace::from_static("ns")),
+            1
+        );
+        // The expiration of the replaced registration is ignored.
+        assert_eq!(
+            registrations.next_expiration(),
+            Some(now + Duration::from_secs(120))
+        );
+    }
+
+    #[test]
+    fn test_discover_with_cookie() {
+        let now = Instant::now();
+        let mut registrations = Registrations::default();
+        for _ in 0..3 {
+            registrations.add(Entry::new(
+                registration(&Keypair::generate_ed25519(), "ns", 60),
+                now,
+            ));
+        }
+        registrations.add(Entry::new(
+            registration(&Keypair::generate_ed25519(), "other", 60),
+            now,
+        ));
+
+        let namespace = Some(Namespace::from_static("ns"));
+
+        let (first, cookie) = registrations
+            .discover(namespace.clone(), None, Some(2), now)
+            .unwrap();
+        assert_eq!(first.len(), 2);
+
+        let (second, cookie) = registrations
+            .discover(namespace.clone(), Some(cookie), None, now)
+            .unwrap();
+        assert_eq!(second.len(), 1);
+        assert!(!first.contains(&second[0]));
+
+        let (third, _) = registrations
+            .discover(namespace, Some(cookie.clone()), None, now)
+            .unwrap();
+        assert!(third.is_empty());
+
+        assert_eq!(
+            registrations.discover(None, Some(cookie), None, now),
+            Err(ErrorCode::InvalidCookie)
+        );
+    }
+
+    #[test]
+    fn test_expire_registrations() {
+        let now = Instant::now();
+        let keypair = Keypair::generate_ed25519();
+        let mut registrations = Registrations::default();
+
+        registrations.add(Entry::new(registration(&keypair, "ns", 60), now));
+        assert_eq!(registrations.pop_expired(now), None);
+
+        let later = now + Duration::from_secs(60);
+        let (discovered, _) = registrations.discover(None, None, None, later).unwrap();
+        assert!(discovered.is_empty());
+
+        let expired = registrations.pop_expired(later).unwrap();
+        assert_eq!(expired.namespace, Namespace::from_static("ns"));
+        assert_eq!(registrations.len(), 0);
+        assert_eq!(
+            registrations.namespace_len(&Namespace::from_static("ns")),
+            0
+        );
+        assert_eq!(registrations.next_expiration(), None);
+    }
+}
@@ -0,0 +1,1175 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use camino::Utf8PathBuf;
+use eyre::WrapErr;
+use libp2p::futures::prelude::*;
+use libp2p::rendezvous::Registration;
+use libp2p::swarm::{DialError, ListenError, NetworkBehaviour, SwarmEvent};
+use libp2p::{
+    autonat, identify, identity, kad, ping, relay, Multiaddr, PeerId, StreamProtocol, Swarm,
+};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    access, admin, audit, circuits, classes, compat, external_addrs, limits, logging, metrics,
+    rendezvous, shutdown, state, store, token, transport,
+};
+
+pub const PROTOCOL_VERSION: &str =
+    concat!("/", env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+const CALIMERO_KAD_PROTO_NAME: StreamProtocol = StreamProtocol::new("/calimero/kad/1.0.0");
+
+/// Delay before the first retry of a failed Kademlia bootstrap, doubled on every
+/// consecutive failure up to the configured bootstrap interval.
+const BOOTSTRAP_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Interval between sweeps removing expired records from the Kademlia store.
+const KAD_STORE_CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Interval between checks for promoted external addresses that went unconfirmed.
+const EXTERNAL_ADDRS_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Interval between checks of the access list file for changes.
+const ACCESS_LIST_RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Interval between snapshots of the rendezvous registrations to the data directory.
+const RENDEZVOUS_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Interval between full syncs of the local rendezvous registrations to the federation
+/// peers, repairing replicas missed while a peer was unreachable.
+const FEDERATION_SYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Maximum number of registrations replicated in one federation request.
+const FEDERATION_SYNC_BATCH_SIZE: usize = 256;
+
+/// Number of events buffered for the [`RelayServer`] handle before new ones are dropped.
+const EVENT_BUFFER_SIZE: usize = 1024;
+
+#[derive(NetworkBehaviour)]
+pub(crate) struct Behaviour {
+    pub(crate) access: access::Behaviour,
+    pub(crate) autonat: autonat::Behaviour,
+    pub(crate) compat: compat::Behaviour,
+    pub(crate) connection_limits: limits::Behaviour,
+    pub(crate) federation: rendezvous::federation::Behaviour,
+    pub(crate) identify: identify::Behaviour,
+    pub(crate) kad: kad::Behaviour<store::PersistentStore>,
+    pub(crate) ping: ping::Behaviour,
+    pub(crate) relay: classes::Behaviour,
+    pub(crate) relay_token: token::Behaviour,
+    pub(crate) rendezvous: rendezvous::Behaviour,
+}
+
+/// Events of a running [`RelayServer`].
+#[derive(Debug)]
+#[non_exhaustive]
+#[allow(clippy::large_enum_variant)]
+pub enum Event {
+    NewListenAddr(Multiaddr),
+    ExternalAddrConfirmed(Multiaddr),
+    ConnectionEstablished(PeerId),
+    /// The last connection to the peer was closed.
+    PeerDisconnected(PeerId),
+    Relay(relay::Event),
+    Rendezvous(rendezvous::Event),
+}
+
+/// Requests sent by the [`RelayServer`] handle to its event loop.
+#[derive(Debug)]
+enum Control {
+    Shutdown,
+    ReloadAccessList,
+    AddExternalAddress(Multiaddr),
+    AddFederationPeer(PeerId, Multiaddr),
+}
+
+/// Builder of a [`RelayServer`], configured with the defaults of the boot node binary.
+pub struct RelayServerBuilder {
+    keypair: identity::Keypair,
+    listen_addrs: Vec<Multiaddr>,
+    external_addrs: Vec<Multiaddr>,
+    external_address_confirmations: usize,
+    bootstrap_peers: Vec<Multiaddr>,
+    bootstrap_interval: Duration,
+    federation_peers: Vec<Multiaddr>,
+    kad: kad::Config,
+    kad_store: kad::store::MemoryStoreConfig,
+    autonat: autonat::Config,
+    identify: Option<identify::Config>,
+    ping: ping::Config,
+    relay: relay::Config,
+    relay_token_issuers: Vec<identity::PublicKey>,
+    relay_classes: Option<classes::Config>,
+    circuit_policy: circuits::Policy,
+    rendezvous: rendezvous::Config,
+    version_policy: compat::Policy,
+    limits: limits::Limits,
+    transport: transport::Config,
+    data_dir: Option<Utf8PathBuf>,
+    access_list: Option<Utf8PathBuf>,
+    drain_timeout: Duration,
+    audit_log: Option<(Utf8PathBuf, audit::Rotation)>,
+    metrics_listen: Option<SocketAddr>,
+    admin_listen: Option<SocketAddr>,
+}
+
+impl RelayServerBuilder {
+    /// Adds an address to listen on. Addresses without an IP address, e.g. `/tcp/4001`,
+    /// are listened on on all IPv4 and IPv6 interfaces.
+    pub fn listen_on(mut self, addr: Multiaddr) -> Self {
+        self.listen_addrs.push(addr);
+        self
+    }
+
+    /// Adds an address known to be reachable from the internet, advertised to peers
+    /// without waiting for them to confirm it.
+    pub fn external_address(mut self, addr: Multiaddr) -> Self {
+        self.external_addrs.push(addr);
+        self
+    }
+
+    /// Sets the number of peers from distinct subnets that must observe an address
+    /// before it is advertised as external.
+    pub fn external_address_confirmations(mut self, confirmations: usize) -> Self {
+        self.external_address_confirmations = confirmations;
+        self
+    }
+
+    /// Adds a Kademlia peer to bootstrap from, as a multiaddr ending with `/p2p/<peer id>`.
+    pub fn bootstrap_peer(mut self, addr: Multiaddr) -> Self {
+        self.bootstrap_peers.push(addr);
+        self
+    }
+
+    pub fn bootstrap_interval(mut self, interval: Duration) -> Self {
+        self.bootstrap_interval = interval;
+        self
+    }
+
+    /// Adds a boot node to replicate rendezvous registrations with, as a multiaddr ending
+    /// with `/p2p/<peer id>`.
+    ///
+    /// Registrations are only replicated to the boot nodes they were made with, so
+    /// every boot node of the fleet must list all others. Replicated peer records are
+    /// signed by the registered peers, while their namespaces are trusted.
+    pub fn federation_peer(mut self, addr: Multiaddr) -> Self {
+        self.federation_peers.push(addr);
+        self
+    }
+
+    /// Sets the Kademlia configuration. Its protocol names are replaced by the one of
+    /// the Calimero network.
+    pub fn kad(mut self, config: kad::Config) -> Self {
+        self.kad = config;
+        self
+    }
+
+    pub fn kad_store(mut self, config: kad::store::MemoryStoreConfig) -> Self {
+        self.kad_store = config;
+        self
+    }
+
+    pub fn autonat(mut self, config: autonat::Config) -> Self {
+        self.autonat = config;
+        self
+    }
+
+    /// Sets the identify configuration, by default the one of [`PROTOCOL_VERSION`].
+    pub fn identify(mut self, config: identify::Config) -> Self {
+        self.identify = Some(config);
+        self
+    }
+
+    pub fn ping(mut self, config: ping::Config) -> Self {
+        self.ping = config;
+        self
+    }
+
+    /// Sets the relay configuration. Rate limiters enforcing the access list, the version
+    /// policy, the relay tokens, the circuit policy and the drain on shutdown are added
+    /// to the ones of `config`.
+    pub fn relay(mut self, config: relay::Config) -> Self {
+        self.relay = config;
+        self
+    }
+
+    /// Adds an operator key trusted to issue relay tokens. Once any is added, only
+    /// peers that presented a valid token signed by one of them can make relay
+    /// reservations.
+    pub fn relay_token_issuer(mut self, key: identity::PublicKey) -> Self {
+        self.relay_token_issuers.push(key);
+        self
+    }
+
+    /// Serves the peers of the given classes with their own relay limits, replacing the
+    /// reservation and circuit limits of the relay configuration with those of the
+    /// classes and of anonymous peers.
+    pub fn relay_classes(mut self, config: classes::Config) -> Self {
+        self.relay_classes = Some(config);
+        self
+    }
+
+    /// Sets the peers that may be connected through relayed circuits, any by default.
+    pub fn circuit_policy(mut self, policy: circuits::Policy) -> Self {
+        self.circuit_policy = policy;
+        self
+    }
+
+    pub fn rendezvous(mut self, config: rendezvous::Config) -> Self {
+        self.rendezvous = config;
+        self
+    }
+
+    /// Sets the versions of remote peers' software to accept, any by default. Peers
+    /// advertising other versions are disconnected once identified, and relay and
+    /// rendezvous requests are only served once the peer making them was identified.
+    pub fn version_policy(mut self, policy: compat::Policy) -> Self {
+        self.version_policy = policy;
+        self
+    }
+
+    pub fn connection_limits(mut self, limits: limits::Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets the transports reachable from browsers to listen on in addition to TCP and
+    /// QUIC.
+    pub fn transport(mut self, config: transport::Config) -> Self {
+        self.transport = config;
+        self
+    }
+
+    /// Sets the directory for state persisted across restarts, kept in memory only if
+    /// unset.
+    pub fn data_dir(mut self, dir: Utf8PathBuf) -> Self {
+        self.data_dir = Some(dir);
+        self
+    }
+
+    /// Sets the file with peers and networks allowed or denied to connect, reloaded
+    /// when modified.
+    pub fn access_list(mut self, path: Utf8PathBuf) -> Self {
+        self.access_list = Some(path);
+        self
+    }
+
+    /// Sets the time relayed circuits are given to drain on shutdown.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    pub fn audit_log(mut self, path: Utf8PathBuf, rotation: audit::Rotation) -> Self {
+        self.audit_log = Some((path, rotation));
+        self
+    }
+
+    /// Serves Prometheus metrics on `addr`.
+    pub fn metrics_listen(mut self, addr: SocketAddr) -> Self {
+        self.metrics_listen = Some(addr);
+        self
+    }
+
+    /// Serves the read-only admin API on `addr`.
+    pub fn admin_listen(mut self, addr: SocketAddr) -> Self {
+        self.admin_listen = Some(addr);
+        self
+    }
+
+    /// Starts the server on the current tokio runtime.
+    pub fn start(self) -> eyre::Result<RelayServer> {
+        let keypair = self.keypair;
+        let peer_id = keypair.public().to_peer_id();
+
+        let bootstrap_peers = parse_peers("bootstrap", self.bootstrap_peers, &peer_id)?;
+        let federation_peers = parse_peers("federation", self.federation_peers, &peer_id)?;
+
+        let kad_store = store::PersistentStore::open(
+            peer_id,
+            self.kad_store,
+            self.data_dir.as_ref().map(|dir| dir.join("kad")),
+        )?;
+
+        let compat = compat::Behaviour::new(self.version_policy);
+
+        let rendezvous = rendezvous::Behaviour::open(
+            self.rendezvous,
+            self.data_dir.as_ref().map(|dir| dir.join("rendezvous")),
+            compat.verified(),
+        )?;
+
+        let access = access::Behaviour::open(self.access_list)?;
+
+        let relay_token = token::Behaviour::new(peer_id, self.relay_token_issuers);
+
+        let circuit_admission =
+            circuits::Admission::new(self.circuit_policy, rendezvous.registrations());
+
+        let drain = shutdown::Drain::new(self.drain_timeout);
+
+        let audit_log = self
+            .audit_log
+            .map(|(path, rotation)| audit::AuditLog::open(path, rotation))
+            .transpose()?;
+
+        let mut relay_config = self.relay;
+        relay_config.reservation_rate_limiters.extend([
+            access.relay_filter(),
+            compat.relay_filter(),
+            relay_token.relay_filter(),
+            drain.relay_filter(),
+        ]);
+        relay_config.circuit_src_rate_limiters.extend([
+            access.relay_filter(),
+            compat.relay_filter(),
+            drain.relay_filter(),
+        ]);
+        let verified = compat.verified();
+
+        let mut metric_registry = prometheus_client::registry::Registry::default();
+        let mut metrics = metrics::Metrics::new(&mut metric_registry);
+        metrics.observe_registrations(rendezvous.registrations());
+
+        let browser_transport =
+            transport::build(&keypair, &self.transport, self.data_dir.as_deref())?;
+
+        let private_transport = match &self.transport.swarm_key {
+            Some(path) => {
+                let psk = transport::load_swarm_key(path)?;
+                info!(fingerprint = %psk.fingerprint(), "Joining private network");
+                Some(transport::private_tcp(&keypair, psk)?)
+            }
+            None => None,
+        };
+
+        let identify_config = self.identify.unwrap_or_else(|| {
+            identify::Config::new(PROTOCOL_VERSION.to_owned(), keypair.public())
+        });
+
+        let behaviour = |keypair: &identity::Keypair| Behaviour {
+            access,
+            autonat: autonat::Behaviour::new(peer_id, self.autonat),
+            compat,
+            connection_limits: limits::Behaviour::new(self.limits),
+            federation: rendezvous::federation::Behaviour::new(federation_peers),
+            identify: identify::Behaviour::new(identify_config),
+            kad: {
+                let mut kademlia_config = self.kad;
+                kademlia_config.set_protocol_names(vec![CALIMERO_KAD_PROTO_NAME]);
+
+                let mut kademlia = kad::Behaviour::with_config(peer_id, kad_store, kademlia_config);
+                kademlia.set_mode(Some(kad::Mode::Server));
+
+                kademlia
+            },
+            ping: ping::Behaviour::new(self.ping),
+            rendezvous,
+            relay: classes::Behaviour::new(
+                keypair.public().to_peer_id(),
+                relay_config,
+                self.relay_classes,
+                relay_token.grants(),
+                circuit_admission.clone(),
+                verified,
+                metrics.relay_circuit_bytes(),
+            ),
+            relay_token,
+        };
+
+        let builder = libp2p::SwarmBuilder::with_existing_identity(keypair).with_tokio();
+        let private = private_transport.is_some();
+        let mut swarm = match private_transport {
+            Some(private_transport) => builder
+                .with_other_transport(|_| private_transport)?
+                .with_bandwidth_metrics(&mut metric_registry)
+                .with_behaviour(behaviour)?
+                .build(),
+            None => builder
+                .with_tcp(
+                    Default::default(),
+                    (libp2p::tls::Config::new, libp2p::noise::Config::new),
+                    libp2p::yamux::Config::default,
+                )?
+                .with_quic()
+                .with_other_transport(|_| browser_transport)?
+                .with_bandwidth_metrics(&mut metric_registry)
+                .with_behaviour(behaviour)?
+                .build(),
+        };
+
+        let listen_addrs = self
+            .listen_addrs
+            .into_iter()
+            .chain(transport::listen_addrs(&self.transport));
+        for addr in listen_addrs {
+            if private && is_quic(&addr) {
+                eyre::bail!("Failed to listen on {addr}, QUIC cannot be used in a private network");
+            }
+
+            if has_ip(&addr) {
+                swarm
+                    .listen_on(addr.clone())
+                    .wrap_err_with(|| format!("Failed to listen on {addr}"))?;
+            } else {
+                listen_on_all_interfaces(&mut swarm, &addr)?;
+            }
+        }
+
+        for addr in &self.external_addrs {
+            info!(%addr, "Adding configured external address");
+            swarm.add_external_address(addr.clone());
+        }
+        let external_addrs = external_addrs::ExternalAddrs::new(
+            self.external_addrs,
+            self.external_address_confirmations,
+        );
+
+        let mut servers = vec![];
+
+        if let Some(addr) = self.metrics_listen {
+            servers.push(tokio::spawn(async move {
+                if let Err(err) = metrics::serve(addr, metric_registry).await {
+                    error!(%err, "Metrics server failed");
+                }
+            }));
+        }
+
+        let (command_sender, command_receiver) = mpsc::channel(32);
+
+        if let Some(addr) = self.admin_listen {
+            servers.push(tokio::spawn(async move {
+                if let Err(err) = admin::serve(addr, command_sender).await {
+                    error!(%err, "Admin API server failed");
+                }
+            }));
+        }
+
+        let (control_sender, control_receiver) = mpsc::unbounded_channel();
+        let (event_sender, event_receiver) = mpsc::channel(EVENT_BUFFER_SIZE);
+
+        let event_loop = EventLoop {
+            swarm,
+            state: Default::default(),
+            metrics,
+            command_receiver,
+            control_receiver,
+            event_sender,
+            external_addrs,
+            bootstrap_peers,
+            bootstrap_interval: self.bootstrap_interval,
+            bootstrap_retry_delay: BOOTSTRAP_RETRY_DELAY,
+            bootstrap_timer: Box::pin(time::sleep(Duration::ZERO)),
+            drain,
+            circuit_admission,
+            audit_log,
+        };
+
+        Ok(RelayServer {
+            peer_id,
+            control_sender,
+            event_receiver,
+            task: tokio::spawn(event_loop.run()),
+            servers,
+        })
+    }
+}
+
+/// Handle of a running relay, rendezvous and Kademlia boot node.
+///
+/// Yields the [`Event`]s of the server as a [`Stream`]. Events are dropped while more
+/// than 1024 of them are left unconsumed. Dropping the handle shuts the server down.
+pub struct RelayServer {
+    peer_id: PeerId,
+    control_sender: mpsc::UnboundedSender<Control>,
+    event_receiver: mpsc::Receiver<Event>,
+    task: JoinHandle<eyre::Result<()>>,
+    /// Metrics and admin API servers, stopped together with the server.
+    servers: Vec<JoinHandle<()>>,
+}
+
+impl RelayServer {
+    pub fn builder(keypair: identity::Keypair) -> RelayServerBuilder {
+        RelayServerBuilder {
+            keypair,
+            listen_addrs: vec![],
+            external_addrs: vec![],
+            external_address_confirmations: 3,
+            bootstrap_peers: vec![],
+            bootstrap_interval: Duration::from_secs(300),
+            federation_peers: vec![],
+            kad: Default::default(),
+            kad_store: Default::default(),
+            autonat: Default::default(),
+            identify: None,
+            ping: Default::default(),
+            relay: Default::default(),
+            relay_token_issuers: vec![],
+            relay_classes: None,
+            circuit_policy: Default::default(),
+            rendezvous: Default::default(),
+            version_policy: Default::default(),
+            limits: Default::default(),
+            transport: Default::default(),
+            data_dir: None,
+            access_list: None,
+            drain_timeout: Duration::from_secs(30),
+            audit_log: None,
+            metrics_listen: None,
+            admin_listen: None,
+        }
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    /// Reloads the access list file.
+    pub fn reload_access_list(&self) {
+        let _ = self.control_sender.send(Control::ReloadAccessList);
+    }
+
+    /// Adds an external address like [`RelayServerBuilder::external_address`], e.g. once
+    /// the port picked for a listen address is known.
+    pub fn add_external_address(&self, addr: Multiaddr) {
+        let _ = self.control_sender.send(Control::AddExternalAddress(addr));
+    }
+
+    /// Adds a boot node to replicate rendezvous registrations with like
+    /// [`RelayServerBuilder::federation_peer`], and syncs the registrations to it.
+    pub fn add_federation_peer(&self, addr: Multiaddr) -> eyre::Result<()> {
+        for (peer_id, addr) in parse_peers("federation", vec![addr], &self.peer_id)? {
+            let _ = self
+                .control_sender
+                .send(Control::AddFederationPeer(peer_id, addr));
+        }
+        Ok(())
+    }
+
+    /// Starts a graceful shutdown: new relay reservations and circuits are denied and
+    /// the active circuits are given the drain timeout to close. Requesting the
+    /// shutdown again closes the remaining circuits right away.
+    pub fn request_shutdown(&self) {
+        let _ = self.control_sender.send(Control::Shutdown);
+    }
+
+    /// Waits for the server to stop after a shutdown was requested.
+    pub async fn stopped(&mut self) -> eyre::Result<()> {
+        let result = (&mut self.task)
+            .await
+            .wrap_err("Relay server event loop panicked")?;
+
+        for server in &self.servers {
+            server.abort();
+        }
+
+        result
+    }
+
+    /// Shuts the server down gracefully and waits for it to stop.
+    pub async fn shutdown(mut self) -> eyre::Result<()> {
+        self.request_shutdown();
+        self.stopped().await
+    }
+}
+
+impl Stream for RelayServer {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.event_receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for RelayServer {
+    fn drop(&mut self) {
+        for server in &self.servers {
+            server.abort();
+        }
+    }
+}
+
+fn has_ip(addr: &Multiaddr) -> bool {
+    matches!(
+        addr.iter().next(),
+        Some(multiaddr::Protocol::Ip4(_) | multiaddr::Protocol::Ip6(_))
+    )
+}
+
+fn is_quic(addr: &Multiaddr) -> bool {
+    addr.iter().any(|protocol| {
+        matches!(
+            protocol,
+            multiaddr::Protocol::Quic | multiaddr::Protocol::QuicV1
+        )
+    })
+}
+
+/// Listens on `addr` on all IPv4 and IPv6 interfaces, tolerating hosts without IPv4 or
+/// IPv6 connectivity.
+fn listen_on_all_interfaces(swarm: &mut Swarm<Behaviour>, addr: &Multiaddr) -> eyre::Result<()> {
+    let mut listening = false;
+
+    for ip in [
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    ] {
+        let addr = addr.iter().fold(Multiaddr::from(ip), Multiaddr::with);
+
+        match swarm.listen_on(addr.clone()) {
+            Ok(_) => listening = true,
+            Err(err) => warn!(%addr, %err, "Failed to listen on all interfaces"),
+        }
+    }
+
+    if !listening {
+        eyre::bail!("Failed to listen on {addr} on any interface");
+    }
+
+    Ok(())
+}
+
+fn parse_peers(
+    kind: &str,
+    addrs: Vec<Multiaddr>,
+    local_peer_id: &PeerId,
+) -> eyre::Result<Vec<(PeerId, Multiaddr)>> {
+    let mut peers = vec![];
+
+    for mut addr in addrs {
+        let Some(multiaddr::Protocol::P2p(peer_id)) = addr.pop() else {
+            eyre::bail!("Failed to parse peer id from {kind} peer addr {:?}", addr);
+        };
+
+        if peer_id == *local_peer_id {
+            warn!(%addr, "Skipping {kind} peer with the local peer id");
+            continue;
+        }
+
+        peers.push((peer_id, addr));
+    }
+
+    Ok(peers)
+}
+
+pub(crate) struct EventLoop {
+    pub(crate) swarm: Swarm<Behaviour>,
+    pub(crate) state: state::NodeState,
+    metrics: metrics::Metrics,
+    command_receiver: mpsc::Receiver<admin::Command>,
+    control_receiver: mpsc::UnboundedReceiver<Control>,
+    event_sender: mpsc::Sender<Event>,
+    external_addrs: external_addrs::ExternalAddrs,
+    bootstrap_peers: Vec<(PeerId, Multiaddr)>,
+    bootstrap_interval: Duration,
+    bootstrap_retry_delay: Duration,
+    bootstrap_timer: Pin<Box<time::Sleep>>,
+    drain: shutdown::Drain,
+    circuit_admission: circuits::Admission,
+    audit_log: Option<audit::AuditLog>,
+}
+
+impl EventLoop {
+    /// Runs until a shutdown is requested or the handle is dropped, then shuts down
+    /// gracefully.
+    async fn run(mut self) -> eyre::Result<()> {
+        let mut kad_store_cleanup_tick = time::interval(KAD_STORE_CLEANUP_INTERVAL);
+        let mut rendezvous_snapshot_tick = time::interval(RENDEZVOUS_SNAPSHOT_INTERVAL);
+        let mut access_list_reload_tick = time::interval(ACCESS_LIST_RELOAD_INTERVAL);
+        let mut federation_sync_tick = time::interval(FEDERATION_SYNC_INTERVAL);
+        let mut external_addrs_expiry_tick = time::interval(EXTERNAL_ADDRS_EXPIRY_INTERVAL);
+
+        loop {
+            tokio::select! {
+                event = self.swarm.next() => {
+                    self.handle_swarm_event(event.expect("Swarm stream to be infinite.")).await;
+                }
+                // Disabled for the current iteration once the admin API is gone.
+                Some(command) = self.command_receiver.recv() => self.handle_command(command),
+                _ = &mut self.bootstrap_timer => self.bootstrap(),
+                _ = kad_store_cleanup_tick.tick() => {
+                    self.swarm
+                        .behaviour_mut()
+                        .kad
+                        .store_mut()
+                        .remove_expired(std::time::Instant::now());
+                }
+                _ = rendezvous_snapshot_tick.tick() => {
+                    self.swarm.behaviour_mut().rendezvous.snapshot_in_background();
+                }
+                _ = access_list_reload_tick.tick() => {
+                    self.swarm.behaviour_mut().access.reload_if_changed();
+                }
+                _ = federation_sync_tick.tick() => {
+                    let peers: Vec<_> =
+                        self.swarm.behaviour().federation.peers().copied().collect();
+                    for peer_id in peers {
+                        self.sync_federation_peer(peer_id);
+                    }
+                }
+                _ = external_addrs_expiry_tick.tick() => {
+                    for addr in self.external_addrs.expire(std::time::Instant::now()) {
+                        info!(%addr, "Removing external address no longer confirmed by peers");
+                        self.swarm.remove_external_address(&addr);
+                    }
+                }
+                control = self.control_receiver.recv() => match control {
+                    Some(Control::ReloadAccessList) => {
+                        info!("Reloading access list");
+                        self.swarm.behaviour_mut().access.reload();
+                    }
+                    Some(Control::AddExternalAddress(addr)) => {
+                        info!(%addr, "Adding configured external address");
+                        self.external_addrs.add_configured(addr.clone());
+                        self.swarm.add_external_address(addr);
+                    }
+                    Some(Control::AddFederationPeer(peer_id, addr)) => {
+                        info!(%peer_id, %addr, "Adding federation peer");
+                        self.swarm.behaviour_mut().federation.add_peer(peer_id, addr);
+                        self.sync_federation_peer(peer_id);
+                    }
+                    Some(Control::Shutdown) | None => return self.shutdown().await,
+                },
+            }
+        }
+    }
+
+    /// Stops accepting relay reservations and circuits, lets the active circuits drain
+    /// until the drain timeout or another shutdown request, and flushes the state
+    /// persisted to the data directory.
+    async fn shutdown(mut self) -> eyre::Result<()> {
+        self.drain.start();
+
+        let deadline = time::sleep(self.drain.timeout);
+        let mut deadline = std::pin::pin!(deadline);
+
+        if self.state.num_circuits() > 0 {
+            info!(
+                num_circuits = self.state.num_circuits(),
+                timeout = ?self.drain.timeout,
+                "Draining relayed circuits"
+            );
+        }
+
+        while self.state.num_circuits() > 0 {
+            tokio::select! {
+                event = self.swarm.next() => {
+                    self.handle_swarm_event(event.expect("Swarm stream to be infinite.")).await;
+                }
+                Some(command) = self.command_receiver.recv() => self.handle_command(command),
+                _ = &mut deadline => {
+                    warn!(
+                        num_circuits = self.state.num_circuits(),
+                        "Drain timeout reached, closing remaining circuits"
+                    );
+                    break;
+                }
+                control = self.control_receiver.recv() => match control {
+                    Some(Control::ReloadAccessList) => self.swarm.behaviour_mut().access.reload(),
+                    // Not worth advertising or syncing to while shutting down.
+                    Some(Control::AddExternalAddress(_) | Control::AddFederationPeer(..)) => {}
+                    Some(Control::Shutdown) | None => {
+                        warn!(
+                            num_circuits = self.state.num_circuits(),
+                            "Shutdown requested again, closing remaining circuits"
+                        );
+                        break;
+                    }
+                },
+            }
+        }
+
+        self.swarm
+            .behaviour_mut()
+            .rendezvous
+            .flush()
+            .await
+            .wrap_err("Failed to flush rendezvous registrations")?;
+
+        info!("Shut down");
+
+        Ok(())
+    }
+
+    /// Passes an event on to the handle, dropping it if the handle is not keeping up.
+    fn emit(&mut self, event: Event) {
+        let _ = self.event_sender.try_send(event);
+    }
+
+    fn observe_state(&mut self) {
+        self.metrics.observe_state(&self.state);
+    }
+
+    /// Sends the registrations made with this boot node to the federation peer, with
+    /// their remaining TTL.
+    ///
+    /// At least one request is sent, so that a peer without registrations still makes
+    /// itself known to a restarted peer, which then syncs back.
+    fn sync_federation_peer(&mut self, peer_id: PeerId) {
+        let now = std::time::Instant::now();
+        let behaviour = self.swarm.behaviour_mut();
+
+        let replicas: Vec<_> = behaviour
+            .rendezvous
+            .registrations()
+            .iter()
+            .filter(|entry| entry.replicated_from.is_none())
+            .map(|entry| {
+                rendezvous::federation::Replica::new(&Registration {
+                    ttl: entry.remaining_ttl(now),
+                    ..entry.registration.clone()
+                })
+            })
+            .collect();
+
+        debug!(%peer_id, num_registrations = replicas.len(), "Syncing rendezvous registrations to federation peer");
+
+        let mut batches = replicas.chunks(FEDERATION_SYNC_BATCH_SIZE).peekable();
+        if batches.peek().is_none() {
+            behaviour.federation.send(&peer_id, Default::default());
+        }
+        for batch in batches {
+            behaviour.federation.send(
+                &peer_id,
+                rendezvous::federation::Request {
+                    registrations: batch.to_vec(),
+                    unregistrations: vec![],
+                },
+            );
+        }
+    }
+
+    /// Replicates a change of the registrations made with this boot node to the
+    /// federation peers.
+    fn replicate(&mut self, event: &rendezvous::Event) {
+        let request = match event {
+            rendezvous::Event::PeerRegistered { registration, .. } => {
+                rendezvous::federation::Request {
+                    registrations: vec![rendezvous::federation::Replica::new(registration)],
+                    unregistrations: vec![],
+                }
+            }
+            rendezvous::Event::PeerUnregistered { peer, namespace } => {
+                rendezvous::federation::Request {
+                    registrations: vec![],
+                    unregistrations: vec![rendezvous::federation::Unregistration::new(
+                        peer, namespace,
+                    )],
+                }
+            }
+            _ => return,
+        };
+
+        self.swarm.behaviour_mut().federation.broadcast(request);
+    }
+
+    /// Applies the registrations replicated from the federation peer `from`.
+    fn handle_replicated(&mut self, from: PeerId, request: rendezvous::federation::Request) {
+        for replica in request.registrations {
+            let registration = match replica.decode() {
+                Ok(registration) => registration,
+                Err(err) => {
+                    warn!(%from, %err, "Ignoring replicated registration with an invalid signed peer record");
+                    continue;
+                }
+            };
+
+            let namespace = registration.namespace.clone();
+            match self
+                .swarm
+                .behaviour_mut()
+                .rendezvous
+                .replicate(from, registration)
+            {
+                Ok(Some(registration)) => {
+                    self.on_rendezvous_event(rendezvous::Event::RegistrationReplicated {
+                        from,
+                        registration,
+                    });
+                }
+                Ok(None) => {}
+                Err(error) => debug!(
+                    %from,
+                    %namespace,
+                    error_code = rendezvous::error_code(&error),
+                    "Refused replicated registration"
+                ),
+            }
+        }
+
+        for unregistration in request.unregistrations {
+            let (peer, namespace) = match unregistration.decode() {
+                Ok(unregistration) => unregistration,
+                Err(err) => {
+                    warn!(%from, %err, "Ignoring invalid replicated unregistration");
+                    continue;
+                }
+            };
+
+            if self
+                .swarm
+                .behaviour_mut()
+                .rendezvous
+                .unreplicate(from, &namespace, &peer)
+            {
+                self.on_rendezvous_event(rendezvous::Event::ReplicaUnregistered {
+                    from,
+                    peer,
+                    namespace,
+                });
+            }
+        }
+    }
+
+    fn on_rendezvous_event(&mut self, event: rendezvous::Event) {
+        self.circuit_admission.on_rendezvous_event(&event);
+        logging::rendezvous_event(&event);
+        self.metrics
+            .record_rendezvous_event(&event, self.swarm.behaviour().rendezvous.registrations());
+        if let Some(audit_log) = &mut self.audit_log {
+            audit_log.record_rendezvous_event(&event);
+        }
+        self.emit(Event::Rendezvous(event));
+    }
+
+    /// Starts a Kademlia bootstrap, re-adding the configured bootstrap peers first
+    /// since Kademlia drops addresses of peers it failed to reach.
+    fn bootstrap(&mut self) {
+        let kad = &mut self.swarm.behaviour_mut().kad;
+
+        for (peer_id, addr) in &self.bootstrap_peers {
+            kad.add_address(peer_id, addr.clone());
+        }
+
+        match kad.bootstrap() {
+            Ok(query_id) => {
+                debug!(?query_id, "Started Kademlia bootstrap");
+                // Rescheduled once the bootstrap query finishes.
+                self.schedule_bootstrap(self.bootstrap_interval.max(BOOTSTRAP_RETRY_DELAY));
+            }
+            Err(err) => {
+                if self.bootstrap_peers.is_empty() {
+                    debug!(%err, "No bootstrap peers known yet");
+                    self.schedule_bootstrap(self.bootstrap_interval);
+                } else {
+                    warn!(%err, "Failed to bootstrap Kademlia");
+                    self.schedule_bootstrap_retry();
+                }
+            }
+        }
+    }
+
+    fn handle_bootstrap_result(&mut self, result: kad::BootstrapResult, last: bool) {
+        match result {
+            Ok(kad::BootstrapOk { num_remaining, .. }) => {
+                if !last {
+                    debug!(num_remaining, "Kademlia bootstrap progressed");
+                    return;
+                }
+
+                let routing_table_size: usize = self
+                    .swarm
+                    .behaviour_mut()
+                    .kad
+                    .kbuckets()
+                    .map(|bucket| bucket.num_entries())
+                    .sum();
+
+                if routing_table_size == 0 && !self.bootstrap_peers.is_empty() {
+                    warn!("Kademlia bootstrap finished without reaching any peer");
+                    self.schedule_bootstrap_retry();
+                    return;
+                }
+
+                info!(routing_table_size, "Kademlia bootstrap finished");
+                self.bootstrap_retry_delay = BOOTSTRAP_RETRY_DELAY;
+                self.schedule_bootstrap(self.bootstrap_interval);
+            }
+            Err(err) => {
+                warn!(%err, "Kademlia bootstrap failed");
+                if last {
+                    self.schedule_bootstrap_retry();
+                }
+            }
+        }
+    }
+
+    fn schedule_bootstrap(&mut self, delay: Duration) {
+        self.bootstrap_timer
+            .as_mut()
+            .reset(time::Instant::now() + delay);
+    }
+
+    fn schedule_bootstrap_retry(&mut self) {
+        let delay = self.bootstrap_retry_delay;
+        info!(?delay, "Retrying Kademlia bootstrap");
+        self.schedule_bootstrap(delay);
+        self.bootstrap_retry_delay = (delay * 2).min(self.bootstrap_interval.max(delay));
+    }
+
+    async fn handle_swarm_event(&mut self, event: SwarmEvent<BehaviourEvent>) {
+        self.metrics.record_swarm_event(&event);
+        self.state.on_swarm_event(&event);
+        self.observe_state();
+
+        match event {
+            SwarmEvent::Behaviour(event) => {
+                self.handle_swarm_behaviour_event(event).await;
+            }
+            SwarmEvent::NewListenAddr { address, .. } => {
+                info!(%address, "Listening on address");
+                self.emit(Event::NewListenAddr(address));
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                endpoint,
+                num_established,
+                ..
+            } => {
+                self.external_addrs
+                    .on_connection_established(peer_id, endpoint.get_remote_address());
+
+                // Peers dialing in may have restarted and lost their replicas, while
+                // peers dialed are sent a sync or replicated registration anyway.
+                if endpoint.is_listener()
+                    && num_established.get() == 1
+                    && self.swarm.behaviour().federation.is_peer(&peer_id)
+                {
+                    self.sync_federation_peer(peer_id);
+                }
+                self.emit(Event::ConnectionEstablished(peer_id));
+            }
+            SwarmEvent::IncomingConnectionError {
+                send_back_addr,
+                error: ListenError::Denied { cause },
+                ..
+            } => {
+                if let Some(exceeded) = cause.downcast_ref::<limits::Exceeded>() {
+                    info!(%send_back_addr, %exceeded, "Denied inbound connection");
+                } else if let Some(denied) = cause.downcast_ref::<compat::Denied>() {
+                    info!(%send_back_addr, %denied, "Denied inbound connection");
+                }
+            }
+            SwarmEvent::OutgoingConnectionError {
+                peer_id,
+                error: DialError::Denied { cause },
+                ..
+            } => {
+                if let Some(exceeded) = cause.downcast_ref::<limits::Exceeded>() {
+                    info!(?peer_id, %exceeded, "Denied outbound connection");
+                }
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established: 0,
+                ..
+            } => {
+                self.external_addrs.on_peer_disconnected(&peer_id);
+                self.emit(Event::PeerDisconnected(peer_id));
+            }
+            SwarmEvent::ExternalAddrConfirmed { address } => {
+                if self.external_addrs.accepts_confirmed(&address) {
+                    info!(%address, "Confirmed external address");
+                    self.emit(Event::ExternalAddrConfirmed(address));
+                } else {
+                    warn!(%address, "Removing confirmed external address that is not global");
+                    self.swarm.remove_external_address(&address);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    async fn handle_swarm_behaviour_event(&mut self, event: BehaviourEvent) {
+        match event {
+            BehaviourEvent::Access(event) => match event {},
+            BehaviourEvent::Autonat(event) => {
+                logging::autonat_event(&event);
+                if let autonat::Event::StatusChanged { new, .. } = &event {
+                    for addr in self.external_addrs.on_nat_status(new) {
+                        warn!(%addr, "Removing external address found unreachable by AutoNAT");
+                        self.swarm.remove_external_address(&addr);
+                    }
+                }
+            }
+            BehaviourEvent::Compat(event) => match event {},
+            BehaviourEvent::ConnectionLimits(event) => match event {},
+            BehaviourEvent::Federation(rendezvous::federation::Event::Received {
+                peer,
+                request,
+            }) => {
+                self.handle_replicated(peer, request);
+            }
+            BehaviourEvent::Identify(event) => {
+                logging::identify_event(&event);
+                self.metrics.record_identify_event(&event);
+                self.state.on_identify_event(&event);
+                if let identify::Event::Received { peer_id, info } = event {
+                    self.swarm
+                        .behaviour_mut()
+                        .compat
+                        .on_identify_info(peer_id, &info);
+
+                    let observed_addr = info.observed_addr;
+                    if let Some(addr) = self.external_addrs.on_observed_addr(peer_id, observed_addr)
+                    {
+                        info!(%addr, "Adding external address confirmed by peers");
+                        self.swarm.add_external_address(addr);
+                    }
+                }
+            }
+            BehaviourEvent::Kad(event) => {
+                logging::kad_event(&event);
+                self.metrics.record_kad_event(&event);
+                if let kad::Event::OutboundQueryProgressed {
+                    result: kad::QueryResult::Bootstrap(result),
+                    step,
+                    ..
+                } = event
+                {
+                    self.handle_bootstrap_result(result, step.last);
+                }
+            }
+            BehaviourEvent::Relay(event) => {
+                let (event, bytes) = match event {
+                    classes::Event::Relay(event) => (event, None),
+                    classes::Event::CircuitClosed {
+                        src_peer_id,
+                        dst_peer_id,
+                        error,
+                        bytes,
+                    } => (
+                        relay::Event::CircuitClosed {
+                            src_peer_id,
+                            dst_peer_id,
+                            error,
+                        },
+                        Some(bytes),
+                    ),
+                };
+                logging::relay_event(&event, bytes);
+                self.metrics.record_relay_event(&event);
+                let closed = self.state.on_relay_event(&event);
+                if let Some(audit_log) = &mut self.audit_log {
+                    audit_log.record_relay_event(&event, closed.as_ref(), bytes);
+                }
+                self.observe_state();
+                self.emit(Event::Relay(event));
+            }
+            BehaviourEvent::RelayToken(event) => match event {},
+            BehaviourEvent::Rendezvous(event) => {
+                self.replicate(&event);
+                self.on_rendezvous_event(event);
+            }
+            BehaviourEvent::Ping(event) => {
+                self.metrics.record_ping_event(&event);
+            }
+        }
+    }
+}
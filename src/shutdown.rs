@@ -0,0 +1,59 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use libp2p::{relay, Multiaddr, PeerId};
+
+/// Switch denying new relay reservations and circuits once the boot node started
+/// draining before shutting down.
+#[derive(Clone, Debug)]
+pub struct Drain {
+    draining: Arc<AtomicBool>,
+    /// Time the active circuits are given to close on their own.
+    pub timeout: Duration,
+}
+
+impl Drain {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            draining: Default::default(),
+            timeout,
+        }
+    }
+
+    pub fn start(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    /// Rate limiter denying relay reservations and circuits while draining.
+    pub fn relay_filter(&self) -> Box<dyn relay::RateLimiter> {
+        Box::new(self.clone())
+    }
+}
+
+impl relay::RateLimiter for Drain {
+    fn try_next(&mut self, _: PeerId, _: &Multiaddr, _: Instant) -> bool {
+        !self.draining.load(Ordering::Relaxed)
+    }
+}
+
+/// Resolves with the name of the first termination signal received.
+pub async fn signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                .expect("SIGTERM handler to be installable");
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => "SIGINT",
+            _ = terminate.recv() => "SIGTERM",
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl-C"
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use libp2p::swarm::{ConnectionId, SwarmEvent};
+use libp2p::{identify, relay, Multiaddr, PeerId};
+
+/// Live state of the boot node reconstructed from swarm and behaviour events.
+///
+/// The relay server keeps its reservations and circuits private, so the event loop
+/// mirrors them here to make them observable through metrics and the admin API.
+#[derive(Debug, Default)]
+pub struct NodeState {
+    peers: HashMap<PeerId, PeerState>,
+    reservations: HashMap<PeerId, Reservation>,
+    circuits: Vec<Circuit>,
+}
+
+#[derive(Debug, Default)]
+pub struct PeerState {
+    pub connections: HashMap<ConnectionId, Multiaddr>,
+    pub agent_version: Option<String>,
+    pub protocol_version: Option<String>,
+    pub listen_addrs: Vec<Multiaddr>,
+}
+
+#[derive(Debug)]
+pub struct Reservation {
+    pub accepted_at: SystemTime,
+    pub renewed_at: Option<SystemTime>,
+}
+
+#[derive(Debug)]
+pub struct Circuit {
+    pub src_peer_id: PeerId,
+    pub dst_peer_id: PeerId,
+    pub opened_at: SystemTime,
+}
+
+impl NodeState {
+    pub fn peers(&self) -> impl Iterator<Item = (&PeerId, &PeerState)> {
+        self.peers.iter()
+    }
+
+    pub fn reservations(&self) -> impl Iterator<Item = (&PeerId, &Reservation)> {
+        self.reservations.iter()
+    }
+
+    pub fn circuits(&self) -> impl Iterator<Item = &Circuit> {
+        self.circuits.iter()
+    }
+
+    pub fn num_reservations(&self) -> usize {
+        self.reservations.len()
+    }
+
+    pub fn num_circuits(&self) -> usize {
+        self.circuits.len()
+    }
+
+    pub fn on_swarm_event<TBvEv>(&mut self, event: &SwarmEvent<TBvEv>) {
+        match event {
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                connection_id,
+                endpoint,
+                ..
+            } => {
+                self.peers
+                    .entry(*peer_id)
+                    .or_default()
+                    .connections
+                    .insert(*connection_id, endpoint.get_remote_address().clone());
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                connection_id,
+                num_established,
+                ..
+            } => {
+                if let Some(peer) = self.peers.get_mut(peer_id) {
+                    peer.connections.remove(connection_id);
+                }
+
+                if *num_established == 0 {
+                    self.peers.remove(peer_id);
+                    // The relay drops reservations of disconnected peers without an event.
+                    self.reservations.remove(peer_id);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn on_identify_event(&mut self, event: &identify::Event) {
+        if let identify::Event::Received { peer_id, info } = event {
+            // Identify may race with the connection being closed.
+            if let Some(peer) = self.peers.get_mut(peer_id) {
+                peer.agent_version = Some(info.agent_version.clone());
+                peer.protocol_version = Some(info.protocol_version.clone());
+                peer.listen_addrs.clone_from(&info.listen_addrs);
+            }
+        }
+    }
+
+    /// Mirrors a relay event, returning the circuit it closed if any.
+    pub fn on_relay_event(&mut self, event: &relay::Event) -> Option<Circuit> {
+        match event {
+            relay::Event::ReservationReqAccepted {
+                src_peer_id,
+                renewed,
+            } => {
+                let now = SystemTime::now();
+                match self.reservations.get_mut(src_peer_id) {
+                    Some(reservation) if *renewed => reservation.renewed_at = Some(now),
+                    _ => {
+                        self.reservations.insert(
+                            *src_peer_id,
+                            Reservation {
+                                accepted_at: now,
+                                renewed_at: None,
+                            },
+                        );
+                    }
+                }
+            }
+            relay::Event::ReservationTimedOut { src_peer_id } => {
+                self.reservations.remove(src_peer_id);
+            }
+            relay::Event::CircuitReqAccepted {
+                src_peer_id,
+                dst_peer_id,
+            } => {
+                self.circuits.push(Circuit {
+                    src_peer_id: *src_peer_id,
+                    dst_peer_id: *dst_peer_id,
+                    opened_at: SystemTime::now(),
+                });
+            }
+            relay::Event::CircuitClosed {
+                src_peer_id,
+                dst_peer_id,
+                ..
+            } => {
+                // Circuits carry no id in relay events, close the oldest matching one.
+                if let Some(index) = self.circuits.iter().position(|circuit| {
+                    circuit.src_peer_id == *src_peer_id && circuit.dst_peer_id == *dst_peer_id
+                }) {
+                    return Some(self.circuits.remove(index));
+                }
+            }
+            _ => {}
+        }
+
+        None
+    }
+}
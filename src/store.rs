@@ -0,0 +1,612 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Write;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use libp2p::kad::store::{self, MemoryStore, MemoryStoreConfig, RecordStore};
+use libp2p::kad::{ProviderRecord, Record, RecordKey};
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
+
+const RECORDS_DIR: &str = "records";
+const PROVIDERS_DIR: &str = "providers";
+
+/// Kademlia record store keeping records in memory and mirroring every change to
+/// disk, so that records and provider records survive restarts of the boot node.
+///
+/// Each record and the set of providers of each key are stored in their own JSON
+/// file, holding the key and named after its SHA-256 hash so that file names stay
+/// within file system limits for keys of any length. Expiration times are persisted
+/// as wall clock timestamps and records which expired while the node was down are
+/// dropped on load.
+///
+/// Files are written by a [`Writer`] thread rather than by the Kademlia behaviour
+/// changing the records, so that the event loop does not wait for the disk.
+pub struct PersistentStore {
+    inner: MemoryStore,
+    dir: Option<Utf8PathBuf>,
+    writer: Option<Writer>,
+    provider_keys: HashSet<RecordKey>,
+}
+
+impl PersistentStore {
+    /// Opens the store, loading previously persisted records from `dir`.
+    ///
+    /// Without a directory the store behaves like a plain [`MemoryStore`].
+    pub fn open(
+        local_id: PeerId,
+        config: MemoryStoreConfig,
+        dir: Option<Utf8PathBuf>,
+    ) -> eyre::Result<Self> {
+        let mut store = Self {
+            inner: MemoryStore::with_config(local_id, config),
+            dir: None,
+            writer: None,
+            provider_keys: HashSet::new(),
+        };
+
+        let Some(dir) = dir else {
+            return Ok(store);
+        };
+
+        std::fs::create_dir_all(dir.join(RECORDS_DIR))?;
+        std::fs::create_dir_all(dir.join(PROVIDERS_DIR))?;
+
+        let mut num_records = 0;
+        for path in read_dir(&dir.join(RECORDS_DIR))? {
+            match load_record(&path) {
+                Ok(Some(record)) => match store.inner.put(record) {
+                    Ok(()) => num_records += 1,
+                    Err(err) => warn!(%path, %err, "Failed to restore persisted record"),
+                },
+                Ok(None) => remove_file(&path),
+                Err(err) => warn!(%path, %err, "Skipping unreadable persisted record"),
+            }
+        }
+
+        let mut num_providers = 0;
+        for path in read_dir(&dir.join(PROVIDERS_DIR))? {
+            match load_providers(&path) {
+                Ok(providers) if providers.is_empty() => remove_file(&path),
+                Ok(providers) => {
+                    for record in providers {
+                        let key = record.key.clone();
+                        match store.inner.add_provider(record) {
+                            Ok(()) => {
+                                store.provider_keys.insert(key);
+                                num_providers += 1;
+                            }
+                            Err(err) => {
+                                warn!(%path, %err, "Failed to restore persisted provider record")
+                            }
+                        }
+                    }
+                }
+                Err(err) => warn!(%path, %err, "Skipping unreadable persisted provider records"),
+            }
+        }
+
+        info!(%dir, num_records, num_providers, "Loaded persisted Kademlia records");
+
+        store.writer = Some(Writer::spawn()?);
+        store.dir = Some(dir);
+
+        Ok(store)
+    }
+
+    /// Removes all records and provider records which expired before `now`,
+    /// returning the number of removed entries.
+    pub fn remove_expired(&mut self, now: Instant) -> usize {
+        let expired_records = self
+            .inner
+            .records()
+            .filter(|record| record.is_expired(now))
+            .map(|record| record.key.clone())
+            .collect::<Vec<_>>();
+
+        let expired_providers = self
+            .provider_keys
+            .iter()
+            .flat_map(|key| self.inner.providers(key))
+            .filter(|record| record.is_expired(now))
+            .collect::<Vec<_>>();
+
+        let count = expired_records.len() + expired_providers.len();
+
+        for key in expired_records {
+            self.remove(&key);
+        }
+
+        for record in expired_providers {
+            self.remove_provider(&record.key, &record.provider);
+        }
+
+        if count > 0 {
+            debug!(count, "Removed expired Kademlia records");
+        }
+
+        count
+    }
+
+    fn persist_record(&self, key: &RecordKey) {
+        let (Some(dir), Some(writer)) = (&self.dir, &self.writer) else {
+            return;
+        };
+
+        let path = dir.join(RECORDS_DIR).join(file_name(key));
+
+        let Some(record) = self.inner.get(key) else {
+            writer.remove(path);
+            return;
+        };
+
+        let stored = StoredRecord {
+            key: hex::encode(record.key.as_ref()),
+            value: hex::encode(&record.value),
+            publisher: record.publisher.map(|peer_id| peer_id.to_base58()),
+            expires_at: record.expires.map(to_unix_millis),
+        };
+
+        writer.write_json(path, &stored);
+    }
+
+    fn persist_providers(&mut self, key: &RecordKey) {
+        let providers = self.inner.providers(key);
+
+        if providers.is_empty() {
+            self.provider_keys.remove(key);
+        } else {
+            self.provider_keys.insert(key.clone());
+        }
+
+        let (Some(dir), Some(writer)) = (&self.dir, &self.writer) else {
+            return;
+        };
+
+        let path = dir.join(PROVIDERS_DIR).join(file_name(key));
+
+        if providers.is_empty() {
+            writer.remove(path);
+            return;
+        }
+
+        let stored = StoredProviders {
+            key: hex::encode(key.as_ref()),
+            providers: providers
+                .into_iter()
+                .map(|record| StoredProvider {
+                    provider: record.provider.to_base58(),
+                    expires_at: record.expires.map(to_unix_millis),
+                    addresses: record.addresses.iter().map(ToString::to_string).collect(),
+                })
+                .collect(),
+        };
+
+        writer.write_json(path, &stored);
+    }
+}
+
+/// Change of a persisted file, its new contents or `None` to remove it.
+type Change = (Utf8PathBuf, Option<Vec<u8>>);
+
+/// Thread writing the changes of the store to disk in batches.
+///
+/// Changes queued while a batch is written are coalesced, so that only the latest
+/// contents of a file are written. Dropping the writer waits for the queued changes
+/// to be written.
+struct Writer {
+    sender: Option<mpsc::Sender<Change>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Writer {
+    fn spawn() -> eyre::Result<Self> {
+        let (sender, receiver) = mpsc::channel::<Change>();
+
+        let thread = thread::Builder::new()
+            .name("kad-store-writer".to_owned())
+            .spawn(move || {
+                while let Ok(change) = receiver.recv() {
+                    let batch: HashMap<_, _> =
+                        std::iter::once(change).chain(receiver.try_iter()).collect();
+                    write_batch(batch);
+                }
+            })?;
+
+        Ok(Self {
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+
+    fn write_json<T: Serialize>(&self, path: Utf8PathBuf, value: &T) {
+        match serde_json::to_vec(value) {
+            Ok(bytes) => self.send((path, Some(bytes))),
+            Err(err) => warn!(%path, %err, "Failed to serialize persisted record"),
+        }
+    }
+
+    fn remove(&self, path: Utf8PathBuf) {
+        self.send((path, None));
+    }
+
+    fn send(&self, change: Change) {
+        let sender = self
+            .sender
+            .as_ref()
+            .expect("sender to be set until dropped");
+        if let Err(mpsc::SendError((path, _))) = sender.send(change) {
+            warn!(%path, "Failed to persist record, the writer thread is gone");
+        }
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Applies a batch of changes, syncing each directory once after its files.
+fn write_batch(batch: HashMap<Utf8PathBuf, Option<Vec<u8>>>) {
+    let mut dirs = HashSet::new();
+
+    for (path, bytes) in batch {
+        match bytes {
+            Some(bytes) => {
+                if let Err(err) = write_file(&path, &bytes) {
+                    warn!(%path, %err, "Failed to persist record");
+                    continue;
+                }
+            }
+            None => remove_file(&path),
+        }
+
+        if let Some(dir) = path.parent() {
+            dirs.insert(dir.to_owned());
+        }
+    }
+
+    for dir in dirs {
+        if let Err(err) = sync_dir(&dir) {
+            warn!(%dir, %err, "Failed to sync persisted records");
+        }
+    }
+}
+
+impl RecordStore for PersistentStore {
+    type RecordsIter<'a> = <MemoryStore as RecordStore>::RecordsIter<'a>;
+    type ProvidedIter<'a> = <MemoryStore as RecordStore>::ProvidedIter<'a>;
+
+    fn get(&self, k: &RecordKey) -> Option<Cow<'_, Record>> {
+        self.inner.get(k)
+    }
+
+    fn put(&mut self, r: Record) -> store::Result<()> {
+        let key = r.key.clone();
+        self.inner.put(r)?;
+        self.persist_record(&key);
+        Ok(())
+    }
+
+    fn remove(&mut self, k: &RecordKey) {
+        self.inner.remove(k);
+        self.persist_record(k);
+    }
+
+    fn records(&self) -> Self::RecordsIter<'_> {
+        self.inner.records()
+    }
+
+    fn add_provider(&mut self, record: ProviderRecord) -> store::Result<()> {
+        let key = record.key.clone();
+        self.inner.add_provider(record)?;
+        self.persist_providers(&key);
+        Ok(())
+    }
+
+    fn providers(&self, key: &RecordKey) -> Vec<ProviderRecord> {
+        self.inner.providers(key)
+    }
+
+    fn provided(&self) -> Self::ProvidedIter<'_> {
+        self.inner.provided()
+    }
+
+    fn remove_provider(&mut self, k: &RecordKey, p: &PeerId) {
+        self.inner.remove_provider(k, p);
+        self.persist_providers(k);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredRecord {
+    key: String,
+    value: String,
+    publisher: Option<String>,
+    expires_at: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredProviders {
+    key: String,
+    providers: Vec<StoredProvider>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredProvider {
+    provider: String,
+    expires_at: Option<u64>,
+    addresses: Vec<String>,
+}
+
+/// Loads a persisted record, returning `None` if it has already expired.
+fn load_record(path: &Utf8Path) -> eyre::Result<Option<Record>> {
+    let stored: StoredRecord = serde_json::from_slice(&std::fs::read(path)?)?;
+
+    let expires = match stored.expires_at {
+        Some(expires_at) => match from_unix_millis(expires_at) {
+            Some(expires) => Some(expires),
+            None => return Ok(None),
+        },
+        None => None,
+    };
+
+    Ok(Some(Record {
+        key: RecordKey::from(hex::decode(stored.key)?),
+        value: hex::decode(stored.value)?,
+        publisher: stored
+            .publisher
+            .map(|publisher| publisher.parse::<PeerId>())
+            .transpose()?,
+        expires,
+    }))
+}
+
+/// Loads the persisted provider records of a key, skipping already expired ones.
+fn load_providers(path: &Utf8Path) -> eyre::Result<Vec<ProviderRecord>> {
+    let stored: StoredProviders = serde_json::from_slice(&std::fs::read(path)?)?;
+    let key = RecordKey::from(hex::decode(stored.key)?);
+
+    let mut providers = vec![];
+
+    for provider in stored.providers {
+        let expires = match provider.expires_at {
+            Some(expires_at) => match from_unix_millis(expires_at) {
+                Some(expires) => Some(expires),
+                None => continue,
+            },
+            None => None,
+        };
+
+        providers.push(ProviderRecord {
+            key: key.clone(),
+            provider: provider.provider.parse()?,
+            expires,
+            addresses: provider
+                .addresses
+                .iter()
+                .map(|addr| addr.parse::<Multiaddr>())
+                .collect::<Result<_, _>>()?,
+        });
+    }
+
+    Ok(providers)
+}
+
+fn file_name(key: &RecordKey) -> String {
+    format!("{}.json", hex::encode(Sha256::digest(key.as_ref())))
+}
+
+fn read_dir(dir: &Utf8Path) -> eyre::Result<Vec<Utf8PathBuf>> {
+    let mut paths = vec![];
+
+    for entry in dir.read_dir_utf8()? {
+        let path = entry?.into_path();
+        if path.extension() == Some("json") {
+            paths.push(path);
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Writes the bytes to a temporary file first and renames it over the target,
+/// so that a crash never leaves a partially written file behind.
+///
+/// Blocks until the file and its directory are synced to disk.
+pub fn write_bytes(path: &Utf8Path, bytes: &[u8]) -> eyre::Result<()> {
+    write_file(path, bytes)?;
+    if let Some(dir) = path.parent() {
+        sync_dir(dir)?;
+    }
+    Ok(())
+}
+
+/// Writes and syncs a temporary file before renaming it over `path`, leaving the sync
+/// of the rename to the caller, see [`sync_dir`].
+fn write_file(path: &Utf8Path, bytes: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("json.tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)
+}
+
+/// Syncs the entries of a directory, making renames and removals in it durable.
+fn sync_dir(dir: &Utf8Path) -> std::io::Result<()> {
+    let dir = if dir.as_str().is_empty() {
+        Utf8Path::new(".")
+    } else {
+        dir
+    };
+    File::open(dir)?.sync_all()
+}
+
+fn remove_file(path: &Utf8Path) {
+    match std::fs::remove_file(path) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => warn!(%path, %err, "Failed to remove persisted record"),
+    }
+}
+
+/// Converts a future [`Instant`] into a wall clock timestamp that survives restarts.
+pub fn to_unix_millis(instant: Instant) -> u64 {
+    let remaining = instant.saturating_duration_since(Instant::now());
+    system_time_to_unix_millis(SystemTime::now() + remaining)
+}
+
+/// Converts a wall clock timestamp back into an [`Instant`], returning `None` if
+/// the timestamp lies in the past.
+pub fn from_unix_millis(millis: u64) -> Option<Instant> {
+    let remaining = system_time_from_unix_millis(millis)
+        .duration_since(SystemTime::now())
+        .ok()?;
+    Some(Instant::now() + remaining)
+}
+
+pub fn system_time_to_unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+pub fn system_time_from_unix_millis(millis: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(dir: &tempfile::TempDir, local_id: PeerId) -> PersistentStore {
+        let path = Utf8PathBuf::try_from(dir.path().to_path_buf()).unwrap();
+        PersistentStore::open(local_id, MemoryStoreConfig::default(), Some(path)).unwrap()
+    }
+
+    #[test]
+    fn test_records_survive_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let local_id = PeerId::random();
+        let publisher = PeerId::random();
+
+        let mut store = open(&dir, local_id);
+        let mut record = Record::new(b"key".to_vec(), b"value".to_vec());
+        record.publisher = Some(publisher);
+        record.expires = Some(Instant::now() + Duration::from_secs(60));
+        store.put(record).unwrap();
+        drop(store);
+
+        let store = open(&dir, local_id);
+        let record = store.get(&RecordKey::new(b"key")).unwrap();
+        assert_eq!(record.value, b"value");
+        assert_eq!(record.publisher, Some(publisher));
+        assert!(record.expires.unwrap() > Instant::now() + Duration::from_secs(55));
+    }
+
+    #[test]
+    fn test_long_keys_survive_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let local_id = PeerId::random();
+        // Twice the maximum file name length of most file systems once hex encoded.
+        let key = RecordKey::new(&[7; 255]);
+
+        let mut store = open(&dir, local_id);
+        store
+            .put(Record::new(key.clone(), b"value".to_vec()))
+            .unwrap();
+        drop(store);
+
+        let store = open(&dir, local_id);
+        assert_eq!(store.get(&key).unwrap().value, b"value");
+    }
+
+    #[test]
+    fn test_removed_records_are_not_restored() {
+        let dir = tempfile::tempdir().unwrap();
+        let local_id = PeerId::random();
+
+        let mut store = open(&dir, local_id);
+        store
+            .put(Record::new(b"key".to_vec(), b"value".to_vec()))
+            .unwrap();
+        store.remove(&RecordKey::new(b"key"));
+        drop(store);
+
+        let store = open(&dir, local_id);
+        assert!(store.get(&RecordKey::new(b"key")).is_none());
+    }
+
+    #[test]
+    fn test_expired_records_are_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let local_id = PeerId::random();
+        let key = RecordKey::new(b"app");
+
+        let mut store = open(&dir, local_id);
+        let mut record = Record::new(key.clone(), b"value".to_vec());
+        record.expires = Some(Instant::now() + Duration::from_millis(10));
+        store.put(record).unwrap();
+        store
+            .add_provider(ProviderRecord {
+                key: key.clone(),
+                provider: PeerId::random(),
+                expires: Some(Instant::now() + Duration::from_millis(10)),
+                addresses: vec!["/ip4/127.0.0.1/tcp/4001".parse().unwrap()],
+            })
+            .unwrap();
+
+        std::thread::sleep(Duration::from_millis(20));
+
+        let mut reopened = open(&dir, local_id);
+        assert!(reopened.get(&key).is_none());
+        assert!(reopened.providers(&key).is_empty());
+
+        assert_eq!(store.remove_expired(Instant::now()), 2);
+        assert!(store.get(&key).is_none());
+        assert!(store.providers(&key).is_empty());
+        assert_eq!(reopened.remove_expired(Instant::now()), 0);
+    }
+
+    #[test]
+    fn test_providers_survive_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let local_id = PeerId::random();
+        let key = RecordKey::new(b"app");
+        let provider = PeerId::random();
+        let address: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+
+        let mut store = open(&dir, local_id);
+        store
+            .add_provider(ProviderRecord {
+                key: key.clone(),
+                provider,
+                expires: Some(Instant::now() + Duration::from_secs(60)),
+                addresses: vec![address.clone()],
+            })
+            .unwrap();
+        drop(store);
+
+        let mut store = open(&dir, local_id);
+        let providers = store.providers(&key);
+        assert_eq!(providers.len(), 1);
+        assert_eq!(providers[0].provider, provider);
+        assert_eq!(providers[0].addresses, vec![address]);
+
+        store.remove_provider(&key, &provider);
+        drop(store);
+
+        let store = open(&dir, local_id);
+        assert!(store.providers(&key).is_empty());
+    }
+}
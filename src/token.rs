@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use camino::Utf8PathBuf;
+use clap::Args;
+use libp2p::core::{Endpoint, SignedEnvelope};
+use libp2p::swarm::{
+    ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+    THandlerOutEvent, ToSwarm,
+};
+use libp2p::{identity, relay, request_response, Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::key;
+
+pub use boot_node_common::token::{Request, Response, PROTOCOL_NAME};
+
+/// Domain of the token signatures, so that other data signed by an issuer key cannot
+/// be passed off as a token.
+const SIGNATURE_DOMAIN: &str = "calimero-relay-token";
+const PAYLOAD_TYPE: &[u8] = b"/calimero/relay-token";
+
+/// Grant of relay reservations to a peer, signed by an operator key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub peer_id: PeerId,
+    /// Relays the token may be presented to.
+    pub relays: Vec<PeerId>,
+    pub expires_at: SystemTime,
+    /// Relay class granted to the peer, if any.
+    pub class: Option<String>,
+}
+
+/// Signed payload of a [`Token`].
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    peer_id: String,
+    relays: Vec<String>,
+    /// Seconds since the unix epoch.
+    expires_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    class: Option<String>,
+}
+
+/// Reason a token was rejected.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Invalid {
+    #[error("malformed token: {0}")]
+    Malformed(String),
+    #[error("token was not signed by a trusted issuer")]
+    UnknownIssuer,
+    #[error("token was issued to {0}")]
+    OtherPeer(PeerId),
+    #[error("token is not valid for this relay")]
+    OtherRelay,
+    #[error("token expired")]
+    Expired,
+}
+
+impl Token {
+    /// Signs the token with the issuer key, returning the hex encoded signed envelope
+    /// presented to relays.
+    pub fn sign(&self, issuer: &identity::Keypair) -> eyre::Result<String> {
+        let claims = Claims {
+            peer_id: self.peer_id.to_string(),
+            relays: self.relays.iter().map(PeerId::to_string).collect(),
+            expires_at: self.expires_at.duration_since(UNIX_EPOCH)?.as_secs(),
+            class: self.class.clone(),
+        };
+
+        let envelope = SignedEnvelope::new(
+            issuer,
+            SIGNATURE_DOMAIN.to_owned(),
+            PAYLOAD_TYPE.to_vec(),
+            serde_json::to_vec(&claims)?,
+        )?;
+
+        Ok(hex::encode(envelope.into_protobuf_encoding()))
+    }
+
+    /// Decodes a token presented by `peer` to `relay`, checking that it was signed by
+    /// one of `issuers`, is bound to the peer and scoped to the relay, and has not
+    /// expired by `now`.
+    pub fn verify(
+        encoded: &str,
+        issuers: &[identity::PublicKey],
+        peer: &PeerId,
+        relay: &PeerId,
+        now: SystemTime,
+    ) -> Result<Token, Invalid> {
+        let malformed = |err: &dyn std::fmt::Display| Invalid::Malformed(err.to_string());
+
+        let bytes = hex::decode(encoded.trim()).map_err(|err| malformed(&err))?;
+        let envelope =
+            SignedEnvelope::from_protobuf_encoding(&bytes).map_err(|err| malformed(&err))?;
+        let (payload, signing_key) = envelope
+            .payload_and_signing_key(SIGNATURE_DOMAIN.to_owned(), PAYLOAD_TYPE)
+            .map_err(|err| malformed(&err))?;
+
+        if !issuers.contains(signing_key) {
+            return Err(Invalid::UnknownIssuer);
+        }
+
+        let claims: Claims = serde_json::from_slice(payload).map_err(|err| malformed(&err))?;
+        let token = Token {
+            peer_id: claims.peer_id.parse().map_err(|err| malformed(&err))?,
+            relays: claims
+                .relays
+                .iter()
+                .map(|relay| relay.parse())
+                .collect::<Result<_, _>>()
+                .map_err(|err| malformed(&err))?,
+            expires_at: UNIX_EPOCH + Duration::from_secs(claims.expires_at),
+            class: claims.class,
+        };
+
+        if token.peer_id != *peer {
+            return Err(Invalid::OtherPeer(token.peer_id));
+        }
+        if !token.relays.contains(relay) {
+            return Err(Invalid::OtherRelay);
+        }
+        if token.expires_at <= now {
+            return Err(Invalid::Expired);
+        }
+
+        Ok(token)
+    }
+}
+
+/// Public key of a token issuer, parsed from its hex encoded protobuf encoding as
+/// printed by `inspect-key`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issuer(pub identity::PublicKey);
+
+impl FromStr for Issuer {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(identity::PublicKey::try_decode_protobuf(
+            &hex::decode(s.trim())?,
+        )?))
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct IssueTokenOpt {
+    /// The file with the protobuf encoded private key of the issuer
+    #[clap(long, value_name = "FILE")]
+    issuer_key: Utf8PathBuf,
+
+    /// The peer the token is issued to
+    #[clap(long, value_name = "PEER_ID")]
+    peer_id: PeerId,
+
+    /// Peer id of a boot node the token may be presented to
+    #[clap(long = "relay", value_name = "PEER_ID", required = true)]
+    relays: Vec<PeerId>,
+
+    /// Time in seconds the token is valid for
+    #[clap(long, value_name = "SECONDS", default_value = "2592000")]
+    valid_for: u64,
+
+    /// Relay class granted to the peer, as named in the relay classes file
+    #[clap(long, value_name = "NAME")]
+    class: Option<String>,
+}
+
+pub fn issue(opt: IssueTokenOpt) -> eyre::Result<()> {
+    let issuer = key::load(&opt.issuer_key, false)?;
+    let token = Token {
+        peer_id: opt.peer_id,
+        relays: opt.relays,
+        expires_at: SystemTime::now() + Duration::from_secs(opt.valid_for),
+        class: opt.class,
+    };
+
+    println!("{}", token.sign(&issuer)?);
+
+    Ok(())
+}
+
+/// Restricts relay reservations, through [`Self::relay_filter`], to peers that
+/// presented a valid token signed by one of the configured issuers.
+///
+/// Peers present their token with a request of [`PROTOCOL_NAME`] before requesting a
+/// reservation. Reservations made with a token expire at the latest when they have to
+/// be renewed after the token expired. Without issuers any peer can make reservations
+/// and the protocol is not supported.
+pub struct Behaviour {
+    inner: request_response::json::Behaviour<Request, Response>,
+    local_peer_id: PeerId,
+    issuers: Vec<identity::PublicKey>,
+    grants: Grants,
+}
+
+/// Valid tokens presented by peers, shared with the relay filters.
+#[derive(Clone, Default)]
+pub(crate) struct Grants(Arc<RwLock<HashMap<PeerId, Token>>>);
+
+impl Grants {
+    /// Whether `peer` presented a token that has not expired yet.
+    pub(crate) fn contains(&self, peer: &PeerId) -> bool {
+        self.with_token(peer, |_| ()).is_some()
+    }
+
+    /// Relay class granted to `peer` by an unexpired token.
+    pub(crate) fn class(&self, peer: &PeerId) -> Option<String> {
+        self.with_token(peer, |token| token.class.clone()).flatten()
+    }
+
+    fn with_token<T>(&self, peer: &PeerId, f: impl FnOnce(&Token) -> T) -> Option<T> {
+        self.0
+            .read()
+            .expect("grants lock to not be poisoned")
+            .get(peer)
+            .filter(|token| token.expires_at > SystemTime::now())
+            .map(f)
+    }
+
+    fn insert(&self, token: Token) {
+        let now = SystemTime::now();
+        let mut tokens = self.0.write().expect("grants lock to not be poisoned");
+        tokens.retain(|_, token| token.expires_at > now);
+        tokens.insert(token.peer_id, token);
+    }
+}
+
+impl Behaviour {
+    pub fn new(local_peer_id: PeerId, issuers: Vec<identity::PublicKey>) -> Self {
+        let protocols = (!issuers.is_empty())
+            .then_some((PROTOCOL_NAME, request_response::ProtocolSupport::Inbound));
+
+        Self {
+            inner: request_response::json::Behaviour::new(
+                protocols,
+                request_response::Config::default(),
+            ),
+            local_peer_id,
+            issuers,
+            grants: Default::default(),
+        }
+    }
+
+    /// Tokens presented by peers so far.
+    pub(crate) fn grants(&self) -> Grants {
+        self.grants.clone()
+    }
+
+    /// Rate limiter denying relay reservations of peers without a valid token, if any
+    /// issuer is configured.
+    pub fn relay_filter(&self) -> Box<dyn relay::RateLimiter> {
+        Box::new(RelayFilter {
+            required: !self.issuers.is_empty(),
+            grants: self.grants.clone(),
+        })
+    }
+
+    fn authorize(&mut self, peer: &PeerId, token: &str) -> Result<SystemTime, Invalid> {
+        let now = SystemTime::now();
+        let token = Token::verify(token, &self.issuers, peer, &self.local_peer_id, now)?;
+        let expires_at = token.expires_at;
+        self.grants.insert(token);
+
+        Ok(expires_at)
+    }
+}
+
+struct RelayFilter {
+    required: bool,
+    grants: Grants,
+}
+
+impl relay::RateLimiter for RelayFilter {
+    fn try_next(&mut self, peer: PeerId, _: &Multiaddr, _: Instant) -> bool {
+        !self.required || self.grants.contains(&peer)
+    }
+}
+
+impl NetworkBehaviour for Behaviour {
+    type ConnectionHandler =
+        <request_response::json::Behaviour<Request, Response> as NetworkBehaviour>::ConnectionHandler;
+    type ToSwarm = Infallible;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner.handle_established_inbound_connection(
+            connection_id,
+            peer,
+            local_addr,
+            remote_addr,
+        )
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner
+            .handle_established_outbound_connection(connection_id, peer, addr, role_override)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        self.inner.on_swarm_event(event);
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        self.inner
+            .on_connection_handler_event(peer_id, connection_id, event);
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<ToSwarm<Infallible, THandlerInEvent<Self>>> {
+        loop {
+            let event = match self.inner.poll(cx) {
+                Poll::Ready(ToSwarm::GenerateEvent(event)) => event,
+                Poll::Ready(to_swarm) => {
+                    return Poll::Ready(to_swarm.map_out(|_| unreachable!("mapped above")));
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+
+            match event {
+                request_response::Event::Message {
+                    peer,
+                    message:
+                        request_response::Message::Request {
+                            request, channel, ..
+                        },
+                } => {
+                    let response = match self.authorize(&peer, &request.token) {
+                        Ok(expires_at) => {
+                            let expires_at = expires_at
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap_or_default()
+                                .as_secs();
+                            info!(%peer, expires_at, "Accepted relay token");
+                            Response::Accepted { expires_at }
+                        }
+                        Err(err) => {
+                            info!(%peer, %err, "Rejected relay token");
+                            Response::Rejected {
+                                reason: err.to_string(),
+                            }
+                        }
+                    };
+
+                    if self.inner.send_response(channel, response).is_err() {
+                        debug!(%peer, "Relay token response channel closed");
+                    }
+                }
+                request_response::Event::InboundFailure { peer, error, .. } => {
+                    debug!(%peer, %error, "Failed to receive relay token");
+                }
+                request_response::Event::OutboundFailure { .. }
+                | request_response::Event::Message {
+                    message: request_response::Message::Response { .. },
+                    ..
+                }
+                | request_response::Event::ResponseSent { .. } => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_token() {
+        let issuer = identity::Keypair::generate_ed25519();
+        let issuers = [issuer.public()];
+        let (peer, relay) = (PeerId::random(), PeerId::random());
+        let now = UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+
+        let token = Token {
+            peer_id: peer,
+            relays: vec![PeerId::random(), relay],
+            expires_at: now + Duration::from_secs(60),
+            class: Some("verified".to_owned()),
+        };
+        let encoded = token.sign(&issuer).unwrap();
+
+        assert_eq!(
+            Token::verify(&encoded, &issuers, &peer, &relay, now),
+            Ok(token.clone())
+        );
+        assert_eq!(
+            Token::verify(&encoded, &[], &peer, &relay, now),
+            Err(Invalid::UnknownIssuer)
+        );
+        assert_eq!(
+            Token::verify(&encoded, &issuers, &relay, &relay, now),
+            Err(Invalid::OtherPeer(peer))
+        );
+        assert_eq!(
+            Token::verify(&encoded, &issuers, &peer, &PeerId::random(), now),
+            Err(Invalid::OtherRelay)
+        );
+        assert_eq!(
+            Token::verify(&encoded, &issuers, &peer, &relay, token.expires_at),
+            Err(Invalid::Expired)
+        );
+
+        // A token signed by another key fails even when claiming the same payload.
+        let forged = token.sign(&identity::Keypair::generate_ed25519()).unwrap();
+        assert_eq!(
+            Token::verify(&forged, &issuers, &peer, &relay, now),
+            Err(Invalid::UnknownIssuer)
+        );
+        assert!(matches!(
+            Token::verify("nonsense", &issuers, &peer, &relay, now),
+            Err(Invalid::Malformed(_))
+        ));
+    }
+}
@@ -0,0 +1,255 @@
+use camino::{Utf8Path, Utf8PathBuf};
+use eyre::WrapErr;
+use libp2p::core::muxing::StreamMuxerBox;
+use libp2p::core::transport::dummy::DummyTransport;
+use libp2p::core::transport::Boxed;
+use libp2p::pnet::PreSharedKey;
+use libp2p::{identity, Multiaddr, PeerId, Transport};
+
+pub use boot_node_common::transport::private_tcp;
+
+pub type BoxedTransport = Boxed<(PeerId, StreamMuxerBox)>;
+
+/// Ports of the transports reachable from browsers to listen on, on all interfaces.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    pub websocket_port: Option<u16>,
+    /// Port of TLS secured WebSocket connections, requiring a certificate and key.
+    pub websocket_tls_port: Option<u16>,
+    /// PEM file with the certificate chain for secure WebSocket connections.
+    pub websocket_tls_cert: Option<Utf8PathBuf>,
+    /// PEM file with the private key of the certificate.
+    pub websocket_tls_key: Option<Utf8PathBuf>,
+    pub webrtc_port: Option<u16>,
+    /// Port of WebTransport connections, which cannot be served yet, see [`build`].
+    pub webtransport_port: Option<u16>,
+    /// Swarm key file of a private network, see [`load_swarm_key`].
+    pub swarm_key: Option<Utf8PathBuf>,
+}
+
+/// Builds the transports reachable from browsers enabled in `opt`, used alongside
+/// TCP and QUIC.
+///
+/// WebTransport is not offered, rust-libp2p only implements it for browsers, so a
+/// WebTransport port is rejected rather than silently ignored.
+pub fn build(
+    keypair: &identity::Keypair,
+    opt: &Config,
+    data_dir: Option<&Utf8Path>,
+) -> eyre::Result<BoxedTransport> {
+    let mut transport = DummyTransport::new().boxed();
+
+    if opt.webtransport_port.is_some() {
+        eyre::bail!(
+            "WebTransport listeners are not supported, rust-libp2p only implements \
+             WebTransport for browsers. Serve browsers with WebRTC-direct or WebSocket instead"
+        );
+    }
+
+    if opt.swarm_key.is_some() && !listen_addrs(opt).is_empty() {
+        eyre::bail!("Transports reachable from browsers cannot be used in a private network");
+    }
+
+    if opt.websocket_port.is_some() || opt.websocket_tls_port.is_some() {
+        transport = or_transport(transport, websocket::build(keypair, opt)?);
+    }
+
+    if opt.webrtc_port.is_some() {
+        transport = or_transport(transport, webrtc::build(keypair, data_dir)?);
+    }
+
+    Ok(transport)
+}
+
+/// Loads the pre-shared key of a private network from a swarm key file, in the format
+/// of go-libp2p and IPFS:
+///
+/// ```text
+/// /key/swarm/psk/1.0.0/
+/// /base16/
+/// <64 hex digits, e.g. generated with `openssl rand -hex 32`>
+/// ```
+///
+/// All peers of the network must use the same key. Peers without it cannot complete
+/// a handshake, their connections fail before any libp2p protocol is negotiated.
+pub fn load_swarm_key(path: &Utf8Path) -> eyre::Result<PreSharedKey> {
+    std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read swarm key {path}"))?
+        .parse()
+        .wrap_err_with(|| format!("Failed to parse swarm key {path}"))
+}
+
+/// Listen addresses of the transports enabled in `opt`, without the IP address.
+pub fn listen_addrs(opt: &Config) -> Vec<Multiaddr> {
+    let mut addrs = vec![];
+
+    if let Some(port) = opt.websocket_port {
+        addrs.push(
+            Multiaddr::empty()
+                .with(multiaddr::Protocol::Tcp(port))
+                .with(multiaddr::Protocol::Ws("/".into())),
+        );
+    }
+
+    if let Some(port) = opt.websocket_tls_port {
+        addrs.push(
+            Multiaddr::empty()
+                .with(multiaddr::Protocol::Tcp(port))
+                .with(multiaddr::Protocol::Wss("/".into())),
+        );
+    }
+
+    if let Some(port) = opt.webrtc_port {
+        addrs.push(
+            Multiaddr::empty()
+                .with(multiaddr::Protocol::Udp(port))
+                .with(multiaddr::Protocol::WebRTCDirect),
+        );
+    }
+
+    addrs
+}
+
+fn or_transport(a: BoxedTransport, b: BoxedTransport) -> BoxedTransport {
+    a.or_transport(b)
+        .map(|either, _| either.into_inner())
+        .boxed()
+}
+
+#[cfg(feature = "websocket")]
+mod websocket {
+    use std::fs::File;
+    use std::io::BufReader;
+
+    use camino::Utf8Path;
+    use eyre::{OptionExt, WrapErr};
+    use libp2p::core::muxing::StreamMuxerBox;
+    use libp2p::core::upgrade;
+    use libp2p::websocket::{tls, WsConfig};
+    use libp2p::{identity, noise, tcp, yamux, Transport};
+
+    use super::{BoxedTransport, Config};
+
+    pub fn build(keypair: &identity::Keypair, opt: &Config) -> eyre::Result<BoxedTransport> {
+        let mut transport = WsConfig::new(tcp::tokio::Transport::new(tcp::Config::default()));
+
+        match (&opt.websocket_tls_cert, &opt.websocket_tls_key) {
+            (Some(cert), Some(key)) => {
+                transport.set_tls_config(tls_config(cert, key)?);
+            }
+            // Only checked by clap for flags and environment variables.
+            _ if opt.websocket_tls_port.is_some() => eyre::bail!(
+                "A secure WebSocket port requires both a TLS certificate and private key"
+            ),
+            _ => {}
+        }
+
+        // Browsers cannot use the libp2p TLS handshake, so connections are secured with noise.
+        Ok(transport
+            .upgrade(upgrade::Version::V1Lazy)
+            .authenticate(noise::Config::new(keypair)?)
+            .multiplex(yamux::Config::default())
+            .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+            .boxed())
+    }
+
+    fn tls_config(cert: &Utf8Path, key: &Utf8Path) -> eyre::Result<tls::Config> {
+        let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert)?))
+            .map(|cert| cert.map(|cert| tls::Certificate::new(cert.to_vec())))
+            .collect::<Result<Vec<_>, _>>()
+            .wrap_err_with(|| format!("Failed to read TLS certificates {cert}"))?;
+
+        let private_key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key)?))
+            .wrap_err_with(|| format!("Failed to read TLS private key {key}"))?
+            .ok_or_eyre(format!("No private key found in {key}"))?;
+
+        Ok(tls::Config::new(
+            tls::PrivateKey::new(private_key.secret_der().to_vec()),
+            certs,
+        )?)
+    }
+}
+
+#[cfg(not(feature = "websocket"))]
+mod websocket {
+    use libp2p::identity;
+
+    use super::{BoxedTransport, Config};
+
+    pub fn build(_: &identity::Keypair, _: &Config) -> eyre::Result<BoxedTransport> {
+        eyre::bail!(
+            "WebSocket listeners require boot-node to be built with the `websocket` feature"
+        )
+    }
+}
+
+#[cfg(feature = "webrtc")]
+mod webrtc {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    use camino::Utf8Path;
+    use eyre::WrapErr;
+    use libp2p::core::muxing::StreamMuxerBox;
+    use libp2p::{identity, Transport};
+    use libp2p_webrtc::tokio::{Certificate, Transport as WebRtcTransport};
+    use tracing::{info, warn};
+
+    use super::BoxedTransport;
+
+    pub fn build(
+        keypair: &identity::Keypair,
+        data_dir: Option<&Utf8Path>,
+    ) -> eyre::Result<BoxedTransport> {
+        let certificate = certificate(data_dir)?;
+
+        Ok(WebRtcTransport::new(keypair.clone(), certificate)
+            .map(|(peer_id, connection), _| (peer_id, StreamMuxerBox::new(connection)))
+            .boxed())
+    }
+
+    /// Loads the certificate from the data directory, since its hash is part of the
+    /// WebRTC listen addresses that clients dial.
+    fn certificate(data_dir: Option<&Utf8Path>) -> eyre::Result<Certificate> {
+        let Some(data_dir) = data_dir else {
+            warn!("No data directory set, WebRTC addresses change on every restart");
+            return Ok(Certificate::generate(&mut rand::thread_rng())?);
+        };
+
+        let path = data_dir.join("webrtc-certificate.pem");
+
+        if path.try_exists()? {
+            let pem = std::fs::read_to_string(&path)?;
+            return Certificate::from_pem(&pem)
+                .wrap_err_with(|| format!("Failed to decode WebRTC certificate {path}"));
+        }
+
+        let certificate = Certificate::generate(&mut rand::thread_rng())?;
+
+        std::fs::create_dir_all(data_dir)?;
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(&path)
+            .and_then(|mut file| file.write_all(certificate.serialize_pem().as_bytes()))
+            .wrap_err_with(|| format!("Failed to write WebRTC certificate {path}"))?;
+
+        info!(%path, "Generated a new WebRTC certificate");
+
+        Ok(certificate)
+    }
+}
+
+#[cfg(not(feature = "webrtc"))]
+mod webrtc {
+    use camino::Utf8Path;
+    use libp2p::identity;
+
+    use super::BoxedTransport;
+
+    pub fn build(_: &identity::Keypair, _: Option<&Utf8Path>) -> eyre::Result<BoxedTransport> {
+        eyre::bail!("WebRTC listeners require boot-node to be built with the `webrtc` feature")
+    }
+}
@@ -0,0 +1,419 @@
+use std::time::{Duration, SystemTime};
+
+use boot_node::config::RateLimit;
+use boot_node::token::Token;
+use boot_node::{circuits, classes, compat, rendezvous, transport, Event};
+use camino::Utf8PathBuf;
+use chat_example::node::Mode;
+use libp2p::{identity, pnet, relay};
+
+mod harness;
+
+use harness::{BootNode, ChatNode, ChatOptions, RelayClient};
+
+const TOPIC: &str = "e2e";
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_chat_through_boot_node() -> eyre::Result<()> {
+    let mut boot_node = BootNode::start().await?;
+
+    let echo = ChatNode::spawn(&boot_node, Mode::Echo, TOPIC).await?;
+    boot_node
+        .wait_for("the relay reservation of the echo node", |event| {
+            matches!(
+                event,
+                Event::Relay(relay::Event::ReservationReqAccepted { src_peer_id, .. })
+                    if *src_peer_id == echo.peer_id
+            )
+        })
+        .await?;
+    boot_node
+        .wait_for("the registration of the echo node", |event| {
+            matches!(
+                event,
+                Event::Rendezvous(rendezvous::Event::PeerRegistered { peer, .. })
+                    if *peer == echo.peer_id
+            )
+        })
+        .await?;
+
+    let sender = ChatNode::spawn(&boot_node, Mode::Interactive, TOPIC).await?;
+    boot_node
+        .wait_for("the discovery of the echo node", |event| {
+            matches!(
+                event,
+                Event::Rendezvous(rendezvous::Event::DiscoverServed {
+                    enquirer,
+                    registrations,
+                }) if *enquirer == sender.peer_id
+                    && registrations
+                        .iter()
+                        .any(|registration| registration.record.peer_id() == echo.peer_id)
+            )
+        })
+        .await?;
+
+    // Publishing fails until the subscription of the echo node is known, so it is
+    // repeated until the echo arrives.
+    harness::eventually("the echo of a published message", || async {
+        let _ = sender.publish(TOPIC, "hello").await;
+        sender
+            .messages(TOPIC)
+            .await
+            .iter()
+            .any(|message| message.starts_with("echo") && message.ends_with("'hello'"))
+    })
+    .await?;
+    assert!(echo
+        .messages(TOPIC)
+        .await
+        .iter()
+        .all(|message| message == "hello"));
+
+    // Only the echo node is left to catch up from.
+    drop(sender);
+    let late = ChatNode::spawn(&boot_node, Mode::Interactive, TOPIC).await?;
+    harness::eventually("the catchup from the echo node", || async {
+        late.messages(TOPIC).await == echo.messages(TOPIC).await
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_discover_through_federated_boot_node() -> eyre::Result<()> {
+    let mut boot_nodes = BootNode::start_federation(2).await?;
+    let (first, second) = boot_nodes.split_at_mut(1);
+    let (first, second) = (&mut first[0], &mut second[0]);
+
+    let echo = ChatNode::spawn(first, Mode::Echo, TOPIC).await?;
+    // The echo node may also find the second boot node through the first one's
+    // routing table and register with it directly, before the replica arrives.
+    second
+        .wait_for("the echo node's registration", |event| {
+            matches!(
+                event,
+                Event::Rendezvous(
+                    rendezvous::Event::RegistrationReplicated { registration, .. }
+                        | rendezvous::Event::PeerRegistered { registration, .. }
+                ) if registration.record.peer_id() == echo.peer_id
+            )
+        })
+        .await?;
+
+    let sender = ChatNode::spawn(second, Mode::Interactive, TOPIC).await?;
+    second
+        .wait_for("the discovery of the echo node", |event| {
+            matches!(
+                event,
+                Event::Rendezvous(rendezvous::Event::DiscoverServed {
+                    enquirer,
+                    registrations,
+                }) if *enquirer == sender.peer_id
+                    && registrations
+                        .iter()
+                        .any(|registration| registration.record.peer_id() == echo.peer_id)
+            )
+        })
+        .await?;
+
+    harness::eventually("the echo of a published message", || async {
+        let _ = sender.publish(TOPIC, "hello").await;
+        sender
+            .messages(TOPIC)
+            .await
+            .iter()
+            .any(|message| message.starts_with("echo") && message.ends_with("'hello'"))
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_private_network() -> eyre::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let swarm_key_path = Utf8PathBuf::try_from(dir.path().join("swarm.key"))?;
+    let swarm_key = pnet::PreSharedKey::new([7; 32]);
+    std::fs::write(&swarm_key_path, swarm_key.to_string())?;
+
+    let mut boot_node = BootNode::start_with(|builder| {
+        builder.transport(transport::Config {
+            swarm_key: Some(swarm_key_path.clone()),
+            ..Default::default()
+        })
+    })
+    .await?;
+
+    let member = ChatNode::spawn_with(
+        &boot_node,
+        Mode::Echo,
+        TOPIC,
+        ChatOptions {
+            swarm_key: Some(swarm_key),
+            ..Default::default()
+        },
+    )
+    .await?;
+    boot_node
+        .wait_for("the registration of the node with the swarm key", |event| {
+            matches!(
+                event,
+                Event::Rendezvous(rendezvous::Event::PeerRegistered { peer, .. })
+                    if *peer == member.peer_id
+            )
+        })
+        .await?;
+
+    let outsider = ChatNode::spawn(&boot_node, Mode::Echo, TOPIC).await?;
+    let connected = tokio::time::timeout(
+        Duration::from_secs(5),
+        boot_node.wait_for("a connection of the node without the swarm key", |event| {
+            matches!(event, Event::ConnectionEstablished(peer) if *peer == outsider.peer_id)
+        }),
+    )
+    .await;
+    assert!(connected.is_err());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_relay_reservation_requires_token() -> eyre::Result<()> {
+    let issuer = identity::Keypair::generate_ed25519();
+    let mut boot_node =
+        BootNode::start_with(|builder| builder.relay_token_issuer(issuer.public())).await?;
+
+    let keypair = identity::Keypair::generate_ed25519();
+    let relay_token = Token {
+        peer_id: keypair.public().to_peer_id(),
+        relays: vec![boot_node.peer_id()],
+        expires_at: SystemTime::now() + Duration::from_secs(3600),
+        class: None,
+    }
+    .sign(&issuer)?;
+
+    let paying = ChatNode::spawn_with(
+        &boot_node,
+        Mode::Echo,
+        TOPIC,
+        ChatOptions {
+            keypair: Some(keypair),
+            relay_token: Some(relay_token),
+            ..Default::default()
+        },
+    )
+    .await?;
+    boot_node
+        .wait_for("the reservation of the node with a token", |event| {
+            matches!(
+                event,
+                Event::Relay(relay::Event::ReservationReqAccepted { src_peer_id, .. })
+                    if *src_peer_id == paying.peer_id
+            )
+        })
+        .await?;
+
+    let freeloader = ChatNode::spawn(&boot_node, Mode::Echo, TOPIC).await?;
+    boot_node
+        .wait_for(
+            "the denied reservation of the node without a token",
+            |event| {
+                matches!(
+                    event,
+                    Event::Relay(relay::Event::ReservationReqDenied { src_peer_id })
+                        if *src_peer_id == freeloader.peer_id
+                )
+            },
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_relay_class_granted_by_token() -> eyre::Result<()> {
+    const SENT: usize = 1 << 18;
+
+    let anonymous = classes::Limits {
+        max_reservations: 16,
+        max_circuits_per_peer: 4,
+        max_circuit_duration: Duration::from_secs(120),
+        max_circuit_bytes: 1 << 16,
+        reservation_rate_per_peer: RateLimit::Unlimited,
+        reservation_rate_per_ip: RateLimit::Unlimited,
+        circuit_rate_per_peer: RateLimit::Unlimited,
+        circuit_rate_per_ip: RateLimit::Unlimited,
+    };
+    let relay_classes = classes::Config {
+        anonymous: anonymous.clone(),
+        classes: vec![classes::Class {
+            name: "bulk".to_owned(),
+            peers: Default::default(),
+            limits: classes::Limits {
+                max_circuit_bytes: 1 << 20,
+                ..anonymous
+            },
+        }],
+    };
+    let issuer = identity::Keypair::generate_ed25519();
+    let mut boot_node = BootNode::start_with(|builder| {
+        builder
+            .relay_token_issuer(issuer.public())
+            .relay_classes(relay_classes.clone())
+    })
+    .await?;
+    let token = |keypair: &identity::Keypair, class: Option<&str>| {
+        Token {
+            peer_id: keypair.public().to_peer_id(),
+            relays: vec![boot_node.peer_id()],
+            expires_at: SystemTime::now() + Duration::from_secs(3600),
+            class: class.map(str::to_owned),
+        }
+        .sign(&issuer)
+    };
+
+    let keypair = identity::Keypair::generate_ed25519();
+    let destination =
+        RelayClient::spawn(&boot_node, keypair.clone(), Some(token(&keypair, None)?)).await?;
+    destination.reserve().await?;
+
+    // The class is granted on a connection established before the token was presented.
+    let keypair = identity::Keypair::generate_ed25519();
+    let mut bulk = RelayClient::spawn(
+        &boot_node,
+        keypair.clone(),
+        Some(token(&keypair, Some("bulk"))?),
+    )
+    .await?;
+    bulk.connect_relayed(destination.peer_id).await?;
+    bulk.send(destination.peer_id, SENT).await?;
+    harness::eventually("the data relayed with the limits of the class", || async {
+        destination.received_from(&bulk.peer_id) == SENT
+    })
+    .await?;
+
+    let mut anonymous =
+        RelayClient::spawn(&boot_node, identity::Keypair::generate_ed25519(), None).await?;
+    anonymous.connect_relayed(destination.peer_id).await?;
+    anonymous.send(destination.peer_id, SENT).await?;
+    boot_node
+        .wait_for("the anonymous circuit reaching its byte limit", |event| {
+            matches!(
+                event,
+                Event::Relay(relay::Event::CircuitClosed { src_peer_id, error: Some(_), .. })
+                    if *src_peer_id == anonymous.peer_id
+            )
+        })
+        .await?;
+    assert!(destination.received_from(&anonymous.peer_id) < SENT);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_circuit_outside_namespace_refused() -> eyre::Result<()> {
+    const SENT: usize = 1 << 10;
+
+    let mut boot_node = BootNode::start_with(|builder| {
+        builder.circuit_policy(circuits::Policy::SharedNamespace { links: vec![] })
+    })
+    .await?;
+
+    let mut clients = vec![];
+    for namespace in ["/calimero/test/a", "/calimero/test/a", "/calimero/test/b"] {
+        let client =
+            RelayClient::spawn(&boot_node, identity::Keypair::generate_ed25519(), None).await?;
+        client.reserve().await?;
+        client.register(namespace).await?;
+        clients.push(client);
+    }
+    let [mut source, member, outsider] = <[RelayClient; 3]>::try_from(clients)
+        .map_err(|_| eyre::eyre!("Expected three relay clients"))?;
+
+    source.connect_relayed(member.peer_id).await?;
+    source.dial_relayed(outsider.peer_id)?;
+    boot_node
+        .wait_for(
+            "the refused circuit to the peer outside the namespace",
+            |event| {
+                matches!(
+                    event,
+                    Event::Relay(relay::Event::CircuitReqDenied { src_peer_id, dst_peer_id })
+                        if *src_peer_id == source.peer_id && *dst_peer_id == outsider.peer_id
+                )
+            },
+        )
+        .await?;
+    assert!(!outsider.is_connected(&source.peer_id));
+
+    // The circuit of the source within its namespace is left open.
+    source.send(member.peer_id, SENT).await?;
+    harness::eventually("the data sent over the remaining circuit", || async {
+        member.received_from(&source.peer_id) == SENT
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_incompatible_agent_not_served() -> eyre::Result<()> {
+    let mut boot_node = BootNode::start_with(|builder| {
+        builder.version_policy(compat::Policy::new(vec!["relay-client=^1"
+            .parse()
+            .unwrap()]))
+    })
+    .await?;
+
+    // Served once identified, the reservation being held until then.
+    let compatible =
+        RelayClient::spawn(&boot_node, identity::Keypair::generate_ed25519(), None).await?;
+    compatible.reserve().await?;
+
+    let unidentified = RelayClient::spawn_with_agent(
+        &boot_node,
+        identity::Keypair::generate_ed25519(),
+        None,
+        None,
+    )
+    .await?;
+    unidentified.request_reservation()?;
+    boot_node
+        .wait_for(
+            "the denied reservation of the peer never identified",
+            |event| {
+                matches!(
+                    event,
+                    Event::Relay(relay::Event::ReservationReqDenied { src_peer_id })
+                        if *src_peer_id == unidentified.peer_id
+                )
+            },
+        )
+        .await?;
+
+    let incompatible = RelayClient::spawn_with_agent(
+        &boot_node,
+        identity::Keypair::generate_ed25519(),
+        None,
+        Some("relay-client/0.9.0"),
+    )
+    .await?;
+    incompatible.request_reservation()?;
+
+    let mut served = false;
+    boot_node
+        .wait_for("the disconnection of the incompatible peer", |event| {
+            served |= matches!(
+                event,
+                Event::Relay(relay::Event::ReservationReqAccepted { src_peer_id, .. })
+                    if *src_peer_id == incompatible.peer_id
+            );
+            matches!(event, Event::PeerDisconnected(peer) if *peer == incompatible.peer_id)
+        })
+        .await?;
+    assert!(!served);
+
+    Ok(())
+}
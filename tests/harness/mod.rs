@@ -0,0 +1,550 @@
+//! Runs a boot node and chat example nodes in one process, connected over loopback.
+
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use boot_node::{token, Event, RelayServer, RelayServerBuilder};
+use chat_example::network::client::NetworkClient;
+use chat_example::node::{Mode, Node};
+use chat_example::{network, store, types};
+use futures_util::{AsyncReadExt, AsyncWriteExt, StreamExt};
+use libp2p::swarm::behaviour::toggle::Toggle;
+use libp2p::swarm::{NetworkBehaviour, SwarmEvent};
+use libp2p::{
+    gossipsub, identify, identity, noise, pnet, relay, rendezvous, request_response, tcp, yamux,
+    Multiaddr, PeerId, StreamProtocol,
+};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// How long to wait for any single step of a flow.
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+const RENDEZVOUS_NAMESPACE: &str = "/calimero/test/chat";
+
+pub struct BootNode {
+    server: RelayServer,
+    /// Dialable address including the peer id.
+    pub address: Multiaddr,
+    /// Events received so far, kept since events are not cloneable and several
+    /// nodes may wait on the same kind of event.
+    events: Vec<Event>,
+}
+
+impl BootNode {
+    /// Starts a boot node listening on a free loopback port, announcing it as its
+    /// external address so that relay reservations carry a dialable address.
+    pub async fn start() -> eyre::Result<Self> {
+        Self::start_with(|builder| builder).await
+    }
+
+    /// Starts a boot node with additional configuration.
+    pub async fn start_with(
+        configure: impl Fn(RelayServerBuilder) -> RelayServerBuilder,
+    ) -> eyre::Result<Self> {
+        Ok(Self::launch(1, configure).await?.remove(0))
+    }
+
+    /// Starts `size` boot nodes replicating their rendezvous registrations with each
+    /// other.
+    pub async fn start_federation(size: usize) -> eyre::Result<Vec<Self>> {
+        Self::launch(size, |builder| builder).await
+    }
+
+    /// Starts the boot nodes on ports picked by the OS, and only adds the external
+    /// and federation addresses once their listen addresses are known.
+    async fn launch(
+        size: usize,
+        configure: impl Fn(RelayServerBuilder) -> RelayServerBuilder,
+    ) -> eyre::Result<Vec<Self>> {
+        let mut nodes = vec![];
+        for _ in 0..size {
+            let builder = configure(RelayServer::builder(identity::Keypair::generate_ed25519()))
+                .listen_on("/ip4/127.0.0.1/tcp/0".parse()?);
+
+            let mut node = Self {
+                server: builder.start()?,
+                address: Multiaddr::empty(),
+                events: vec![],
+            };
+
+            let mut address = None;
+            node.wait_for("listen address", |event| match event {
+                Event::NewListenAddr(addr) => {
+                    address = Some(addr.clone());
+                    true
+                }
+                _ => false,
+            })
+            .await?;
+            let address = address.expect("listen address to be set when matched");
+
+            node.server.add_external_address(address.clone());
+            node.address = address.with(multiaddr::Protocol::P2p(node.peer_id()));
+            nodes.push(node);
+        }
+
+        for node in &nodes {
+            for other in &nodes {
+                if other.address != node.address {
+                    node.server.add_federation_peer(other.address.clone())?;
+                }
+            }
+        }
+
+        Ok(nodes)
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        self.server.peer_id()
+    }
+
+    /// Waits for an event matching `predicate`, including events received before.
+    pub async fn wait_for(
+        &mut self,
+        what: &str,
+        mut predicate: impl FnMut(&Event) -> bool,
+    ) -> eyre::Result<()> {
+        if self.events.iter().any(&mut predicate) {
+            return Ok(());
+        }
+
+        let wait = async {
+            while let Some(event) = self.server.next().await {
+                let matched = predicate(&event);
+                self.events.push(event);
+                if matched {
+                    return Ok(());
+                }
+            }
+
+            eyre::bail!("Boot node stopped while waiting for {what}")
+        };
+
+        tokio::time::timeout(TIMEOUT, wait)
+            .await
+            .map_err(|_| eyre::eyre!("Timed out waiting for {what}"))?
+    }
+}
+
+/// Options of a [`ChatNode`] beyond the ones of [`ChatNode::spawn`].
+#[derive(Default)]
+pub struct ChatOptions {
+    /// Identity of the node, generated if unset.
+    pub keypair: Option<identity::Keypair>,
+    /// Key of the private network to join.
+    pub swarm_key: Option<pnet::PreSharedKey>,
+    /// Token presented to the boot node before reserving.
+    pub relay_token: Option<String>,
+}
+
+/// Chat example node, stopped when dropped.
+pub struct ChatNode {
+    pub peer_id: PeerId,
+    client: NetworkClient,
+    store: store::Store,
+    task: JoinHandle<eyre::Result<()>>,
+}
+
+impl ChatNode {
+    /// Starts a node bootstrapping from `boot_node` and subscribed to `topic`, with a
+    /// pending catchup of the topic from the first subscribed peer.
+    pub async fn spawn(boot_node: &BootNode, mode: Mode, topic: &str) -> eyre::Result<Self> {
+        Self::spawn_with(boot_node, mode, topic, Default::default()).await
+    }
+
+    /// Starts a node like [`Self::spawn`], with additional options.
+    pub async fn spawn_with(
+        boot_node: &BootNode,
+        mode: Mode,
+        topic: &str,
+        options: ChatOptions,
+    ) -> eyre::Result<Self> {
+        let keypair = options
+            .keypair
+            .unwrap_or_else(identity::Keypair::generate_ed25519);
+        let peer_id = keypair.public().to_peer_id();
+
+        let (client, mut events) = network::run(
+            keypair,
+            0,
+            vec![boot_node.address.clone()],
+            rendezvous::Namespace::from_static(RENDEZVOUS_NAMESPACE),
+            vec![],
+            options.swarm_key,
+            options.relay_token,
+        )
+        .await?;
+
+        let store = store::Store::default();
+        let mut node = Node::new(mode, store.clone(), client.clone());
+        node.boot(None, Some(vec![topic.to_owned()])).await?;
+
+        let task = tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                node.handle_network_event(event, peer_id).await?;
+            }
+
+            Ok(())
+        });
+
+        Ok(Self {
+            peer_id,
+            client,
+            store,
+            task,
+        })
+    }
+
+    pub async fn publish(&self, topic: &str, text: &str) -> eyre::Result<()> {
+        self.client
+            .publish(
+                gossipsub::IdentTopic::new(topic).hash(),
+                text.as_bytes().to_vec(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Texts of the messages stored for `topic`, received or caught up.
+    pub async fn messages(&self, topic: &str) -> Vec<String> {
+        let application_id = types::ApplicationId::from(topic.to_owned());
+        let mut batches = self.store.batch_stream(application_id, usize::MAX);
+
+        let mut messages = vec![];
+        while let Some(batch) = batches.next().await {
+            messages.extend(
+                batch
+                    .into_iter()
+                    .map(|message| String::from_utf8_lossy(&message.data).into_owned()),
+            );
+        }
+
+        messages
+    }
+}
+
+impl Drop for ChatNode {
+    fn drop(&mut self) {
+        // The network event loop stops once the node and its client are dropped.
+        self.task.abort();
+    }
+}
+
+/// Protocol of the streams [`RelayClient`]s send data over.
+const DATA_PROTOCOL: StreamProtocol = StreamProtocol::new("/calimero/test/data");
+
+/// Agent version [`RelayClient`]s identify with by default.
+pub const RELAY_CLIENT_AGENT: &str = "relay-client/1.0.0";
+
+#[derive(NetworkBehaviour)]
+struct RelayClientBehaviour {
+    identify: Toggle<identify::Behaviour>,
+    relay: relay::client::Behaviour,
+    relay_token: request_response::json::Behaviour<token::Request, token::Response>,
+    rendezvous: rendezvous::client::Behaviour,
+    stream: libp2p_stream::Behaviour,
+}
+
+enum Command {
+    Dial(Multiaddr),
+    Listen(Multiaddr),
+    Register(rendezvous::Namespace),
+}
+
+/// What a [`RelayClient`] went through so far.
+#[derive(Default)]
+struct RelayClientStatus {
+    /// Whether a connection to the boot node was established, even if closed since.
+    reached: bool,
+    token_accepted: bool,
+    reserved: bool,
+    registered: HashSet<rendezvous::Namespace>,
+    connected: HashSet<PeerId>,
+    /// Bytes received from each peer over data streams.
+    received: HashMap<PeerId, usize>,
+}
+
+/// Bare relay client, reserving, registering and opening circuits through the boot node
+/// to send raw data over them, stopped when dropped.
+pub struct RelayClient {
+    pub peer_id: PeerId,
+    boot_node: Multiaddr,
+    control: libp2p_stream::Control,
+    commands: mpsc::UnboundedSender<Command>,
+    status: Arc<Mutex<RelayClientStatus>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl RelayClient {
+    /// Starts a client connected to `boot_node`, presenting `relay_token` to it if given.
+    pub async fn spawn(
+        boot_node: &BootNode,
+        keypair: identity::Keypair,
+        relay_token: Option<String>,
+    ) -> eyre::Result<Self> {
+        Self::spawn_with_agent(boot_node, keypair, relay_token, Some(RELAY_CLIENT_AGENT)).await
+    }
+
+    /// Starts a client identifying with the agent version `agent`, or not identifying
+    /// itself at all without one.
+    pub async fn spawn_with_agent(
+        boot_node: &BootNode,
+        keypair: identity::Keypair,
+        relay_token: Option<String>,
+        agent: Option<&str>,
+    ) -> eyre::Result<Self> {
+        let peer_id = keypair.public().to_peer_id();
+        let mut swarm = libp2p::SwarmBuilder::with_existing_identity(keypair)
+            .with_tokio()
+            .with_tcp(
+                tcp::Config::default(),
+                noise::Config::new,
+                yamux::Config::default,
+            )?
+            .with_relay_client(noise::Config::new, yamux::Config::default)?
+            .with_behaviour(|keypair, relay| RelayClientBehaviour {
+                identify: agent
+                    .map(|agent| {
+                        identify::Behaviour::new(
+                            identify::Config::new(
+                                "/calimero/test/1.0.0".to_owned(),
+                                keypair.public(),
+                            )
+                            .with_agent_version(agent.to_owned()),
+                        )
+                    })
+                    .into(),
+                relay,
+                relay_token: request_response::json::Behaviour::new(
+                    [(
+                        token::PROTOCOL_NAME,
+                        request_response::ProtocolSupport::Outbound,
+                    )],
+                    Default::default(),
+                ),
+                rendezvous: rendezvous::client::Behaviour::new(keypair.clone()),
+                stream: libp2p_stream::Behaviour::new(),
+            })?
+            .with_swarm_config(|config| {
+                config.with_idle_connection_timeout(Duration::from_secs(60))
+            })
+            .build();
+
+        let status = Arc::<Mutex<RelayClientStatus>>::default();
+        let mut control = swarm.behaviour().stream.new_control();
+        let mut incoming = control
+            .accept(DATA_PROTOCOL)
+            .map_err(|_| eyre::eyre!("Data protocol already registered"))?;
+
+        let receiver = tokio::spawn({
+            let status = status.clone();
+            async move {
+                while let Some((peer, mut stream)) = incoming.next().await {
+                    let status = status.clone();
+                    tokio::spawn(async move {
+                        let mut buffer = [0; 4096];
+                        while let Ok(read @ 1..) = stream.read(&mut buffer).await {
+                            *status.lock().unwrap().received.entry(peer).or_default() += read;
+                        }
+                        let _ = stream.close().await;
+                    });
+                }
+            }
+        });
+
+        let boot_node_id = boot_node.peer_id();
+        let with_token = relay_token.is_some();
+        swarm.dial(boot_node.address.clone())?;
+
+        let (commands, mut command_receiver) = mpsc::unbounded_channel();
+        let driver = tokio::spawn({
+            let status = status.clone();
+            async move {
+                loop {
+                    tokio::select! {
+                        Some(command) = command_receiver.recv() => {
+                            let result = match command {
+                                Command::Dial(addr) => swarm.dial(addr).map_err(eyre::Report::from),
+                                Command::Listen(addr) => swarm.listen_on(addr).map(drop).map_err(eyre::Report::from),
+                                Command::Register(namespace) => swarm
+                                    .behaviour_mut()
+                                    .rendezvous
+                                    .register(namespace, boot_node_id, None)
+                                    .map_err(eyre::Report::from),
+                            };
+                            if let Err(err) = result {
+                                tracing::warn!(?err, "Relay client command failed");
+                            }
+                        }
+                        event = swarm.select_next_some() => {
+                            let mut status = status.lock().unwrap();
+                            match event {
+                                SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                                    if peer_id == boot_node_id {
+                                        status.reached = true;
+                                        if let Some(token) = relay_token.clone() {
+                                            swarm
+                                                .behaviour_mut()
+                                                .relay_token
+                                                .send_request(&peer_id, token::Request { token });
+                                        }
+                                    }
+                                    status.connected.insert(peer_id);
+                                }
+                                SwarmEvent::ConnectionClosed {
+                                    peer_id,
+                                    num_established: 0,
+                                    ..
+                                } => {
+                                    status.connected.remove(&peer_id);
+                                }
+                                // Only relayed addresses are listened on, once reserved.
+                                SwarmEvent::NewListenAddr { address, .. } => {
+                                    swarm.add_external_address(address);
+                                    status.reserved = true;
+                                }
+                                SwarmEvent::Behaviour(RelayClientBehaviourEvent::RelayToken(
+                                    request_response::Event::Message {
+                                        message: request_response::Message::Response {
+                                            response: token::Response::Accepted { .. },
+                                            ..
+                                        },
+                                        ..
+                                    },
+                                )) => status.token_accepted = true,
+                                SwarmEvent::Behaviour(RelayClientBehaviourEvent::Rendezvous(
+                                    rendezvous::client::Event::Registered { namespace, .. },
+                                )) => {
+                                    status.registered.insert(namespace);
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+            }
+        });
+
+        let client = Self {
+            peer_id,
+            boot_node: boot_node.address.clone(),
+            control,
+            commands,
+            status,
+            tasks: vec![receiver, driver],
+        };
+
+        eventually("the connection to the boot node", || async {
+            let status = client.status.lock().unwrap();
+            status.reached && (status.token_accepted || !with_token)
+        })
+        .await?;
+
+        Ok(client)
+    }
+
+    /// Makes a reservation with the boot node's relay, to be reachable through it.
+    pub async fn reserve(&self) -> eyre::Result<()> {
+        self.request_reservation()?;
+
+        eventually("the relay reservation", || async {
+            self.status.lock().unwrap().reserved
+        })
+        .await
+    }
+
+    /// Requests a reservation from the boot node's relay, without waiting for it.
+    pub fn request_reservation(&self) -> eyre::Result<()> {
+        self.commands.send(Command::Listen(
+            self.boot_node.clone().with(multiaddr::Protocol::P2pCircuit),
+        ))?;
+
+        Ok(())
+    }
+
+    /// Registers with the boot node's rendezvous server under `namespace`, once
+    /// reachable through a reservation.
+    pub async fn register(&self, namespace: &'static str) -> eyre::Result<()> {
+        let namespace = rendezvous::Namespace::from_static(namespace);
+        self.commands.send(Command::Register(namespace.clone()))?;
+
+        eventually("the registration", || async {
+            self.status.lock().unwrap().registered.contains(&namespace)
+        })
+        .await
+    }
+
+    /// Requests a circuit to `peer` from the boot node's relay, without waiting for it.
+    pub fn dial_relayed(&self, peer: PeerId) -> eyre::Result<()> {
+        self.commands.send(Command::Dial(
+            self.boot_node
+                .clone()
+                .with(multiaddr::Protocol::P2pCircuit)
+                .with(multiaddr::Protocol::P2p(peer)),
+        ))?;
+
+        Ok(())
+    }
+
+    /// Connects to `peer` through a circuit of the boot node's relay.
+    pub async fn connect_relayed(&self, peer: PeerId) -> eyre::Result<()> {
+        self.dial_relayed(peer)?;
+
+        eventually("the circuit", || async { self.is_connected(&peer) }).await
+    }
+
+    pub fn is_connected(&self, peer: &PeerId) -> bool {
+        self.status.lock().unwrap().connected.contains(peer)
+    }
+
+    /// Sends `len` bytes to the connected `peer` over a new stream, returning once
+    /// written, whether or not they arrive.
+    pub async fn send(&mut self, peer: PeerId, len: usize) -> eyre::Result<()> {
+        let mut stream = self.control.open_stream(peer, DATA_PROTOCOL).await?;
+        let data = vec![0; len];
+        let _ = stream.write_all(&data).await;
+        let _ = stream.close().await;
+
+        Ok(())
+    }
+
+    /// Bytes received from `peer` so far.
+    pub fn received_from(&self, peer: &PeerId) -> usize {
+        self.status
+            .lock()
+            .unwrap()
+            .received
+            .get(peer)
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+impl Drop for RelayClient {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Polls `condition` until it holds.
+pub async fn eventually<F, Fut>(what: &str, mut condition: F) -> eyre::Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let poll = async {
+        while !condition().await {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    };
+
+    tokio::time::timeout(TIMEOUT, poll)
+        .await
+        .map_err(|_| eyre::eyre!("Timed out waiting for {what}"))
+}